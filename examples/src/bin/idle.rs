@@ -77,9 +77,8 @@ async fn fetch_and_idle(imap_server: &str, login: &str, password: &str) -> Resul
         Timeout => {
             println!("-- IDLE timed out");
         }
-        NewData(data) => {
-            let s = String::from_utf8(data.borrow_owner().to_vec()).unwrap();
-            println!("-- IDLE data:\n{}", s);
+        NewData(events) => {
+            println!("-- IDLE events: {:?}", events);
         }
     }
 
@@ -1,3 +1,5 @@
+use std::future::Future;
+
 /// This trait allows for pluggable authentication schemes. It is used by [`Client::authenticate`] to
 /// [authenticate using SASL](https://tools.ietf.org/html/rfc3501#section-6.2.2).
 ///
@@ -10,3 +12,45 @@ pub trait Authenticator {
     /// The returned byte-string is base64-encoded and then sent back to the server.
     fn process(&mut self, challenge: &[u8]) -> Self::Response;
 }
+
+/// An asynchronous and fallible variant of [`Authenticator`]. It is used by
+/// [`Client::authenticate_async`] for mechanisms that need to wait for something before they can
+/// answer a challenge, such as refreshing an OAuth2 token or signing with a hardware token.
+///
+/// If `process` returns an error, the client cancels the exchange by sending a line consisting of
+/// a single `*`, as described in [RFC 3501 section
+/// 6.2.2](https://tools.ietf.org/html/rfc3501#section-6.2.2), and returns the error as
+/// [`Error::AuthenticationAborted`].
+///
+/// [`Client::authenticate_async`]: crate::Client::authenticate_async
+/// [`Error::AuthenticationAborted`]: crate::error::Error::AuthenticationAborted
+pub trait AsyncAuthenticator {
+    /// The type of the response to the challenge. This will usually be a `Vec<u8>` or `String`.
+    type Response: AsRef<[u8]>;
+
+    /// The error returned when a challenge can not be answered.
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Each base64-decoded server challenge is passed to `process`.
+    /// The returned byte-string is base64-encoded and then sent back to the server.
+    fn process(
+        &mut self,
+        challenge: &[u8],
+    ) -> impl Future<Output = Result<Self::Response, Self::Error>> + Send;
+}
+
+/// Adapts a synchronous [`Authenticator`] to the [`AsyncAuthenticator`] interface.
+pub(crate) struct SyncAuthenticator<A>(pub(crate) A);
+
+impl<A: Authenticator> AsyncAuthenticator for SyncAuthenticator<A> {
+    type Response = Vec<u8>;
+    type Error = std::convert::Infallible;
+
+    fn process(
+        &mut self,
+        challenge: &[u8],
+    ) -> impl Future<Output = Result<Self::Response, Self::Error>> + Send {
+        let response = self.0.process(challenge).as_ref().to_vec();
+        futures::future::ready(Ok(response))
+    }
+}
@@ -0,0 +1,247 @@
+use std::fmt;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+#[cfg(feature = "runtime-async-std")]
+use async_std::io::{Read, Write};
+use futures::io;
+use imap_proto::{AttributeValue, RequestId, Response};
+#[cfg(feature = "runtime-tokio")]
+use tokio::io::{AsyncRead as Read, AsyncWrite as Write};
+
+use crate::client::Session;
+use crate::error::{Error, ParseError, Result};
+use crate::parse::handle_unilateral;
+use crate::types::{Fetch, Uid};
+
+/// A body section of a message that is read directly from the connection, returned by
+/// [`Session::uid_fetch_body`].
+///
+/// Only the part of the body that was not read yet is kept in memory, not the whole body, so it
+/// can be copied to a file or another writer regardless of its size. It has to be read before
+/// other commands can be sent, [`BodyReader::finish`] then returns the rest of the `FETCH`
+/// response. If the reader is dropped early, the rest of the body is skipped by the next command.
+///
+/// ```ignore
+/// if let Some(mut body) = session.uid_fetch_body(uid, "").await? {
+///     let mut file = File::create("message.eml").await?;
+///     io::copy(&mut body, &mut file).await?;
+///     body.finish().await?;
+/// }
+/// ```
+#[derive(Debug)]
+pub struct BodyReader<'a, T: Read + Write + Unpin + fmt::Debug + Send> {
+    session: &'a mut Session<T>,
+    id: RequestId,
+    len: u64,
+    /// The body if the server did not send it as a literal, e.g. because it is empty, and the
+    /// number of bytes of it that were read.
+    buffered: Option<(Vec<u8>, usize)>,
+    /// The `FETCH` response, if it was read already.
+    fetch: Option<Fetch>,
+}
+
+impl<'a, T: Read + Write + Unpin + fmt::Debug + Send> BodyReader<'a, T> {
+    /// Reads the responses to the `UID FETCH` command with tag `id` until the body of the message
+    /// with `uid` starts. Returns `None` if the command completes without it.
+    pub(crate) async fn new(
+        session: &'a mut Session<T>,
+        id: RequestId,
+        uid: Uid,
+    ) -> Result<Option<Self>> {
+        loop {
+            let streamed = futures::future::poll_fn(|cx| session.conn.poll_literal_start(cx));
+            if let Some(len) = streamed.await? {
+                return Ok(Some(BodyReader {
+                    session,
+                    id,
+                    len,
+                    buffered: None,
+                    fetch: None,
+                }));
+            }
+
+            let response = session.conn.next_response().await?;
+            match response.parsed() {
+                Response::Fetch(_, attributes)
+                    if attributes.contains(&AttributeValue::Uid(uid)) =>
+                {
+                    let body = attributes
+                        .iter()
+                        .find_map(|attribute| match attribute {
+                            AttributeValue::BodySection { data, .. } => {
+                                Some(data.as_deref().unwrap_or_default().to_vec())
+                            }
+                            _ => None,
+                        })
+                        .unwrap_or_default();
+                    return Ok(Some(BodyReader {
+                        session,
+                        id,
+                        len: body.len() as u64,
+                        buffered: Some((body, 0)),
+                        fetch: Some(Fetch::new(response)),
+                    }));
+                }
+                Response::Done {
+                    tag,
+                    status,
+                    code,
+                    information,
+                } if *tag == id => {
+                    session
+                        .conn
+                        .check_status_ok(status, code.as_ref(), information.as_deref())?;
+                    return Ok(None);
+                }
+                _ => handle_unilateral(response, session.unsolicited_responses_tx.clone()).await?,
+            }
+        }
+    }
+
+    /// The length of the body in bytes, as announced by the server.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the body is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Skips the part of the body that was not read, and reads the rest of the response.
+    ///
+    /// Returns the `FETCH` response with the other data items that were fetched, such as
+    /// [`Fetch::uid`]. The body itself is not part of it.
+    pub async fn finish(mut self) -> Result<Fetch> {
+        let mut fetch = self.fetch.take();
+        loop {
+            let response = self.session.conn.next_response().await?;
+            match response.parsed() {
+                // The rest of the response is read right after the body.
+                Response::Fetch(..) if fetch.is_none() => fetch = Some(Fetch::new(response)),
+                Response::Done {
+                    tag,
+                    status,
+                    code,
+                    information,
+                } if *tag == self.id => {
+                    self.session.conn.check_status_ok(
+                        status,
+                        code.as_ref(),
+                        information.as_deref(),
+                    )?;
+                    return fetch.ok_or_else(|| {
+                        Error::Parse(ParseError::ExpectedResponseNotFound("FETCH".into()))
+                    });
+                }
+                _ => {
+                    let unsolicited = self.session.unsolicited_responses_tx.clone();
+                    handle_unilateral(response, unsolicited).await?;
+                }
+            }
+        }
+    }
+
+    fn poll_read_body(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        match &mut self.buffered {
+            Some((body, read)) => {
+                let num_bytes = buf.len().min(body.len() - *read);
+                buf[..num_bytes].copy_from_slice(&body[*read..*read + num_bytes]);
+                *read += num_bytes;
+                Poll::Ready(Ok(num_bytes))
+            }
+            None => self.session.conn.poll_read_literal(cx, buf),
+        }
+    }
+}
+
+#[cfg(feature = "runtime-async-std")]
+impl<T: Read + Write + Unpin + fmt::Debug + Send> Read for BodyReader<'_, T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_read_body(cx, buf)
+    }
+}
+
+#[cfg(feature = "runtime-tokio")]
+impl<T: Read + Write + Unpin + fmt::Debug + Send> Read for BodyReader<'_, T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let num_bytes =
+            futures::ready!(self.get_mut().poll_read_body(cx, buf.initialize_unfilled()))?;
+        buf.advance(num_bytes);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::mock_session;
+    use crate::mock_stream::MockStream;
+    use crate::types::{Flag, UnsolicitedResponse};
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn uid_fetch_body() {
+        #[cfg(feature = "runtime-async-std")]
+        use async_std::io::ReadExt;
+        #[cfg(feature = "runtime-tokio")]
+        use tokio::io::AsyncReadExt;
+
+        // Larger than the buffer, which only holds the start of it.
+        let body = "Subject: large\r\n\r\n".to_string() + &"0123456789".repeat(2000);
+        let mut response = format!(
+            "* 3 EXISTS\r\n* 2 FETCH (UID 7 BODY[] {{{}}}\r\n{} FLAGS (\\Seen))\r\n",
+            body.len(),
+            body
+        );
+        response += "A0001 OK Fetch completed\r\n";
+        // Dropped after reading a part of it.
+        response += &format!(
+            "* 2 FETCH (BODY[1]<0> {{{}}}\r\n{} UID 7)\r\nA0002 OK Fetch completed\r\n",
+            body.len(),
+            body
+        );
+        response += "A0003 OK Fetch completed\r\nA0004 OK Noop completed\r\n";
+        let mock_stream = MockStream::new(response.into_bytes());
+        let mut session = mock_session!(mock_stream);
+
+        let mut reader = session.uid_fetch_body(7, "").await.unwrap().unwrap();
+        assert_eq!(reader.len(), body.len() as u64);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, body.as_bytes());
+        let fetch = reader.finish().await.unwrap();
+        assert_eq!(fetch.uid, Some(7));
+        assert_eq!(fetch.flags().collect::<Vec<_>>(), vec![Flag::Seen]);
+        assert_eq!(fetch.body(), None);
+        assert_eq!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Exists(3)
+        );
+
+        let mut reader = session.uid_fetch_body(7, "1").await.unwrap().unwrap();
+        let mut start = [0; 10];
+        reader.read_exact(&mut start).await.unwrap();
+        assert_eq!(&start, b"Subject: l");
+        drop(reader);
+
+        assert!(session.uid_fetch_body(8, "").await.unwrap().is_none());
+        session.noop().await.unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 UID FETCH 7 (UID BODY.PEEK[])\r\n\
+            A0002 UID FETCH 7 (UID BODY.PEEK[1])\r\n\
+            A0003 UID FETCH 8 (UID BODY.PEEK[])\r\n\
+            A0004 NOOP\r\n"
+                .to_vec()
+        );
+    }
+}
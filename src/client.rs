@@ -1,8 +1,10 @@
 use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::ops::{Deref, DerefMut};
+use std::ops::{Deref, DerefMut, RangeInclusive};
 use std::pin::Pin;
 use std::str;
+use std::task::{Context, Poll};
+use std::time::Duration;
 
 use async_channel::{self as channel, bounded};
 #[cfg(feature = "runtime-async-std")]
@@ -10,17 +12,21 @@ use async_std::io::{Read, Write, WriteExt};
 use base64::Engine as _;
 use extensions::id::{format_identification, parse_id};
 use extensions::quota::parse_get_quota_root;
-use futures::{io, Stream, StreamExt};
-use imap_proto::{Metadata, RequestId, Response};
+use futures::{io, FutureExt, Stream, StreamExt, TryStreamExt};
+use imap_proto::{AttributeValue, MailboxDatum, Metadata, RequestId, Response};
 #[cfg(feature = "runtime-tokio")]
 use tokio::io::{AsyncRead as Read, AsyncWrite as Write, AsyncWriteExt};
 
-use super::authenticator::Authenticator;
+use super::authenticator::{AsyncAuthenticator, Authenticator, SyncAuthenticator};
 use super::error::{Error, ParseError, Result, ValidateError};
 use super::parse::*;
 use super::types::*;
+use crate::body::BodyReader;
 use crate::extensions::{self, quota::parse_get_quota};
 use crate::imap_stream::ImapStream;
+use crate::pipeline::{parse_set, Pipeline};
+use crate::runtime::Timer;
+use crate::unsolicited::{OverflowPolicy, UnsolicitedHandler, UnsolicitedSender};
 
 macro_rules! quote {
     ($x:expr) => {
@@ -40,10 +46,14 @@ macro_rules! quote {
 #[derive(Debug)]
 pub struct Session<T: Read + Write + Unpin + fmt::Debug> {
     pub(crate) conn: Connection<T>,
-    pub(crate) unsolicited_responses_tx: channel::Sender<UnsolicitedResponse>,
+    pub(crate) unsolicited_responses_tx: UnsolicitedSender,
 
     /// Server responses that are not related to the current command. See also the note on
     /// [unilateral server responses in RFC 3501](https://tools.ietf.org/html/rfc3501#section-7).
+    ///
+    /// What happens when the channel is full is configured with
+    /// [`Session::configure_unsolicited_responses`]. Alternatively, responses can be handled as
+    /// they are read with [`Session::set_unsolicited_handler`].
     pub unsolicited_responses: channel::Receiver<UnsolicitedResponse>,
 }
 
@@ -77,6 +87,94 @@ pub struct Connection<T: Read + Write + Unpin + fmt::Debug> {
 
     /// Manages the request ids.
     pub(crate) request_ids: IdGenerator,
+
+    /// Capabilities announced by the server, if known.
+    pub(crate) capabilities: Option<Capabilities>,
+
+    /// Set once the server sent an untagged `BYE`, returned when the connection ends.
+    pub(crate) bye: Option<Error>,
+
+    /// The tag of the last command sent.
+    last_command: Option<RequestId>,
+
+    /// The response code of the tagged `OK` of the last command.
+    pub(crate) last_ok_code: Option<ResponseCode>,
+
+    /// Whether to track the selected mailbox, see [`Session::set_track_selected_mailbox`].
+    pub(crate) track_selected: bool,
+
+    /// The tracked state of the selected mailbox.
+    pub(crate) selected: Option<SelectedMailbox>,
+
+    /// Tags of the commands sent whose tagged response has not been read yet.
+    pub(crate) pending: Vec<RequestId>,
+
+    /// The tag of an `IDLE` started by [`Session::idle_stream`] that has not been ended with
+    /// `DONE` yet.
+    pub(crate) idling: Option<RequestId>,
+
+    /// How long to wait for the next response to a pending command.
+    pub(crate) command_timeout: Option<Duration>,
+
+    /// Fires when the server took longer than `command_timeout` to respond.
+    deadline: Option<Timer>,
+
+    /// Set when a command timed out, as its responses may still arrive.
+    pub(crate) poisoned: bool,
+
+    /// Set when reading from the stream failed or the server closed the connection.
+    pub(crate) failed: bool,
+
+    /// The number of bytes fetched at once, see [`Session::set_download_chunk_size`].
+    download_chunk_size: u64,
+
+    /// A read error held back by
+    /// [`Handle::wait_with_timeout`](extensions::idle::Handle::wait_with_timeout) to return the
+    /// events read before it first.
+    pub(crate) read_error: Option<Error>,
+
+    /// The messages of the pending commands that return `FETCH` responses, to tell them apart
+    /// from unsolicited ones, see [`Connection::expect_fetches`].
+    fetch_sets: Vec<FetchSet>,
+}
+
+/// The messages a command returns `FETCH` responses for.
+#[derive(Debug)]
+struct FetchSet {
+    tag: RequestId,
+    uid: bool,
+    /// `None` if the set is unknown, e.g. for the `$` of
+    /// [RFC 5182](https://tools.ietf.org/html/rfc5182).
+    set: Option<Vec<RangeInclusive<u32>>>,
+}
+
+/// The server greeting, as returned by [`Client::read_greeting`].
+///
+/// The greeting is the first response the server sends after the connection is established, see
+/// [section 7.1 of RFC 3501](https://tools.ietf.org/html/rfc3501#section-7.1). A `BYE` greeting,
+/// sent by servers that refuse the connection, is returned as [`Error::Bye`] instead.
+#[derive(Debug)]
+pub enum Greeting<T: Read + Write + Unpin + fmt::Debug> {
+    /// An `OK` greeting. The connection is in the not authenticated state and the client has to
+    /// log in using [`Client::login`] or [`Client::authenticate`].
+    Ok {
+        /// The unauthenticated client.
+        client: Client<T>,
+        /// Capabilities sent in a `CAPABILITY` response code of the greeting, if any.
+        capabilities: Option<Capabilities>,
+        /// Human-readable text of the greeting.
+        information: Option<String>,
+    },
+    /// A `PREAUTH` greeting. The connection has already been authenticated by external means,
+    /// e.g. when talking to an IMAP server spawned over a local pipe, so no login is needed.
+    PreAuth {
+        /// The authenticated session.
+        session: Session<T>,
+        /// Capabilities sent in a `CAPABILITY` response code of the greeting, if any.
+        capabilities: Option<Capabilities>,
+        /// Human-readable text of the greeting.
+        information: Option<String>,
+    },
 }
 
 // `Deref` instances are so we can make use of the same underlying primitives in `Client` and
@@ -137,10 +235,106 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Client<T> {
             conn: Connection {
                 stream,
                 request_ids: IdGenerator::new(),
+                capabilities: None,
+                bye: None,
+                last_command: None,
+                last_ok_code: None,
+                track_selected: false,
+                selected: None,
+                pending: Vec::new(),
+                idling: None,
+                command_timeout: None,
+                deadline: None,
+                poisoned: false,
+                failed: false,
+                read_error: None,
+                download_chunk_size: DOWNLOAD_CHUNK_SIZE,
+                fetch_sets: Vec::new(),
             },
         }
     }
 
+    /// Reads the server greeting.
+    ///
+    /// This has to be called right after connecting, before any command is sent. Depending on
+    /// the greeting, either the unauthenticated client or an already authenticated [`Session`]
+    /// is returned.  If the server greets with `BYE`, [`Error::Bye`] is returned.
+    ///
+    /// Capabilities sent along with the greeting are remembered and returned by
+    /// [`Client::capabilities`] without asking the server again.
+    ///
+    /// ```ignore
+    /// match client.read_greeting().await? {
+    ///     Greeting::Ok { client, .. } => {
+    ///         let session = client.login("user", "pass").await.map_err(|(err, _)| err)?;
+    ///     }
+    ///     Greeting::PreAuth { session, .. } => {
+    ///         // already authenticated
+    ///     }
+    /// }
+    /// ```
+    pub async fn read_greeting(mut self) -> Result<Greeting<T>> {
+        let res = self.conn.next_response().await?;
+
+        let Response::Data {
+            status,
+            code,
+            information,
+        } = res.parsed()
+        else {
+            return Err(Error::Parse(ParseError::Unexpected(format!(
+                "{:?}",
+                res.parsed()
+            ))));
+        };
+
+        let capabilities = match code {
+            Some(imap_proto::ResponseCode::Capabilities(caps)) => {
+                Some(Capabilities::from_parsed(caps))
+            }
+            _ => None,
+        };
+        let information = information.as_ref().map(|s| s.to_string());
+
+        match status {
+            imap_proto::Status::Ok => Ok(Greeting::Ok {
+                client: self,
+                capabilities,
+                information,
+            }),
+            imap_proto::Status::PreAuth => Ok(Greeting::PreAuth {
+                session: Session::new(self.conn),
+                capabilities,
+                information,
+            }),
+            imap_proto::Status::Bye => Err(Error::bye(code.as_ref(), information.as_deref())),
+            _ => Err(Error::Parse(ParseError::Unexpected(format!(
+                "{:?}",
+                res.parsed()
+            )))),
+        }
+    }
+
+    /// Returns the capabilities of the server.
+    ///
+    /// If the server announced its capabilities in the greeting (see [`Client::read_greeting`]),
+    /// these are returned without another round trip. Otherwise the [`CAPABILITY`
+    /// command](https://tools.ietf.org/html/rfc3501#section-6.1.1) is sent.
+    ///
+    /// This allows to pick an authentication mechanism, or to detect `LOGINDISABLED`, before
+    /// calling [`Client::login`] or [`Client::authenticate`].
+    pub async fn capabilities(&mut self) -> Result<Capabilities> {
+        if let Some(capabilities) = &self.conn.capabilities {
+            return Ok(capabilities.clone());
+        }
+
+        let id = self.run_command("CAPABILITY").await?;
+        // There is no unsolicited responses channel before authentication, drop them.
+        let (tx, _rx) = bounded(1);
+        let capabilities = parse_capabilities(&mut self.conn, tx.into(), id).await?;
+        Ok(capabilities)
+    }
+
     /// Convert this Client into the raw underlying stream.
     pub fn into_inner(self) -> T {
         let Self { conn, .. } = self;
@@ -241,6 +435,48 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Client<T> {
         mut self,
         auth_type: S,
         authenticator: A,
+    ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
+        let id = ok_or_unauth_client_err!(
+            self.run_command(&format!("AUTHENTICATE {}", auth_type.as_ref()))
+                .await,
+            self
+        );
+        let session = self
+            .do_auth_handshake(id, SyncAuthenticator(authenticator))
+            .await?;
+        Ok(session)
+    }
+
+    /// Authenticate with the server using the given custom `authenticator` to handle the server's
+    /// challenge, like [`Client::authenticate`], but allows the `authenticator` to wait for
+    /// external resources and to fail.
+    ///
+    /// If the `authenticator` returns an error, the exchange is cancelled and
+    /// [`Error::AuthenticationAborted`] is returned together with the original `Client`.
+    ///
+    /// ```ignore
+    /// struct OAuth2 {
+    ///     user: String,
+    ///     tokens: TokenStore,
+    /// }
+    ///
+    /// impl async_imap::AsyncAuthenticator for &OAuth2 {
+    ///     type Response = String;
+    ///     type Error = TokenError;
+    ///
+    ///     async fn process(&mut self, _: &[u8]) -> Result<Self::Response, Self::Error> {
+    ///         let access_token = self.tokens.refresh().await?;
+    ///         Ok(format!(
+    ///             "user={}\x01auth=Bearer {}\x01\x01",
+    ///             self.user, access_token
+    ///         ))
+    ///     }
+    /// }
+    /// ```
+    pub async fn authenticate_async<A: AsyncAuthenticator, S: AsRef<str>>(
+        mut self,
+        auth_type: S,
+        authenticator: A,
     ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
         let id = ok_or_unauth_client_err!(
             self.run_command(&format!("AUTHENTICATE {}", auth_type.as_ref()))
@@ -252,7 +488,7 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Client<T> {
     }
 
     /// This func does the handshake process once the authenticate command is made.
-    async fn do_auth_handshake<A: Authenticator>(
+    async fn do_auth_handshake<A: AsyncAuthenticator>(
         mut self,
         id: RequestId,
         mut authenticator: A,
@@ -261,23 +497,38 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Client<T> {
         // early (see also comment on `login`)
         loop {
             if let Some(res) = self.read_response().await {
-                let res = ok_or_unauth_client_err!(res.map_err(Into::into), self);
+                let res = res.map_err(|err| self.conn.closed(Some(err)));
+                let res = ok_or_unauth_client_err!(res, self);
                 match res.parsed() {
                     Response::Continue { information, .. } => {
                         let challenge = if let Some(text) = information {
-                            ok_or_unauth_client_err!(
-                                base64::engine::general_purpose::STANDARD
-                                    .decode(text.as_ref())
-                                    .map_err(|e| Error::Parse(ParseError::Authentication(
+                            match base64::engine::general_purpose::STANDARD.decode(text.as_ref()) {
+                                Ok(challenge) => challenge,
+                                Err(e) => {
+                                    let err = Error::Parse(ParseError::Authentication(
                                         (*text).to_string(),
-                                        Some(e)
-                                    ))),
-                                self
-                            )
+                                        Some(e),
+                                    ));
+                                    ok_or_unauth_client_err!(
+                                        self.cancel_auth_handshake(&id).await,
+                                        self
+                                    );
+                                    return Err((err, self));
+                                }
+                            }
                         } else {
                             Vec::new()
                         };
-                        let raw_response = &mut authenticator.process(&challenge);
+                        let raw_response = match authenticator.process(&challenge).await {
+                            Ok(raw_response) => raw_response,
+                            Err(e) => {
+                                ok_or_unauth_client_err!(
+                                    self.cancel_auth_handshake(&id).await,
+                                    self
+                                );
+                                return Err((Error::AuthenticationAborted(Box::new(e)), self));
+                            }
+                        };
                         let auth_response =
                             base64::engine::general_purpose::STANDARD.encode(raw_response);
 
@@ -295,22 +546,31 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Client<T> {
                     }
                 }
             } else {
-                return Err((Error::ConnectionLost, self));
+                let err = self.conn.closed(None);
+                return Err((err, self));
             }
         }
     }
+
+    /// Cancels a running `AUTHENTICATE` exchange by sending `*` and waits for the server to
+    /// reject the command.
+    async fn cancel_auth_handshake(&mut self, id: &RequestId) -> Result<()> {
+        self.conn.run_command_untagged("*").await?;
+        match self.conn.check_done_ok(id, None).await {
+            Ok(()) | Err(Error::Bad { .. }) | Err(Error::No { .. }) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
 }
 
 impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
-    unsafe_pinned!(conn: Connection<T>);
-
-    pub(crate) fn get_stream(self: Pin<&mut Self>) -> Pin<&mut ImapStream<T>> {
-        self.conn().stream()
+    pub(crate) fn get_stream(self: Pin<&mut Self>) -> Pin<&mut Connection<T>> {
+        Pin::new(&mut self.get_mut().conn)
     }
 
     // not public, just to avoid duplicating the channel creation code
     fn new(conn: Connection<T>) -> Self {
-        let (tx, rx) = bounded(100);
+        let (tx, rx) = UnsolicitedSender::new(100, OverflowPolicy::default());
         Session {
             conn,
             unsolicited_responses: rx,
@@ -318,6 +578,72 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
         }
     }
 
+    /// A session on `stream` that skips the greeting and login, see `mock_session!`.
+    #[cfg(test)]
+    pub(crate) fn from_stream(stream: T) -> Self {
+        Session::new(Client::new(stream).conn)
+    }
+
+    /// Replaces the [`Session::unsolicited_responses`] channel with one of the given `capacity`,
+    /// which handles responses that do not fit anymore according to `policy`. A capacity of 0 is
+    /// taken as 1. Responses queued in the previous channel are moved over; those that do not fit
+    /// are dropped and counted in [`Session::unsolicited_overflow_count`].
+    ///
+    /// Receivers of the previous channel, i.e. clones of [`Session::unsolicited_responses`] taken
+    /// before, do not receive any more responses and have to be obtained again. This includes the
+    /// receiver of a [`SessionHandle`](crate::SessionHandle), so the channel has to be configured
+    /// before creating one.
+    ///
+    /// By default, the channel has a capacity of 100 and uses [`OverflowPolicy::DropNewest`].
+    pub fn configure_unsolicited_responses(&mut self, capacity: usize, policy: OverflowPolicy) {
+        let (tx, rx) = self.unsolicited_responses_tx.reconfigure(capacity, policy);
+        let old = std::mem::replace(&mut self.unsolicited_responses, rx);
+        while let Ok(response) = old.try_recv() {
+            if tx.try_send(response).is_err() {
+                tx.overflowed();
+            }
+        }
+        self.unsolicited_responses_tx = tx;
+    }
+
+    /// Returns the number of unsolicited responses that were dropped because the
+    /// [`Session::unsolicited_responses`] channel was full, see [`OverflowPolicy`].
+    pub fn unsolicited_overflow_count(&self) -> u64 {
+        self.unsolicited_responses_tx.overflow_count()
+    }
+
+    /// Sets a handler that is called with every unsolicited response as it is read, instead of
+    /// sending it to the [`Session::unsolicited_responses`] channel.
+    ///
+    /// Responses already queued in the channel stay there.
+    pub fn set_unsolicited_handler<H: UnsolicitedHandler + 'static>(&mut self, handler: H) {
+        self.unsolicited_responses_tx
+            .set_handler(Some(Box::new(handler)));
+    }
+
+    /// Removes the handler set with [`Session::set_unsolicited_handler`], so that unsolicited
+    /// responses are sent to the [`Session::unsolicited_responses`] channel again.
+    pub fn remove_unsolicited_handler(&mut self) {
+        self.unsolicited_responses_tx.set_handler(None);
+    }
+
+    /// Enables or disables tracking of the selected mailbox, see [`SelectedMailbox`].
+    ///
+    /// Once enabled, tracking starts with the next [`Session::select`] or [`Session::examine`].
+    /// Disabling it drops the tracked state.
+    pub fn set_track_selected_mailbox(&mut self, track: bool) {
+        self.conn.track_selected = track;
+        if !track {
+            self.conn.selected = None;
+        }
+    }
+
+    /// Returns the tracked state of the selected mailbox, if tracking is enabled and a mailbox is
+    /// selected.
+    pub fn selected_mailbox(&self) -> Option<&SelectedMailbox> {
+        self.conn.selected.as_ref()
+    }
+
     /// Selects a mailbox.
     ///
     /// The `SELECT` command selects a mailbox so that messages in the mailbox can be accessed.
@@ -336,18 +662,16 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
     /// you *may* see additional untagged `RECENT`, `EXISTS`, `FETCH`, and `EXPUNGE` responses.
     /// You can get them from the `unsolicited_responses` channel of the [`Session`](struct.Session.html).
     pub async fn select<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<Mailbox> {
-        // TODO: also note READ/WRITE vs READ-only mode!
         let id = self
             .run_command(&format!("SELECT {}", validate_str(mailbox_name.as_ref())?))
             .await?;
-        let mbox = parse_mailbox(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        )
-        .await?;
+        self.conn.begin_select(mailbox_name.as_ref());
+        let mbox = parse_mailbox(&mut self.conn, self.unsolicited_responses_tx.clone(), id).await;
+        if mbox.is_err() {
+            self.conn.selected = None;
+        }
 
-        Ok(mbox)
+        mbox
     }
 
     /// Selects a mailbox with `(CONDSTORE)` parameter as defined in
@@ -359,14 +683,13 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
                 validate_str(mailbox_name.as_ref())?
             ))
             .await?;
-        let mbox = parse_mailbox(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        )
-        .await?;
+        self.conn.begin_select(mailbox_name.as_ref());
+        let mbox = parse_mailbox(&mut self.conn, self.unsolicited_responses_tx.clone(), id).await;
+        if mbox.is_err() {
+            self.conn.selected = None;
+        }
 
-        Ok(mbox)
+        mbox
     }
 
     /// The `EXAMINE` command is identical to [`Session::select`] and returns the same output;
@@ -377,14 +700,13 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
         let id = self
             .run_command(&format!("EXAMINE {}", validate_str(mailbox_name.as_ref())?))
             .await?;
-        let mbox = parse_mailbox(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        )
-        .await?;
+        self.conn.begin_select(mailbox_name.as_ref());
+        let mbox = parse_mailbox(&mut self.conn, self.unsolicited_responses_tx.clone(), id).await;
+        if mbox.is_err() {
+            self.conn.selected = None;
+        }
 
-        Ok(mbox)
+        mbox
     }
 
     /// Fetch retreives data associated with a set of messages in the mailbox.
@@ -408,34 +730,34 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
     ///  - `INTERNALDATE`: The internal date of the message.
     ///  - `BODY[<section>]`:
     ///
-    ///     The text of a particular body section.  The section specification is a set of zero or
-    ///     more part specifiers delimited by periods.  A part specifier is either a part number
-    ///     (see RFC) or one of the following: `HEADER`, `HEADER.FIELDS`, `HEADER.FIELDS.NOT`,
-    ///     `MIME`, and `TEXT`.  An empty section specification (i.e., `BODY[]`) refers to the
-    ///     entire message, including the header.
-    ///
-    ///     The `HEADER`, `HEADER.FIELDS`, and `HEADER.FIELDS.NOT` part specifiers refer to the
-    ///     [RFC-2822](https://tools.ietf.org/html/rfc2822) header of the message or of an
-    ///     encapsulated [MIME-IMT](https://tools.ietf.org/html/rfc2046)
-    ///     MESSAGE/[RFC822](https://tools.ietf.org/html/rfc822) message. `HEADER.FIELDS` and
-    ///     `HEADER.FIELDS.NOT` are followed by a list of field-name (as defined in
-    ///     [RFC-2822](https://tools.ietf.org/html/rfc2822)) names, and return a subset of the
-    ///     header.  The subset returned by `HEADER.FIELDS` contains only those header fields with
-    ///     a field-name that matches one of the names in the list; similarly, the subset returned
-    ///     by `HEADER.FIELDS.NOT` contains only the header fields with a non-matching field-name.
-    ///     The field-matching is case-insensitive but otherwise exact.  Subsetting does not
-    ///     exclude the [RFC-2822](https://tools.ietf.org/html/rfc2822) delimiting blank line
-    ///     between the header and the body; the blank line is included in all header fetches,
-    ///     except in the case of a message which has no body and no blank line.
-    ///
-    ///     The `MIME` part specifier refers to the [MIME-IMB](https://tools.ietf.org/html/rfc2045)
-    ///     header for this part.
-    ///
-    ///     The `TEXT` part specifier refers to the text body of the message,
-    ///     omitting the [RFC-2822](https://tools.ietf.org/html/rfc2822) header.
-    ///
-    ///     [`Flag::Seen`] is implicitly set when `BODY` is fetched; if this causes the flags to
-    ///     change, they will generally be included as part of the `FETCH` responses.
+    ///    The text of a particular body section.  The section specification is a set of zero or
+    ///    more part specifiers delimited by periods.  A part specifier is either a part number
+    ///    (see RFC) or one of the following: `HEADER`, `HEADER.FIELDS`, `HEADER.FIELDS.NOT`,
+    ///    `MIME`, and `TEXT`.  An empty section specification (i.e., `BODY[]`) refers to the
+    ///    entire message, including the header.
+    ///
+    ///    The `HEADER`, `HEADER.FIELDS`, and `HEADER.FIELDS.NOT` part specifiers refer to the
+    ///    [RFC-2822](https://tools.ietf.org/html/rfc2822) header of the message or of an
+    ///    encapsulated [MIME-IMT](https://tools.ietf.org/html/rfc2046)
+    ///    MESSAGE/[RFC822](https://tools.ietf.org/html/rfc822) message. `HEADER.FIELDS` and
+    ///    `HEADER.FIELDS.NOT` are followed by a list of field-name (as defined in
+    ///    [RFC-2822](https://tools.ietf.org/html/rfc2822)) names, and return a subset of the
+    ///    header.  The subset returned by `HEADER.FIELDS` contains only those header fields with
+    ///    a field-name that matches one of the names in the list; similarly, the subset returned
+    ///    by `HEADER.FIELDS.NOT` contains only the header fields with a non-matching field-name.
+    ///    The field-matching is case-insensitive but otherwise exact.  Subsetting does not
+    ///    exclude the [RFC-2822](https://tools.ietf.org/html/rfc2822) delimiting blank line
+    ///    between the header and the body; the blank line is included in all header fetches,
+    ///    except in the case of a message which has no body and no blank line.
+    ///
+    ///    The `MIME` part specifier refers to the [MIME-IMB](https://tools.ietf.org/html/rfc2045)
+    ///    header for this part.
+    ///
+    ///    The `TEXT` part specifier refers to the text body of the message,
+    ///    omitting the [RFC-2822](https://tools.ietf.org/html/rfc2822) header.
+    ///
+    ///    [`Flag::Seen`] is implicitly set when `BODY` is fetched; if this causes the flags to
+    ///    change, they will generally be included as part of the `FETCH` responses.
     ///  - `BODY.PEEK[<section>]`: An alternate form of `BODY[<section>]` that does not implicitly
     ///    set [`Flag::Seen`].
     ///  - `ENVELOPE`: The envelope structure of the message.  This is computed by the server by
@@ -461,11 +783,8 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
                 query.as_ref()
             ))
             .await?;
-        let res = parse_fetches(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        );
+        self.conn.expect_fetches(&id, false, sequence_set.as_ref());
+        let res = parse_fetches(&mut self.conn, self.unsolicited_responses_tx.clone(), id);
 
         Ok(res)
     }
@@ -488,23 +807,160 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
                 query.as_ref()
             ))
             .await?;
-        let res = parse_fetches(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        );
+        self.conn.expect_fetches(&id, true, uid_set.as_ref());
+        let res = parse_fetches(&mut self.conn, self.unsolicited_responses_tx.clone(), id);
         Ok(res)
     }
 
+    /// Fetches a body section of the message with the given UID without buffering it, for
+    /// messages too large to be held in memory.
+    ///
+    /// `section` is a section specification as in `BODY[<section>]`, e.g. `""` for the whole
+    /// message or `"2"` for its second part. The section is fetched with `BODY.PEEK`, so
+    /// [`Flag::Seen`] is not set. The returned [`BodyReader`] reads the body directly from the
+    /// connection, see there for details.
+    ///
+    /// Returns `None` if the message does not exist.
+    pub async fn uid_fetch_body<S: AsRef<str>>(
+        &mut self,
+        uid: Uid,
+        section: S,
+    ) -> Result<Option<BodyReader<'_, T>>> {
+        let id = self
+            .run_command(&format!(
+                "UID FETCH {} (UID BODY.PEEK[{}])",
+                uid,
+                section.as_ref()
+            ))
+            .await?;
+        self.conn.expect_fetches(&id, true, &uid.to_string());
+        BodyReader::new(self, id, uid).await
+    }
+
+    /// Downloads the message with the given UID to `writer`, and returns its size.
+    ///
+    /// The message is fetched in chunks of 1 MiB, see [`Session::set_download_chunk_size`], with
+    /// partial `BODY.PEEK[]<offset.length>` fetches, so an interrupted download can be continued
+    /// with [`Session::resume_download`].
+    /// Once the message was written, its length is checked against its `RFC822.SIZE`, a
+    /// difference is returned as [`Error::SizeMismatch`].
+    ///
+    /// ```ignore
+    /// let mut file = File::create("message.eml").await?;
+    /// if let Err(err) = session.download_message(uid, &mut file).await {
+    ///     // ... reconnect and select the mailbox again ...
+    ///     let offset = file.metadata().await?.len();
+    ///     session.resume_download(uid, offset, &mut file).await?;
+    /// }
+    /// ```
+    pub async fn download_message<W: Write + Unpin>(&mut self, uid: Uid, writer: W) -> Result<u64> {
+        self.resume_download(uid, 0, writer).await
+    }
+
+    /// Continues a [`Session::download_message`] of which the first `offset` bytes were written
+    /// already, writing the rest of the message to `writer`. Returns the size of the message.
+    ///
+    /// UIDs are only valid as long as the `UIDVALIDITY` of the mailbox does not change, which
+    /// should be checked after reconnecting.
+    pub async fn resume_download<W: Write + Unpin>(
+        &mut self,
+        uid: Uid,
+        mut offset: u64,
+        writer: W,
+    ) -> Result<u64> {
+        self.download_from(uid, &mut offset, writer).await
+    }
+
+    /// Sets the number of bytes [`Session::download_message`] and [`Session::resume_download`]
+    /// fetch at once, 1 MiB by default. Larger chunks need fewer round trips, smaller ones hold
+    /// less in memory and lose less when the connection fails. A size of 0 is taken as 1.
+    pub fn set_download_chunk_size(&mut self, size: u64) {
+        self.conn.download_chunk_size = size.max(1);
+    }
+
+    /// The chunk size set with [`Session::set_download_chunk_size`].
+    pub fn download_chunk_size(&self) -> u64 {
+        self.conn.download_chunk_size
+    }
+
+    /// Implements [`Session::resume_download`], keeping `offset` up to date with the bytes written
+    /// so far, also if the download fails.
+    pub(crate) async fn download_from<W: Write + Unpin>(
+        &mut self,
+        uid: Uid,
+        offset: &mut u64,
+        mut writer: W,
+    ) -> Result<u64> {
+        let size = self
+            .uid_fetch_one(uid, "(UID RFC822.SIZE)")
+            .await?
+            .size
+            .map(u64::from)
+            .ok_or_else(|| {
+                Error::Parse(ParseError::ExpectedResponseNotFound("RFC822.SIZE".into()))
+            })?;
+        while *offset < size {
+            let query = format!(
+                "(UID BODY.PEEK[]<{}.{}>)",
+                *offset, self.conn.download_chunk_size
+            );
+            let fetch = self.uid_fetch_one(uid, &query).await?;
+            let chunk = fetch.body().unwrap_or_default();
+            if chunk.is_empty() {
+                // The message is shorter than announced.
+                break;
+            }
+            writer.write_all(chunk).await?;
+            *offset += chunk.len() as u64;
+        }
+        writer.flush().await?;
+        if *offset != size {
+            return Err(Error::SizeMismatch {
+                expected: size,
+                actual: *offset,
+            });
+        }
+        Ok(size)
+    }
+
+    /// Fetches `query` for the message with the given UID, failing if it does not exist.
+    async fn uid_fetch_one(&mut self, uid: Uid, query: &str) -> Result<Fetch> {
+        let fetches: Vec<Fetch> = self
+            .uid_fetch(uid.to_string(), query)
+            .await?
+            .try_collect()
+            .await?;
+        fetches
+            .into_iter()
+            .find(|fetch| fetch.uid == Some(uid))
+            .ok_or_else(|| {
+                Error::Parse(ParseError::ExpectedResponseNotFound(format!(
+                    "message with UID {}",
+                    uid
+                )))
+            })
+    }
+
+    /// Returns a [`Pipeline`] to send several `STATUS`, `FETCH` or `STORE` commands at once,
+    /// without waiting for each response in between. This saves round trips, e.g. when
+    /// checking many mailboxes:
+    ///
+    /// ```ignore
+    /// let results = session
+    ///     .pipeline()
+    ///     .status("INBOX", "(MESSAGES UNSEEN)")
+    ///     .status("Archive", "(MESSAGES UNSEEN)")
+    ///     .run()
+    ///     .await?;
+    /// ```
+    pub fn pipeline(&mut self) -> Pipeline<'_, T> {
+        Pipeline::new(self)
+    }
+
     /// Noop always succeeds, and it does nothing.
     pub async fn noop(&mut self) -> Result<()> {
         let id = self.run_command("NOOP").await?;
-        parse_noop(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        )
-        .await?;
+        parse_noop(&mut self.conn, self.unsolicited_responses_tx.clone(), id).await?;
         Ok(())
     }
 
@@ -632,14 +1088,17 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
     /// The [`CAPABILITY` command](https://tools.ietf.org/html/rfc3501#section-6.1.1) requests a
     /// listing of capabilities that the server supports.  The server will include "IMAP4rev1" as
     /// one of the listed capabilities. See [`Capabilities`] for further details.
+    ///
+    /// The capabilities are cached, so this only sends the command if the server has not
+    /// announced them yet, see [`Connection::cached_capabilities`].
     pub async fn capabilities(&mut self) -> Result<Capabilities> {
+        if let Some(capabilities) = &self.conn.capabilities {
+            return Ok(capabilities.clone());
+        }
+
         let id = self.run_command("CAPABILITY").await?;
-        let c = parse_capabilities(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        )
-        .await?;
+        let c =
+            parse_capabilities(&mut self.conn, self.unsolicited_responses_tx.clone(), id).await?;
         Ok(c)
     }
 
@@ -648,11 +1107,7 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
     /// The message sequence number of each message that is removed is returned.
     pub async fn expunge(&mut self) -> Result<impl Stream<Item = Result<Seq>> + '_ + Send> {
         let id = self.run_command("EXPUNGE").await?;
-        let res = parse_expunge(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        );
+        let res = parse_expunge(&mut self.conn, self.unsolicited_responses_tx.clone(), id);
         Ok(res)
     }
 
@@ -685,11 +1140,7 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
         let id = self
             .run_command(&format!("UID EXPUNGE {}", uid_set.as_ref()))
             .await?;
-        let res = parse_expunge(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        );
+        let res = parse_expunge(&mut self.conn, self.unsolicited_responses_tx.clone(), id);
         Ok(res)
     }
 
@@ -725,6 +1176,29 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
     /// probably ignore) are sent.
     pub async fn close(&mut self) -> Result<()> {
         self.run_command_and_check_ok("CLOSE").await?;
+        self.conn.selected = None;
+        Ok(())
+    }
+
+    /// The [`UNSELECT` command](https://tools.ietf.org/html/rfc3691) closes the selected mailbox
+    /// like [`Session::close`], but without removing the messages that have [`Flag::Deleted`]
+    /// set.
+    ///
+    /// Servers without the `UNSELECT` capability are sent an `EXAMINE` of a mailbox that does not
+    /// exist instead, which deselects the current mailbox as well when it fails.
+    pub async fn unselect(&mut self) -> Result<()> {
+        let capabilities = self.capabilities().await?;
+        if capabilities.has_str("UNSELECT") || capabilities.has_str("IMAP4rev2") {
+            self.run_command_and_check_ok("UNSELECT").await?;
+        } else {
+            match self.examine(UNSELECT_FALLBACK_MAILBOX).await {
+                Err(Error::No { .. }) => {}
+                // The mailbox does exist, it is examined, so closing it does not expunge anything.
+                Ok(_) => self.run_command_and_check_ok("CLOSE").await?,
+                Err(err) => return Err(err),
+            }
+        }
+        self.conn.selected = None;
         Ok(())
     }
 
@@ -793,11 +1267,8 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
                 query.as_ref()
             ))
             .await?;
-        let res = parse_fetches(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        );
+        self.conn.expect_fetches(&id, false, sequence_set.as_ref());
+        let res = parse_fetches(&mut self.conn, self.unsolicited_responses_tx.clone(), id);
         Ok(res)
     }
 
@@ -819,11 +1290,8 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
                 query.as_ref()
             ))
             .await?;
-        let res = parse_fetches(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        );
+        self.conn.expect_fetches(&id, true, uid_set.as_ref());
+        let res = parse_fetches(&mut self.conn, self.unsolicited_responses_tx.clone(), id);
         Ok(res)
     }
 
@@ -975,7 +1443,7 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
             .await?;
 
         Ok(parse_names(
-            &mut self.conn.stream,
+            &mut self.conn,
             self.unsolicited_responses_tx.clone(),
             id,
         ))
@@ -1008,11 +1476,7 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
                 mailbox_pattern.unwrap_or("")
             ))
             .await?;
-        let names = parse_names(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        );
+        let names = parse_names(&mut self.conn, self.unsolicited_responses_tx.clone(), id);
 
         Ok(names)
     }
@@ -1064,7 +1528,7 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
             ))
             .await?;
         let mbox = parse_status(
-            &mut self.conn.stream,
+            &mut self.conn,
             mailbox_name.as_ref(),
             self.unsolicited_responses_tx.clone(),
             id,
@@ -1095,6 +1559,66 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
         extensions::idle::Handle::new(self)
     }
 
+    /// Returns a stream of the changes to the selected mailbox, such as new messages, that keeps
+    /// waiting for them until it is dropped.
+    ///
+    /// Unlike a [`extensions::idle::Handle`], it ends the `IDLE` command and starts a new one
+    /// whenever `keepalive` has passed, as the server or a NAT gateway in between may consider
+    /// the connection inactive otherwise. [RFC 2177](https://tools.ietf.org/html/rfc2177)
+    /// requires this at least every 29 minutes, many gateways need it more often. If the server
+    /// does not support `IDLE`, a `NOOP` is sent every `keepalive` instead to poll for changes.
+    ///
+    /// Events that arrive in quick succession are merged where possible, e.g. only the last of
+    /// several `EXISTS` responses is returned. Other unsolicited responses, such as alerts, are
+    /// sent to the [`Session::unsolicited_responses`] channel as usual. The stream does not end
+    /// by itself, it only returns an error once the connection fails. The `IDLE` command is ended
+    /// by the next command sent after dropping the stream.
+    ///
+    /// ```ignore
+    /// session.select("INBOX").await?;
+    /// let mut events = session.idle_stream(Duration::from_secs(5 * 60));
+    /// while let Some(event) = events.try_next().await? {
+    ///     if let MailboxEvent::NewMessages(n) = event {
+    ///         println!("{} messages", n);
+    ///     }
+    /// }
+    /// ```
+    pub fn idle_stream(
+        &mut self,
+        keepalive: Duration,
+    ) -> impl Stream<Item = Result<MailboxEvent>> + Unpin + Send + '_ {
+        extensions::idle::keepalive_stream(self, keepalive)
+    }
+
+    /// Returns a stream of the changes to a mailbox that is not selected, found by polling it
+    /// with [`Session::status`] every `interval`.
+    ///
+    /// Each `STATUS` result is compared with the previous one: a changed message count or
+    /// [`Mailbox::uid_next`] is returned as [`MailboxEvent::NewMessages`], a changed
+    /// [`Mailbox::highest_modseq`] as [`MailboxEvent::HighestModSeq`] if the server supports
+    /// `CONDSTORE`, and a changed [`Mailbox::uid_validity`] as
+    /// [`MailboxEvent::UidValidityChanged`]. Individual expunges and flag changes cannot be told
+    /// apart this way.
+    ///
+    /// `STATUS` must not be used to check the selected mailbox for changes, use
+    /// [`Session::idle_stream`] for that, which polls with `NOOP` if the server does not support
+    /// `IDLE`. Like it, the stream does not end by itself, it only returns an error once the
+    /// connection fails.
+    ///
+    /// ```ignore
+    /// let mut events = session.watch_mailbox("Archive", Duration::from_secs(60));
+    /// while let Some(event) = events.try_next().await? {
+    ///     println!("Archive changed: {:?}", event);
+    /// }
+    /// ```
+    pub fn watch_mailbox<S: AsRef<str>>(
+        &mut self,
+        mailbox_name: S,
+        interval: Duration,
+    ) -> impl Stream<Item = Result<MailboxEvent>> + Unpin + Send + '_ {
+        crate::watcher::status_stream(self, mailbox_name.as_ref(), interval)
+    }
+
     /// The [`APPEND` command](https://tools.ietf.org/html/rfc3501#section-6.3.11) appends
     /// `content` as a new message to the end of the specified destination `mailbox`.  This
     /// argument SHOULD be in the format of an [RFC-2822](https://tools.ietf.org/html/rfc2822)
@@ -1119,13 +1643,35 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
         mailbox: S,
         content: B,
     ) -> Result<()> {
-        let content = content.as_ref();
-        self.run_command(&format!(
-            "APPEND \"{}\" {{{}}}",
-            mailbox.as_ref(),
-            content.len()
-        ))
-        .await?;
+        self.append_literal(mailbox.as_ref(), content.as_ref(), "")
+            .await
+    }
+
+    /// Appends a message like [`Session::append`], but sends it as a binary literal, see
+    /// [RFC 3516](https://tools.ietf.org/html/rfc3516). The message may then contain 8-bit data
+    /// and NUL bytes, e.g. parts with `Content-Transfer-Encoding: binary`, which the server may
+    /// encode as needed.
+    ///
+    /// The server has to support the `BINARY` extension.
+    pub async fn append_binary<S: AsRef<str>, B: AsRef<[u8]>>(
+        &mut self,
+        mailbox: S,
+        content: B,
+    ) -> Result<()> {
+        self.append_literal(mailbox.as_ref(), content.as_ref(), "~")
+            .await
+    }
+
+    /// Sends `APPEND` with `content` as a literal, or as a literal8 if `prefix` is `~`.
+    async fn append_literal(&mut self, mailbox: &str, content: &[u8], prefix: &str) -> Result<()> {
+        let id = self
+            .run_command(&format!(
+                "APPEND \"{}\" {}{{{}}}",
+                mailbox,
+                prefix,
+                content.len()
+            ))
+            .await?;
 
         match self.read_response().await {
             Some(Ok(res)) => {
@@ -1133,8 +1679,9 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
                     self.stream.as_mut().write_all(content).await?;
                     self.stream.as_mut().write_all(b"\r\n").await?;
                     self.stream.flush().await?;
-                    self.read_response().await.transpose()?;
-                    Ok(())
+                    self.conn
+                        .check_done_ok(&id, Some(self.unsolicited_responses_tx.clone()))
+                        .await
                 } else {
                     Err(Error::Append)
                 }
@@ -1192,12 +1739,7 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
         let id = self
             .run_command(&format!("SEARCH {}", query.as_ref()))
             .await?;
-        let seqs = parse_ids(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        )
-        .await?;
+        let seqs = parse_ids(&mut self.conn, self.unsolicited_responses_tx.clone(), id).await?;
 
         Ok(seqs)
     }
@@ -1209,12 +1751,7 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
         let id = self
             .run_command(&format!("UID SEARCH {}", query.as_ref()))
             .await?;
-        let uids = parse_ids(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        )
-        .await?;
+        let uids = parse_ids(&mut self.conn, self.unsolicited_responses_tx.clone(), id).await?;
 
         Ok(uids)
     }
@@ -1224,12 +1761,7 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
         let id = self
             .run_command(format!("GETQUOTA {}", quote!(quota_root)))
             .await?;
-        let c = parse_get_quota(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        )
-        .await?;
+        let c = parse_get_quota(&mut self.conn, self.unsolicited_responses_tx.clone(), id).await?;
         Ok(c)
     }
 
@@ -1241,12 +1773,8 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
         let id = self
             .run_command(format!("GETQUOTAROOT {}", quote!(mailbox_name)))
             .await?;
-        let c = parse_get_quota_root(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        )
-        .await?;
+        let c =
+            parse_get_quota_root(&mut self.conn, self.unsolicited_responses_tx.clone(), id).await?;
         Ok(c)
     }
 
@@ -1271,7 +1799,7 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
             ))
             .await?;
         let metadata = parse_metadata(
-            &mut self.conn.stream,
+            &mut self.conn,
             mailbox_name,
             self.unsolicited_responses_tx.clone(),
             id,
@@ -1290,12 +1818,8 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
         let id = self
             .run_command(format!("ID ({})", format_identification(identification)))
             .await?;
-        let server_identification = parse_id(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        )
-        .await?;
+        let server_identification =
+            parse_id(&mut self.conn, self.unsolicited_responses_tx.clone(), id).await?;
         Ok(server_identification)
     }
 
@@ -1304,35 +1828,79 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
     /// Sends `ID NIL` command and returns server response.
     pub async fn id_nil(&mut self) -> Result<Option<HashMap<String, String>>> {
         let id = self.run_command("ID NIL").await?;
-        let server_identification = parse_id(
-            &mut self.conn.stream,
-            self.unsolicited_responses_tx.clone(),
-            id,
-        )
-        .await?;
+        let server_identification =
+            parse_id(&mut self.conn, self.unsolicited_responses_tx.clone(), id).await?;
         Ok(server_identification)
     }
 
     // these are only here because they are public interface, the rest is in `Connection`
     /// Runs a command and checks if it returns OK.
     pub async fn run_command_and_check_ok<S: AsRef<str>>(&mut self, command: S) -> Result<()> {
+        let id = self.run_command(command).await?;
         self.conn
-            .run_command_and_check_ok(
-                command.as_ref(),
-                Some(self.unsolicited_responses_tx.clone()),
-            )
+            .check_done_ok(&id, Some(self.unsolicited_responses_tx.clone()))
             .await?;
 
         Ok(())
     }
 
     /// Runs any command passed to it.
+    ///
+    /// The responses to earlier commands that were not read up to their tagged completion, e.g.
+    /// because a stream returned by [`Session::fetch`] was dropped early, are read first. Their
+    /// results are discarded, other responses are handled as unsolicited responses.
     pub async fn run_command<S: AsRef<str>>(&mut self, command: S) -> Result<RequestId> {
+        self.finish_pending().await?;
         let id = self.conn.run_command(command.as_ref()).await?;
 
         Ok(id)
     }
 
+    /// Reads the remaining responses to all commands sent, see [`Session::run_command`]. An
+    /// `IDLE` left running by a dropped [`Session::idle_stream`] is ended first.
+    pub(crate) async fn finish_pending(&mut self) -> Result<()> {
+        if self.conn.poisoned {
+            return Err(Error::Poisoned);
+        }
+        if let Some(id) = self.conn.idling.take() {
+            // An `IDLE` that is still pending has not been continued yet, and `DONE` may only be
+            // sent once it is. The server may also refuse it, which ends it as well.
+            let mut continued = !self.conn.pending.contains(&id);
+            while !continued && self.conn.pending.contains(&id) {
+                let response = self.conn.next_response().await?;
+                match response.parsed() {
+                    Response::Continue { .. } => continued = true,
+                    Response::Done { .. } => {}
+                    _ => handle_unilateral(response, self.unsolicited_responses_tx.clone()).await?,
+                }
+            }
+            if continued {
+                self.conn.run_command_untagged("DONE").await?;
+                if !self.conn.pending.contains(&id) {
+                    self.conn.pending.push(id);
+                }
+            }
+        }
+        while !self.conn.pending.is_empty() {
+            let response = self.conn.next_response().await?;
+            match response.parsed() {
+                // Changes to other messages, e.g. their flags, are passed on.
+                Response::Fetch(seq, attributes) if !self.conn.is_fetched(*seq, attributes) => {
+                    handle_unilateral(response, self.unsolicited_responses_tx.clone()).await?
+                }
+                Response::Done { .. }
+                | Response::Fetch(..)
+                | Response::MailboxData(
+                    MailboxDatum::List { .. }
+                    | MailboxDatum::Search(_)
+                    | MailboxDatum::Status { .. },
+                ) => {}
+                _ => handle_unilateral(response, self.unsolicited_responses_tx.clone()).await?,
+            }
+        }
+        Ok(())
+    }
+
     /// Runs an arbitrary command, without adding a tag to it.
     pub async fn run_command_untagged<S: AsRef<str>>(&mut self, command: S) -> Result<()> {
         self.conn.run_command_untagged(command.as_ref()).await?;
@@ -1347,8 +1915,6 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
 }
 
 impl<T: Read + Write + Unpin + fmt::Debug> Connection<T> {
-    unsafe_pinned!(stream: ImapStream<T>);
-
     /// Convert this connection into the raw underlying stream.
     pub fn into_inner(self) -> T {
         let Self { stream, .. } = self;
@@ -1357,7 +1923,30 @@ impl<T: Read + Write + Unpin + fmt::Debug> Connection<T> {
 
     /// Read the next response on the connection.
     pub async fn read_response(&mut self) -> Option<io::Result<ResponseData>> {
-        self.stream.next().await
+        self.next().await
+    }
+
+    /// Reads the next response, failing with [`Connection::closed`] if there is none.
+    pub(crate) async fn next_response(&mut self) -> Result<ResponseData> {
+        match self.next().await {
+            Some(Ok(response)) => Ok(response),
+            Some(Err(err)) => Err(self.closed(Some(err))),
+            None => Err(self.closed(None)),
+        }
+    }
+
+    /// The error for reading from the connection failing with `err`, or the connection ending if
+    /// it is `None`. After the server sent a `BYE`, that is a graceful shutdown rather than a
+    /// network failure, and the `BYE` is returned as [`Error::Bye`] instead.
+    pub(crate) fn closed(&self, err: Option<io::Error>) -> Error {
+        match (&self.bye, err) {
+            (Some(Error::Bye { code, text }), _) => Error::Bye {
+                code: code.clone(),
+                text: text.clone(),
+            },
+            (_, Some(err)) => Error::Io(err),
+            (_, None) => Error::ConnectionLost,
+        }
     }
 
     pub(crate) async fn run_command_untagged(&mut self, command: &str) -> Result<()> {
@@ -1368,12 +1957,51 @@ impl<T: Read + Write + Unpin + fmt::Debug> Connection<T> {
         Ok(())
     }
 
+    /// Records that the command with `tag` returns `FETCH` responses for the messages in `set`,
+    /// given as UIDs if `uid` is set.
+    pub(crate) fn expect_fetches(&mut self, tag: &RequestId, uid: bool, set: &str) {
+        self.fetch_sets.push(FetchSet {
+            tag: tag.clone(),
+            uid,
+            set: parse_set(set),
+        });
+    }
+
+    /// Whether the `FETCH` response for the message with `seq` belongs to a pending command.
+    fn is_fetched(&self, seq: u32, attributes: &[AttributeValue<'_>]) -> bool {
+        let uid = attributes.iter().find_map(|attribute| match attribute {
+            AttributeValue::Uid(uid) => Some(*uid),
+            _ => None,
+        });
+        self.fetch_sets.iter().any(|fetch_set| {
+            let number = match (fetch_set.uid, uid) {
+                (true, Some(uid)) => uid,
+                // The server includes the UID in every response to a `UID` command.
+                (true, None) => return false,
+                (false, _) => seq,
+            };
+            fetch_set
+                .set
+                .as_ref()
+                .is_none_or(|set| set.iter().any(|range| range.contains(&number)))
+        })
+    }
+
     pub(crate) async fn run_command(&mut self, command: &str) -> Result<RequestId> {
+        if self.poisoned {
+            return Err(Error::Poisoned);
+        }
         let request_id = self.request_ids.next().unwrap(); // safe: never returns Err
+        if invalidates_capabilities(command) {
+            self.capabilities = None;
+        }
+        self.last_command = Some(request_id.clone());
+        self.last_ok_code = None;
         self.stream
             .encode(Request(Some(request_id.clone()), command.as_bytes().into()))
             .await?;
         self.stream.flush().await?;
+        self.pending.push(request_id.clone());
         Ok(request_id)
     }
 
@@ -1384,7 +2012,7 @@ impl<T: Read + Write + Unpin + fmt::Debug> Connection<T> {
         unsolicited: Option<channel::Sender<UnsolicitedResponse>>,
     ) -> Result<()> {
         let id = self.run_command(command).await?;
-        self.check_done_ok(&id, unsolicited).await?;
+        self.check_done_ok(&id, unsolicited.map(Into::into)).await?;
 
         Ok(())
     }
@@ -1392,19 +2020,16 @@ impl<T: Read + Write + Unpin + fmt::Debug> Connection<T> {
     pub(crate) async fn check_done_ok(
         &mut self,
         id: &RequestId,
-        unsolicited: Option<channel::Sender<UnsolicitedResponse>>,
+        unsolicited: Option<UnsolicitedSender>,
     ) -> Result<()> {
-        if let Some(first_res) = self.stream.next().await {
-            self.check_done_ok_from(id, unsolicited, first_res?).await
-        } else {
-            Err(Error::ConnectionLost)
-        }
+        let first_res = self.next_response().await?;
+        self.check_done_ok_from(id, unsolicited, first_res).await
     }
 
     pub(crate) async fn check_done_ok_from(
         &mut self,
         id: &RequestId,
-        unsolicited: Option<channel::Sender<UnsolicitedResponse>>,
+        unsolicited: Option<UnsolicitedSender>,
         mut response: ResponseData,
     ) -> Result<()> {
         loop {
@@ -1418,19 +2043,20 @@ impl<T: Read + Write + Unpin + fmt::Debug> Connection<T> {
                 self.check_status_ok(status, code.as_ref(), information.as_deref())?;
 
                 if tag == id {
+                    if let (Some(imap_proto::ResponseCode::Alert), Some(unsolicited)) =
+                        (code, unsolicited)
+                    {
+                        handle_unilateral(response, unsolicited).await?;
+                    }
                     return Ok(());
                 }
             }
 
             if let Some(unsolicited) = unsolicited.clone() {
-                handle_unilateral(response, unsolicited).await;
+                handle_unilateral(response, unsolicited).await?;
             }
 
-            if let Some(res) = self.stream.next().await {
-                response = res?;
-            } else {
-                return Err(Error::ConnectionLost);
-            }
+            response = self.next_response().await?;
         }
     }
 
@@ -1443,26 +2069,218 @@ impl<T: Read + Write + Unpin + fmt::Debug> Connection<T> {
         use imap_proto::Status;
         match status {
             Status::Ok => Ok(()),
-            Status::Bad => Err(Error::Bad(format!(
-                "code: {:?}, info: {:?}",
-                code, information
-            ))),
-            Status::No => Err(Error::No(format!(
-                "code: {:?}, info: {:?}",
-                code, information
-            ))),
-            _ => Err(Error::Io(io::Error::new(
-                io::ErrorKind::Other,
-                format!(
-                    "status: {:?}, code: {:?}, information: {:?}",
-                    status, code, information
-                ),
-            ))),
+            Status::Bad => Err(Error::bad(code, information)),
+            Status::No => Err(Error::no(code, information)),
+            _ => Err(Error::Io(io::Error::other(format!(
+                "status: {:?}, code: {:?}, information: {:?}",
+                status, code, information
+            )))),
+        }
+    }
+
+    /// Sets how long to wait for the server to send the next response to a command, or `None`
+    /// to wait forever, which is the default.
+    ///
+    /// The timeout applies to every response, so a large `FETCH` does not time out as long as
+    /// data keeps arriving, and it does not apply while no command is running or during `IDLE`,
+    /// which has [`Handle::wait_with_timeout`](crate::extensions::idle::Handle::wait_with_timeout).
+    /// When it expires, the command fails with an [`Error::Io`] of kind
+    /// [`TimedOut`](std::io::ErrorKind::TimedOut). Responses to the command may still arrive
+    /// afterwards, so all further commands fail with [`Error::Poisoned`] and the connection has
+    /// to be replaced.
+    pub fn set_command_timeout(&mut self, timeout: Option<Duration>) {
+        self.command_timeout = timeout;
+        self.deadline = None;
+    }
+
+    /// The timeout set with [`Connection::set_command_timeout`].
+    pub fn command_timeout(&self) -> Option<Duration> {
+        self.command_timeout
+    }
+
+    /// Whether a command timed out, see [`Connection::set_command_timeout`].
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    /// Returns the capabilities last announced by the server, without a round trip.
+    ///
+    /// The cache is filled from untagged `CAPABILITY` responses and from `CAPABILITY` response
+    /// codes, e.g. in the greeting or in the tagged `OK` of `LOGIN`. It is cleared when
+    /// `STARTTLS`, `LOGIN` or `AUTHENTICATE` is sent, because the server may announce different
+    /// capabilities afterwards. `None` means that the capabilities are not known.
+    pub fn cached_capabilities(&self) -> Option<&Capabilities> {
+        self.capabilities.as_ref()
+    }
+
+    /// Starts tracking a newly selected mailbox. A `SELECT` or `EXAMINE` deselects the current
+    /// mailbox even if it fails, see [`Session::select`].
+    fn begin_select(&mut self, name: &str) {
+        self.selected = self.track_selected.then(|| SelectedMailbox::new(name));
+    }
+
+    /// Returns the response code of the tagged `OK` that completed the last command, if the
+    /// server sent one. Examples are `READ-ONLY` after `EXAMINE`, `COPYUID` after `COPY` or
+    /// `HIGHESTMODSEQ` after a `STORE` with `CONDSTORE` enabled.
+    ///
+    /// Only the code of the last command sent is kept, so it has to be read before the next
+    /// command. Commands whose results are streamed, such as [`Session::fetch`] or
+    /// [`Session::list`], only complete once the stream is read to the end, and their code is
+    /// cleared if the stream is dropped before. Commands sent behind the scenes replace it as
+    /// well, e.g. the `NOOP` and `IDLE` of [`Session::idle_stream`] or the commands of other
+    /// callers sharing a [`SessionHandle`](crate::SessionHandle). After a
+    /// [`Pipeline`], it is the code of the command that was added last.
+    pub fn last_ok_code(&self) -> Option<&ResponseCode> {
+        self.last_ok_code.as_ref()
+    }
+
+    fn update_capabilities(&mut self, response: &ResponseData) {
+        use imap_proto::ResponseCode;
+
+        let caps = match response.parsed() {
+            // `ENABLED` responses are parsed as capabilities as well.
+            Response::Capabilities(caps) if !response.is_enabled() => caps,
+            Response::Data {
+                code: Some(ResponseCode::Capabilities(caps)),
+                ..
+            }
+            | Response::Done {
+                code: Some(ResponseCode::Capabilities(caps)),
+                ..
+            } => caps,
+            _ => return,
+        };
+        self.capabilities = Some(Capabilities::from_parsed(caps));
+    }
+
+    /// Fails once the server took longer than the command timeout to respond to a pending
+    /// command, which poisons the connection.
+    fn poll_deadline(&mut self, cx: &mut Context<'_>) -> Poll<io::Error> {
+        // Only commands are timed out, the server may stay silent otherwise, e.g. during `IDLE`.
+        if let (Some(timeout), false) = (self.command_timeout, self.pending.is_empty()) {
+            let deadline = self.deadline.get_or_insert_with(|| Timer::new(timeout));
+            if deadline.poll_unpin(cx).is_ready() {
+                self.deadline = None;
+                self.poisoned = true;
+                return Poll::Ready(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "timed out waiting for a response",
+                ));
+            }
+        }
+        Poll::Pending
+    }
+
+    /// See [`ImapStream::poll_literal_start`].
+    pub(crate) fn poll_literal_start(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<Option<u64>>> {
+        if self.poisoned {
+            // Reported by `poll_next`.
+            return Poll::Ready(Ok(None));
+        }
+        match self.stream.poll_literal_start(cx) {
+            Poll::Ready(res) => {
+                self.deadline = None;
+                Poll::Ready(res)
+            }
+            Poll::Pending => self.poll_deadline(cx).map(Err),
+        }
+    }
+
+    /// See [`ImapStream::poll_read_literal`].
+    pub(crate) fn poll_read_literal(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.poisoned {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "timed out waiting for a response",
+            )));
+        }
+        match self.stream.poll_read_literal(cx, buf) {
+            Poll::Ready(res) => {
+                self.deadline = None;
+                Poll::Ready(res)
+            }
+            Poll::Pending => self.poll_deadline(cx).map(Err),
         }
     }
 }
 
-fn validate_str(value: &str) -> Result<String> {
+impl<T: Read + Write + Unpin + fmt::Debug> Stream for Connection<T> {
+    type Item = io::Result<ResponseData>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        // Whatever the server sends now can not be told apart from the responses to the command
+        // that timed out, so the connection is treated as closed.
+        if this.poisoned {
+            return Poll::Ready(None);
+        }
+        let res = match Pin::new(&mut this.stream).poll_next(cx) {
+            Poll::Ready(res) => res,
+            Poll::Pending => return this.poll_deadline(cx).map(|err| Some(Err(err))),
+        };
+        this.deadline = None;
+        if !matches!(res, Some(Ok(_))) {
+            this.failed = true;
+        }
+        if let Some(Ok(response)) = &res {
+            this.update_capabilities(response);
+            if let Some(selected) = &mut this.selected {
+                selected.apply(response.parsed());
+            }
+            match response.parsed() {
+                Response::Data {
+                    status: imap_proto::Status::Bye,
+                    code,
+                    information,
+                } => {
+                    this.bye = Some(Error::bye(code.as_ref(), information.as_deref()));
+                }
+                Response::Done {
+                    tag,
+                    status,
+                    code,
+                    information,
+                } => {
+                    this.pending.retain(|pending| pending != tag);
+                    this.fetch_sets.retain(|fetch_set| &fetch_set.tag != tag);
+                    // Commands whose responses were not read, e.g. because their stream was
+                    // dropped, may complete after the last command was sent.
+                    if *status == imap_proto::Status::Ok && this.last_command.as_ref() == Some(tag)
+                    {
+                        this.last_ok_code =
+                            ResponseCode::from_status(code.as_ref(), information.as_deref()).0;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Poll::Ready(res)
+    }
+}
+
+/// Whether the server may change its capabilities after `command`, see [RFC 3501 section
+/// 7.2.1](https://tools.ietf.org/html/rfc3501#section-7.2.1).
+fn invalidates_capabilities(command: &str) -> bool {
+    let name = command.split(' ').next().unwrap_or_default();
+    ["STARTTLS", "LOGIN", "AUTHENTICATE"]
+        .iter()
+        .any(|c| c.eq_ignore_ascii_case(name))
+}
+
+/// The mailbox examined by [`Session::unselect`] on servers without `UNSELECT`.
+const UNSELECT_FALLBACK_MAILBOX: &str = "async-imap.unselect.nonexistent";
+
+/// The default number of bytes fetched at once by [`Session::download_message`].
+pub(crate) const DOWNLOAD_CHUNK_SIZE: u64 = 1024 * 1024;
+
+pub(crate) fn validate_str(value: &str) -> Result<String> {
     let quoted = quote!(value);
     if quoted.find('\n').is_some() {
         return Err(Error::Validate(ValidateError('\n')));
@@ -1473,6 +2291,17 @@ fn validate_str(value: &str) -> Result<String> {
     Ok(quoted)
 }
 
+/// Creates a [`Session`] on the given stream, for the tests of other modules.
+#[cfg(test)]
+macro_rules! mock_session {
+    ($s:expr) => {
+        $crate::client::Session::from_stream($s)
+    };
+}
+
+#[cfg(test)]
+pub(crate) use mock_session;
+
 #[cfg(test)]
 mod tests {
     use pretty_assertions::assert_eq;
@@ -1482,8 +2311,13 @@ mod tests {
     use super::*;
     use std::borrow::Cow;
     use std::future::Future;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+
+    use futures::TryStreamExt;
 
     use async_std::sync::{Arc, Mutex};
+    use imap_proto::types::{MessageSection, SectionPath};
     use imap_proto::Status;
 
     macro_rules! mock_client {
@@ -1492,12 +2326,6 @@ mod tests {
         };
     }
 
-    macro_rules! mock_session {
-        ($s:expr) => {
-            Session::new(mock_client!($s).conn)
-        };
-    }
-
     macro_rules! assert_eq_bytes {
         ($a:expr, $b:expr, $c:expr) => {
             assert_eq!(
@@ -1558,6 +2386,172 @@ mod tests {
         client.read_response().await.unwrap().unwrap();
     }
 
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn read_greeting_ok() {
+        let response = b"* OK [CAPABILITY IMAP4rev1 AUTH=PLAIN] Dovecot ready.\r\n".to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let Greeting::Ok {
+            mut client,
+            capabilities,
+            information,
+        } = client.read_greeting().await.unwrap()
+        else {
+            panic!("expected OK greeting");
+        };
+        assert!(capabilities.unwrap().has_str("AUTH=PLAIN"));
+        assert_eq!(information.as_deref(), Some("Dovecot ready."));
+
+        // Capabilities are taken from the greeting without sending CAPABILITY.
+        let capabilities = client.capabilities().await.unwrap();
+        assert!(capabilities.has(&Capability::Imap4rev1));
+        assert!(client.stream.inner.written_buf.is_empty());
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn read_greeting_ok_without_capabilities() {
+        let response = b"* OK IMAP4rev1 server ready\r\n\
+            * CAPABILITY IMAP4rev1 LOGINDISABLED\r\n\
+            A0001 OK CAPABILITY completed\r\n"
+            .to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let Greeting::Ok {
+            mut client,
+            capabilities,
+            ..
+        } = client.read_greeting().await.unwrap()
+        else {
+            panic!("expected OK greeting");
+        };
+        assert!(capabilities.is_none());
+
+        let capabilities = client.capabilities().await.unwrap();
+        assert!(capabilities.has_str("LOGINDISABLED"));
+        assert_eq!(
+            client.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\n".to_vec()
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn read_greeting_preauth() {
+        let response = b"* PREAUTH IMAP4rev1 server logged in as Smith\r\n\
+            A0001 OK NOOP completed\r\n"
+            .to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let Greeting::PreAuth {
+            mut session,
+            capabilities,
+            information,
+        } = client.read_greeting().await.unwrap()
+        else {
+            panic!("expected PREAUTH greeting");
+        };
+        assert!(capabilities.is_none());
+        assert_eq!(
+            information.as_deref(),
+            Some("IMAP4rev1 server logged in as Smith")
+        );
+        session.noop().await.unwrap();
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn read_greeting_bye() {
+        let response = b"* BYE Too many connections\r\n".to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let err = client.read_greeting().await.unwrap_err();
+        assert!(
+            matches!(&err, Error::Bye { code: None, text } if text == "Too many connections"),
+            "Unexpected error: {err:?}"
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn login_replaces_cached_capabilities() {
+        let response = b"* OK [CAPABILITY IMAP4rev1 STARTTLS LOGINDISABLED] ready\r\n\
+            A0001 OK [CAPABILITY IMAP4rev1 IDLE MOVE] Logged in\r\n"
+            .to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let Greeting::Ok { client, .. } = client.read_greeting().await.unwrap() else {
+            panic!("expected OK greeting");
+        };
+        assert!(client
+            .cached_capabilities()
+            .unwrap()
+            .has_str("LOGINDISABLED"));
+
+        let mut session = client.login("username", "password").await.unwrap();
+        let capabilities = session.cached_capabilities().unwrap();
+        assert!(capabilities.has_str("MOVE"));
+        assert!(!capabilities.has_str("LOGINDISABLED"));
+
+        // The cached capabilities are returned without sending CAPABILITY.
+        assert!(session.capabilities().await.unwrap().has_str("IDLE"));
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 LOGIN \"username\" \"password\"\r\n".to_vec()
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn login_invalidates_cached_capabilities() {
+        let response = b"* OK [CAPABILITY IMAP4rev1 AUTH=PLAIN] ready\r\n\
+            A0001 OK Logged in\r\n"
+            .to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let Greeting::Ok { client, .. } = client.read_greeting().await.unwrap() else {
+            panic!("expected OK greeting");
+        };
+        let session = client.login("username", "password").await.unwrap();
+        assert!(session.cached_capabilities().is_none());
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn starttls_invalidates_cached_capabilities() {
+        let response = b"* OK [CAPABILITY IMAP4rev1 STARTTLS] ready\r\n\
+            A0001 OK Begin TLS negotiation now\r\n"
+            .to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let Greeting::Ok { mut client, .. } = client.read_greeting().await.unwrap() else {
+            panic!("expected OK greeting");
+        };
+        client
+            .run_command_and_check_ok("STARTTLS", None)
+            .await
+            .unwrap();
+        assert!(client.cached_capabilities().is_none());
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn unsolicited_capabilities_update_cache() {
+        let response = b"* CAPABILITY IMAP4rev1 IDLE CONDSTORE\r\n\
+            A0001 OK NOOP completed\r\n\
+            * ENABLED CONDSTORE\r\n\
+            A0002 OK ENABLE completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert!(session.cached_capabilities().is_none());
+
+        session.noop().await.unwrap();
+        assert!(session.cached_capabilities().unwrap().has_str("CONDSTORE"));
+
+        // An ENABLED response must not replace the capabilities.
+        session
+            .run_command_and_check_ok("ENABLE CONDSTORE")
+            .await
+            .unwrap();
+        let capabilities = session.cached_capabilities().unwrap();
+        assert!(capabilities.has(&Capability::Imap4rev1));
+        assert!(capabilities.has_str("IDLE"));
+    }
+
     #[cfg_attr(feature = "runtime-tokio", tokio::test)]
     #[cfg_attr(feature = "runtime-async-std", async_std::test)]
     async fn authenticate() {
@@ -1590,6 +2584,105 @@ mod tests {
         );
     }
 
+    #[derive(Debug, thiserror::Error)]
+    #[error("token expired")]
+    struct TokenExpired;
+
+    struct AsyncAuth {
+        fail: bool,
+    }
+
+    impl AsyncAuthenticator for AsyncAuth {
+        type Response = Vec<u8>;
+        type Error = TokenExpired;
+
+        async fn process(
+            &mut self,
+            challenge: &[u8],
+        ) -> std::result::Result<Vec<u8>, TokenExpired> {
+            assert!(challenge == b"bar", "Invalid authenticate challenge");
+            if self.fail {
+                Err(TokenExpired)
+            } else {
+                Ok(b"foo".to_vec())
+            }
+        }
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn authenticate_async() {
+        let response = b"+ YmFy\r\n\
+                         A0001 OK Logged in\r\n"
+            .to_vec();
+        let command = "A0001 AUTHENTICATE XOAUTH2\r\n\
+                       Zm9v\r\n";
+        let mock_stream = MockStream::new(response);
+        let client = mock_client!(mock_stream);
+        let session = client
+            .authenticate_async("XOAUTH2", AsyncAuth { fail: false })
+            .await
+            .ok()
+            .unwrap();
+        assert_eq_bytes!(
+            &session.stream.inner.written_buf,
+            command.as_bytes(),
+            "Invalid authenticate command"
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn authenticate_async_aborted() {
+        let response = b"+ YmFy\r\n\
+                         A0001 BAD AUTHENTICATE cancelled\r\n"
+            .to_vec();
+        let command = "A0001 AUTHENTICATE XOAUTH2\r\n\
+                       *\r\n";
+        let mock_stream = MockStream::new(response);
+        let client = mock_client!(mock_stream);
+        let (err, client) = client
+            .authenticate_async("XOAUTH2", AsyncAuth { fail: true })
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            matches!(&err, Error::AuthenticationAborted(e) if e.is::<TokenExpired>()),
+            "Unexpected error: {err:?}"
+        );
+        assert_eq_bytes!(
+            &client.stream.inner.written_buf,
+            command.as_bytes(),
+            "Invalid authenticate command"
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn authenticate_invalid_challenge() {
+        let response = b"+ !!!\r\n\
+                         A0001 BAD AUTHENTICATE cancelled\r\n"
+            .to_vec();
+        let command = "A0001 AUTHENTICATE PLAIN\r\n\
+                       *\r\n";
+        let mock_stream = MockStream::new(response);
+        let client = mock_client!(mock_stream);
+        let (err, client) = client
+            .authenticate_async("PLAIN", AsyncAuth { fail: false })
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            matches!(err, Error::Parse(ParseError::Authentication(..))),
+            "Unexpected error: {err:?}"
+        );
+        assert_eq_bytes!(
+            &client.stream.inner.written_buf,
+            command.as_bytes(),
+            "Invalid authenticate command"
+        );
+    }
+
     #[cfg_attr(feature = "runtime-tokio", tokio::test)]
     #[cfg_attr(feature = "runtime-async-std", async_std::test)]
     async fn login() {
@@ -1610,6 +2703,300 @@ mod tests {
         }
     }
 
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn login_authentication_failed() {
+        let response = b"A0001 NO [AUTHENTICATIONFAILED] Invalid credentials\r\n".to_vec();
+        let client = mock_client!(MockStream::new(response));
+        let (err, _client) = client.login("username", "password").await.unwrap_err();
+        assert!(err.is_auth_failure());
+        assert!(!err.is_over_quota());
+        match err {
+            Error::No { code, text } => {
+                assert_eq!(code, Some(ResponseCode::AuthenticationFailed));
+                assert_eq!(text, "Invalid credentials");
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn append_over_quota() {
+        let response = b"+ OK\r\nA0001 NO [OVERQUOTA] Quota exceeded\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let err = session.append("INBOX", b"message").await.unwrap_err();
+        assert!(err.is_over_quota());
+        assert_eq!(err.to_string(), "no response: [OVERQUOTA] Quota exceeded");
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn bye_during_command() {
+        let response = b"* BYE [UNAVAILABLE] Server shutting down\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let err = session.noop().await.unwrap_err();
+        assert!(
+            matches!(&err, Error::Bye { code: Some(ResponseCode::Unavailable), text }
+                if text == "Server shutting down"),
+            "Unexpected error: {err:?}"
+        );
+        assert!(matches!(
+            session.unsolicited_responses.try_recv(),
+            Ok(UnsolicitedResponse::Bye {
+                code: Some(ResponseCode::Unavailable),
+                ..
+            })
+        ));
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn bye_during_fetch() {
+        let response = b"* 1 FETCH (UID 7)\r\n\
+            * BYE Server shutting down\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches: Vec<_> = session
+            .fetch("1", "UID")
+            .await
+            .unwrap()
+            .take(2)
+            .collect()
+            .await;
+        assert_eq!(fetches[0].as_ref().unwrap().uid, Some(7));
+        assert!(
+            matches!(&fetches[1], Err(Error::Bye { text, .. }) if text == "Server shutting down"),
+            "Unexpected result: {:?}",
+            fetches[1]
+        );
+
+        // Without a `BYE`, the end of the connection is reported as it is.
+        let mut session = mock_session!(MockStream::new(b"* 1 FETCH (UID 7)\r\n".to_vec()));
+        let fetches: Vec<_> = session
+            .fetch("1", "UID")
+            .await
+            .unwrap()
+            .take(2)
+            .collect()
+            .await;
+        assert!(matches!(&fetches[1], Err(Error::Io(err))
+            if err.kind() == io::ErrorKind::UnexpectedEof));
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn logout_with_bye() {
+        let response = b"* BYE Logging out\r\nA0001 OK Logout completed.\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.logout().await.unwrap();
+        assert!(matches!(
+            session.unsolicited_responses.try_recv(),
+            Ok(UnsolicitedResponse::Bye { code: None, text }) if text == "Logging out"
+        ));
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn idle_bye() {
+        let response = b"+ idling\r\n\
+            * BYE Autologout; idle for too long\r\n"
+            .to_vec();
+        let session = mock_session!(MockStream::new(response));
+        let mut handle = session.idle();
+        handle.init().await.unwrap();
+        let (idle, _stop) = handle.wait();
+        let err = idle.await.unwrap_err();
+        assert!(
+            matches!(&err, Error::Bye { text, .. } if text == "Autologout; idle for too long"),
+            "Unexpected error: {err:?}"
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn tagged_alert() {
+        let response = b"A0001 OK [ALERT] Password expires in 3 days\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.run_command_and_check_ok("CHECK").await.unwrap();
+        assert!(matches!(
+            session.unsolicited_responses.try_recv(),
+            Ok(UnsolicitedResponse::Alert(text)) if text == "Password expires in 3 days"
+        ));
+        assert_eq!(session.last_ok_code(), Some(&ResponseCode::Alert));
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn last_ok_code() {
+        let response = b"A0001 OK [COPYUID 38505 304,319:320 3956:3958] Done\r\n\
+            A0002 OK Noop completed\r\n\
+            A0004 OK Check completed\r\n\
+            A0003 OK [READ-ONLY] Examine completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.uid_copy("304,319:320", "Archive").await.unwrap();
+        assert_eq!(
+            session.last_ok_code(),
+            Some(&ResponseCode::CopyUid(
+                38505,
+                vec![UidSetMember::Uid(304), UidSetMember::UidRange(319..=320)],
+                vec![UidSetMember::UidRange(3956..=3958)]
+            ))
+        );
+
+        session.noop().await.unwrap();
+        assert_eq!(session.last_ok_code(), None);
+
+        // Only the completion of the last command counts.
+        session.conn.run_command("EXAMINE INBOX").await.unwrap();
+        session.conn.run_command("CHECK").await.unwrap();
+        session.read_response().await.unwrap().unwrap();
+        session.read_response().await.unwrap().unwrap();
+        assert_eq!(session.last_ok_code(), None);
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn unsolicited_overflow() {
+        let response = b"* 1 EXISTS\r\n\
+            * 2 EXISTS\r\n\
+            * 3 EXISTS\r\n\
+            A0001 OK Noop completed\r\n\
+            * 4 EXISTS\r\n\
+            * 1 EXPUNGE\r\n\
+            A0002 OK Noop completed\r\n\
+            * 5 EXISTS\r\n\
+            A0003 OK Noop completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.configure_unsolicited_responses(2, OverflowPolicy::Error);
+        assert!(matches!(
+            session.noop().await,
+            Err(Error::UnsolicitedOverflow)
+        ));
+        assert_eq!(session.unsolicited_overflow_count(), 1);
+
+        // Queued responses are kept when reconfiguring.
+        session.configure_unsolicited_responses(2, OverflowPolicy::DropOldest);
+        session.noop().await.unwrap();
+        assert_eq!(session.unsolicited_overflow_count(), 3);
+        assert_eq!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Exists(4)
+        );
+        assert_eq!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Expunge(1)
+        );
+
+        // Responses that do not fit into the new channel are counted.
+        for n in [6, 7] {
+            session
+                .unsolicited_responses_tx
+                .try_send(UnsolicitedResponse::Exists(n))
+                .unwrap();
+        }
+        session.configure_unsolicited_responses(0, OverflowPolicy::DropNewest);
+        assert_eq!(session.unsolicited_overflow_count(), 4);
+        assert_eq!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Exists(6)
+        );
+
+        // A dropped receiver does not fail the session.
+        let (_, rx) = async_channel::bounded(1);
+        drop(std::mem::replace(&mut session.unsolicited_responses, rx));
+        session.noop().await.unwrap();
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn unsolicited_handler() {
+        let response = b"* 3 EXISTS\r\n\
+            A0001 OK Noop completed\r\n\
+            * 4 EXISTS\r\n\
+            A0002 OK Noop completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let exists = Arc::new(AtomicU32::new(0));
+        let handler_exists = exists.clone();
+        session.set_unsolicited_handler(move |response| {
+            if let UnsolicitedResponse::Exists(n) = response {
+                handler_exists.store(n, Ordering::Relaxed);
+            }
+        });
+        session.noop().await.unwrap();
+        assert_eq!(exists.load(Ordering::Relaxed), 3);
+        assert!(session.unsolicited_responses.is_empty());
+
+        session.remove_unsolicited_handler();
+        session.noop().await.unwrap();
+        assert_eq!(exists.load(Ordering::Relaxed), 3);
+        assert_eq!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Exists(4)
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn track_selected_mailbox() {
+        let response = b"* 3 EXISTS\r\n\
+            * OK [UIDVALIDITY 100] UIDs valid\r\n\
+            * OK [UIDNEXT 13] Predicted next UID\r\n\
+            A0001 OK [READ-WRITE] Select completed.\r\n\
+            * 1 FETCH (UID 10 FLAGS (\\Seen))\r\n\
+            * 2 FETCH (UID 11 FLAGS ())\r\n\
+            * 3 FETCH (UID 12 FLAGS ())\r\n\
+            A0002 OK Fetch completed.\r\n\
+            * 2 EXPUNGE\r\n\
+            * 3 EXISTS\r\n\
+            * 3 FETCH (UID 13 FLAGS ())\r\n\
+            * 1 FETCH (FLAGS (\\Seen \\Flagged))\r\n\
+            A0003 OK Noop completed.\r\n\
+            A0004 OK Close completed.\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.set_track_selected_mailbox(true);
+        session.select("INBOX").await.unwrap();
+        let fetches: Vec<_> = session
+            .fetch("1:*", "(UID FLAGS)")
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(fetches.len(), 3);
+
+        let snapshot = session.selected_mailbox().unwrap().clone();
+        assert_eq!(snapshot.name(), "INBOX");
+        assert_eq!(snapshot.exists(), 3);
+        assert_eq!(snapshot.uid(2), Some(11));
+
+        session.noop().await.unwrap();
+        let mailbox = session.selected_mailbox().unwrap();
+        assert_eq!(mailbox.exists(), 3);
+        assert_eq!(mailbox.uid_validity(), Some(100));
+        assert_eq!(mailbox.uid_next(), Some(14));
+        assert_eq!(mailbox.uid(2), Some(12));
+        assert_eq!(mailbox.seq(13), Some(3));
+        assert_eq!(
+            mailbox.message(1).unwrap().flags,
+            Some(vec![Flag::Seen, Flag::Flagged])
+        );
+        assert_eq!(
+            mailbox.diff(&snapshot),
+            MailboxDiff {
+                added: vec![13],
+                removed: vec![11],
+                flags_changed: vec![10],
+            }
+        );
+
+        session.close().await.unwrap();
+        assert!(session.selected_mailbox().is_none());
+    }
+
     #[cfg_attr(feature = "runtime-tokio", tokio::test)]
     #[cfg_attr(feature = "runtime-async-std", async_std::test)]
     async fn logout() {
@@ -1752,6 +3139,10 @@ mod tests {
             uid_next: Some(2),
             uid_validity: Some(1257842737),
             highest_modseq: None,
+            access_mode: Some(AccessMode::ReadOnly),
+            uid_not_sticky: false,
+            no_modseq: false,
+            mailbox_id: None,
         };
         let mailbox_name = "INBOX";
         let command = format!("A0001 EXAMINE {}\r\n", quote!(mailbox_name));
@@ -1801,6 +3192,10 @@ mod tests {
             uid_next: Some(2),
             uid_validity: Some(1257842737),
             highest_modseq: Some(90060115205545359),
+            access_mode: Some(AccessMode::ReadOnly),
+            uid_not_sticky: false,
+            no_modseq: false,
+            mailbox_id: None,
         };
         let mailbox_name = "INBOX";
         let command = format!("A0001 SELECT {}\r\n", quote!(mailbox_name));
@@ -1942,6 +3337,33 @@ mod tests {
         );
     }
 
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn unselect() {
+        let response = b"* CAPABILITY IMAP4rev1 UNSELECT\r\n\
+            A0001 OK Capability completed\r\n\
+            A0002 OK Unselect completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.unselect().await.unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\nA0002 UNSELECT\r\n".to_vec()
+        );
+
+        // Without `UNSELECT`, a nonexistent mailbox is examined instead.
+        let response = b"* CAPABILITY IMAP4rev1\r\n\
+            A0001 OK Capability completed\r\n\
+            A0002 NO Mailbox does not exist\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.unselect().await.unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\nA0002 EXAMINE \"async-imap.unselect.nonexistent\"\r\n".to_vec()
+        );
+    }
+
     #[cfg_attr(feature = "runtime-tokio", tokio::test)]
     #[cfg_attr(feature = "runtime-async-std", async_std::test)]
     async fn store() {
@@ -2464,4 +3886,252 @@ mod tests {
             assert_eq!(metadata[1].value, None);
         }
     }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn command_timeout() {
+        let response = b"* 1 FETCH (FLAGS ())\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response).with_stall());
+        session.set_command_timeout(Some(Duration::from_millis(10)));
+
+        let fetches: Vec<_> = session.fetch("1:2", "FLAGS").await.unwrap().collect().await;
+        assert_eq!(fetches.len(), 2);
+        assert!(fetches[0].is_ok());
+        assert!(matches!(
+            &fetches[1],
+            Err(Error::Io(err)) if err.kind() == io::ErrorKind::TimedOut
+        ));
+
+        assert!(session.is_poisoned());
+        assert!(matches!(session.noop().await, Err(Error::Poisoned)));
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 FETCH 1:2 FLAGS\r\n".to_vec()
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn dropped_stream_is_drained() {
+        let response = b"* 1 FETCH (UID 1)\r\n\
+            * 2 FETCH (UID 2)\r\n\
+            * 3 EXISTS\r\n\
+            A0001 OK Fetch completed\r\n\
+            A0002 OK Noop completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        {
+            let mut fetches = session.fetch("1:*", "UID").await.unwrap();
+            assert_eq!(fetches.next().await.unwrap().unwrap().uid, Some(1));
+        }
+
+        session.noop().await.unwrap();
+        assert_eq!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Exists(3)
+        );
+        assert!(session.unsolicited_responses.is_empty());
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn dropped_stream_keeps_unsolicited_fetches() {
+        let response = b"* 1 FETCH (UID 11 FLAGS ())\r\n\
+            * 7 FETCH (FLAGS (\\Deleted))\r\n\
+            * 2 FETCH (UID 12 FLAGS ())\r\n\
+            * 3 FETCH (UID 20 FLAGS (\\Seen))\r\n\
+            A0001 OK Fetch completed\r\n\
+            A0002 OK Noop completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        {
+            let mut fetches = session.uid_fetch("11:12", "FLAGS").await.unwrap();
+            assert_eq!(fetches.next().await.unwrap().unwrap().uid, Some(11));
+        }
+
+        session.noop().await.unwrap();
+        for seq in [7, 3] {
+            match session.unsolicited_responses.try_recv().unwrap() {
+                UnsolicitedResponse::Other(response) => {
+                    assert!(matches!(response.parsed(), Response::Fetch(n, _) if *n == seq))
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert!(session.unsolicited_responses.is_empty());
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn download_message() {
+        let response = b"* 2 FETCH (UID 7 RFC822.SIZE 12)\r\n\
+            A0001 OK Fetch completed\r\n\
+            * 2 FETCH (UID 7 BODY[]<0> {12}\r\nHello world!)\r\n\
+            A0002 OK Fetch completed\r\n\
+            * 2 FETCH (UID 7 RFC822.SIZE 12)\r\n\
+            A0003 OK Fetch completed\r\n\
+            * 2 FETCH (UID 7 BODY[]<6> {6}\r\nworld!)\r\n\
+            A0004 OK Fetch completed\r\n\
+            * 2 FETCH (UID 7 RFC822.SIZE 20)\r\n\
+            A0005 OK Fetch completed\r\n\
+            * 2 FETCH (UID 7 BODY[]<0> {12}\r\nHello world!)\r\n\
+            A0006 OK Fetch completed\r\n\
+            * 2 FETCH (UID 7 BODY[]<12> \"\")\r\n\
+            A0007 OK Fetch completed\r\n\
+            A0008 OK Fetch completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+
+        let mut message = Vec::new();
+        assert_eq!(session.download_message(7, &mut message).await.unwrap(), 12);
+        assert_eq!(message, b"Hello world!");
+
+        let mut rest = Vec::new();
+        assert_eq!(session.resume_download(7, 6, &mut rest).await.unwrap(), 12);
+        assert_eq!(rest, b"world!");
+
+        assert!(matches!(
+            session.download_message(7, Vec::new()).await,
+            Err(Error::SizeMismatch {
+                expected: 20,
+                actual: 12
+            })
+        ));
+        assert!(matches!(
+            session.download_message(8, Vec::new()).await,
+            Err(Error::Parse(ParseError::ExpectedResponseNotFound(_)))
+        ));
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 UID FETCH 7 (UID RFC822.SIZE)\r\n\
+            A0002 UID FETCH 7 (UID BODY.PEEK[]<0.1048576>)\r\n\
+            A0003 UID FETCH 7 (UID RFC822.SIZE)\r\n\
+            A0004 UID FETCH 7 (UID BODY.PEEK[]<6.1048576>)\r\n\
+            A0005 UID FETCH 7 (UID RFC822.SIZE)\r\n\
+            A0006 UID FETCH 7 (UID BODY.PEEK[]<0.1048576>)\r\n\
+            A0007 UID FETCH 7 (UID BODY.PEEK[]<12.1048576>)\r\n\
+            A0008 UID FETCH 8 (UID RFC822.SIZE)\r\n"
+                .to_vec()
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn download_chunk_size() {
+        let response = b"* 2 FETCH (UID 7 RFC822.SIZE 12)\r\n\
+            A0001 OK Fetch completed\r\n\
+            * 2 FETCH (UID 7 BODY[]<0> {8}\r\nHello wo)\r\n\
+            A0002 OK Fetch completed\r\n\
+            * 2 FETCH (UID 7 BODY[]<8> {4}\r\nrld!)\r\n\
+            A0003 OK Fetch completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.set_download_chunk_size(8);
+        assert_eq!(session.download_chunk_size(), 8);
+
+        let mut message = Vec::new();
+        assert_eq!(session.download_message(7, &mut message).await.unwrap(), 12);
+        assert_eq!(message, b"Hello world!");
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 UID FETCH 7 (UID RFC822.SIZE)\r\n\
+            A0002 UID FETCH 7 (UID BODY.PEEK[]<0.8>)\r\n\
+            A0003 UID FETCH 7 (UID BODY.PEEK[]<8.8>)\r\n"
+                .to_vec()
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn binary() {
+        let response =
+            b"* 2 FETCH (UID 7 BINARY.SIZE[2] 5 BINARY[2] ~{5}\r\n\0\xff)\r\n FLAGS ())\r\n\
+            A0001 OK Fetch completed\r\n\
+            + Ready for literal data\r\n\
+            A0002 OK Append completed\r\n"
+                .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+
+        let fetches: Vec<_> = session
+            .uid_fetch("7", "(UID BINARY.SIZE[2] BINARY.PEEK[2] FLAGS)")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(fetches[0].uid, Some(7));
+        assert_eq!(fetches[0].flags().count(), 0);
+        assert_eq!(fetches[0].binary(&[2]), Some(&b"\0\xff)\r\n"[..]));
+        assert_eq!(fetches[0].binary_size(&[2]), Some(5));
+        assert_eq!(fetches[0].binary(&[1]), None);
+        assert_eq!(fetches[0].body(), None);
+
+        session.append_binary("INBOX", b"\0\xff").await.unwrap();
+
+        // A response with nothing but the `BINARY` item.
+        let response = b"* 1 FETCH (BINARY[1] ~{3}\r\nab\0)\r\n\
+            A0001 OK Fetch completed\r\n"
+            .to_vec();
+        let mut other = mock_session!(MockStream::new(response));
+        let fetches: Vec<_> = other
+            .fetch("1", "BINARY.PEEK[1]")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(fetches[0].message, 1);
+        assert_eq!(fetches[0].binary(&[1]), Some(&b"ab\0"[..]));
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 UID FETCH 7 (UID BINARY.SIZE[2] BINARY.PEEK[2] FLAGS)\r\n\
+            A0002 APPEND \"INBOX\" ~{2}\r\n\0\xff\r\n"
+                .to_vec()
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn decoded_section() {
+        let response = b"* 2 FETCH (UID 7 BODYSTRUCTURE (\
+            (\"TEXT\" \"PLAIN\" (\"CHARSET\" \"ISO-8859-1\") NIL NIL \"QUOTED-PRINTABLE\" 16 1 NIL NIL NIL NIL)\
+            (\"TEXT\" \"HTML\" (\"CHARSET\" \"UTF-8\") NIL NIL \"BASE64\" 16 1 NIL NIL NIL NIL) \
+            \"ALTERNATIVE\" (\"BOUNDARY\" \"b\") NIL NIL NIL) \
+            BODY[1] {17}\r\nCaf=E9 cr=E8me=\r\n BINARY[2] {9}\r\n<b>\xc3\xa9</b>)\r\n\
+            A0001 OK Fetch completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+
+        let fetches: Vec<_> = session
+            .uid_fetch("7", "(UID BODYSTRUCTURE BODY.PEEK[1] BINARY.PEEK[2])")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let structure = fetches[0].message_structure().unwrap();
+        let text = structure.text_part().unwrap();
+        assert_eq!(
+            fetches[0]
+                .decoded_section(&text.section_path(), &structure)
+                .unwrap()
+                .as_deref(),
+            Some("Café crème")
+        );
+        let html = structure.html_part().unwrap();
+        assert_eq!(
+            fetches[0]
+                .decoded_section(&html.section_path(), &structure)
+                .unwrap()
+                .as_deref(),
+            Some("<b>é</b>")
+        );
+        let header = SectionPath::Part(vec![1], Some(MessageSection::Header));
+        assert_eq!(
+            fetches[0].decoded_section(&header, &structure).unwrap(),
+            None
+        );
+    }
 }
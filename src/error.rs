@@ -5,6 +5,8 @@ use std::str::Utf8Error;
 
 use base64::DecodeError;
 
+use crate::types::ResponseCode;
+
 /// A convenience wrapper around `Result` for `imap::Error`.
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -14,13 +16,32 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     /// An `io::Error` that occurred while trying to read or write to a network stream.
     #[error("io: {0}")]
-    Io(#[from] IoError),
+    Io(#[source] IoError),
     /// A BAD response from the IMAP server.
-    #[error("bad response: {0}")]
-    Bad(String),
+    #[error("bad response: {}{text}", code_prefix(.code))]
+    Bad {
+        /// The response code, if the server sent one.
+        code: Option<ResponseCode>,
+        /// Human-readable text sent along with `BAD`.
+        text: String,
+    },
     /// A NO response from the IMAP server.
-    #[error("no response: {0}")]
-    No(String),
+    #[error("no response: {}{text}", code_prefix(.code))]
+    No {
+        /// The response code, if the server sent one.
+        code: Option<ResponseCode>,
+        /// Human-readable text sent along with `NO`.
+        text: String,
+    },
+    /// The server closed the connection with a `BYE` response, e.g. because of an inactivity
+    /// timeout or a shutdown.
+    #[error("server closed the connection: {}{text}", code_prefix(.code))]
+    Bye {
+        /// The response code, if the server sent one.
+        code: Option<ResponseCode>,
+        /// Human-readable text sent along with `BYE`.
+        text: String,
+    },
     /// The connection was terminated unexpectedly.
     #[error("connection lost")]
     ConnectionLost,
@@ -34,6 +55,119 @@ pub enum Error {
     /// Error appending an e-mail.
     #[error("could not append mail to mailbox")]
     Append,
+    /// An unsolicited response could not be queued because the channel was full, see
+    /// [`OverflowPolicy::Error`](crate::OverflowPolicy::Error).
+    #[error("unsolicited responses channel is full")]
+    UnsolicitedOverflow,
+    /// The [`AsyncAuthenticator`](crate::AsyncAuthenticator) failed to answer a server challenge
+    /// and the authentication exchange was cancelled.
+    #[error("authentication aborted: {0}")]
+    AuthenticationAborted(#[source] Box<dyn std::error::Error + Send + Sync>),
+    /// A previous command timed out, so the responses of the server can no longer be matched
+    /// to the commands. See [`Connection::set_command_timeout`](crate::Connection::set_command_timeout).
+    #[error("connection is unusable after a command timed out")]
+    Poisoned,
+    /// The commands of a [`Pipeline`](crate::Pipeline) can not be sent at once, because their
+    /// results would depend on the order in which the server executes them.
+    #[error("ambiguous pipeline: {0}")]
+    Pipeline(String),
+    /// A message had a different size than the server announced, see
+    /// [`Session::download_message`](crate::Session::download_message).
+    #[error("message size mismatch: expected {expected} bytes, got {actual}")]
+    SizeMismatch {
+        /// The `RFC822.SIZE` of the message.
+        expected: u64,
+        /// The number of bytes received.
+        actual: u64,
+    },
+    /// A fetched body section could not be decoded, e.g. because of an unknown charset, see
+    /// [`Fetch::decoded_section`](crate::types::Fetch::decoded_section).
+    #[error("decode: {0}")]
+    Decode(String),
+}
+
+impl From<IoError> for Error {
+    fn from(err: IoError) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl Error {
+    pub(crate) fn bad(
+        code: Option<&imap_proto::ResponseCode<'_>>,
+        information: Option<&str>,
+    ) -> Self {
+        let (code, text) = ResponseCode::from_status(code, information);
+        Error::Bad { code, text }
+    }
+
+    pub(crate) fn no(
+        code: Option<&imap_proto::ResponseCode<'_>>,
+        information: Option<&str>,
+    ) -> Self {
+        let (code, text) = ResponseCode::from_status(code, information);
+        Error::No { code, text }
+    }
+
+    pub(crate) fn bye(
+        code: Option<&imap_proto::ResponseCode<'_>>,
+        information: Option<&str>,
+    ) -> Self {
+        let (code, text) = ResponseCode::from_status(code, information);
+        Error::Bye { code, text }
+    }
+
+    /// The response code of a `NO`, `BAD` or `BYE` response, if the server sent one.
+    pub fn code(&self) -> Option<&ResponseCode> {
+        match self {
+            Error::Bad { code, .. } | Error::No { code, .. } | Error::Bye { code, .. } => {
+                code.as_ref()
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the server rejected the credentials, or they may not be used.
+    ///
+    /// This is the case for the `AUTHENTICATIONFAILED`, `AUTHORIZATIONFAILED` and `EXPIRED`
+    /// response codes of [RFC 5530](https://tools.ietf.org/html/rfc5530).
+    pub fn is_auth_failure(&self) -> bool {
+        matches!(
+            self.code(),
+            Some(
+                ResponseCode::AuthenticationFailed
+                    | ResponseCode::AuthorizationFailed
+                    | ResponseCode::Expired
+            )
+        )
+    }
+
+    /// Whether the command failed because the user is over quota (`OVERQUOTA`).
+    pub fn is_over_quota(&self) -> bool {
+        matches!(self.code(), Some(ResponseCode::OverQuota))
+    }
+
+    /// Whether the target mailbox does not exist, but could be created (`TRYCREATE`).
+    pub fn is_try_create(&self) -> bool {
+        matches!(self.code(), Some(ResponseCode::TryCreate))
+    }
+
+    /// Whether the mailbox or message does not exist (`NONEXISTENT`).
+    pub fn is_nonexistent(&self) -> bool {
+        matches!(self.code(), Some(ResponseCode::NonExistent))
+    }
+
+    /// Whether the mailbox to be created already exists (`ALREADYEXISTS`).
+    pub fn is_already_exists(&self) -> bool {
+        matches!(self.code(), Some(ResponseCode::AlreadyExists))
+    }
+}
+
+fn code_prefix(code: &Option<ResponseCode>) -> String {
+    match code {
+        Some(code) => format!("[{}] ", code.name()),
+        None => String::new(),
+    }
 }
 
 /// An error occured while trying to parse a server response.
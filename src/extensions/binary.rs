@@ -0,0 +1,390 @@
+//! Adds support for the IMAP BINARY extension specified in [RFC 3516](https://tools.ietf.org/html/rfc3516).
+//!
+//! The parser does not know the `BINARY[<section>]` and `BINARY.SIZE[<section>]` items of `FETCH`
+//! responses, nor the `~{<n>}` literals that contain binary data. They are taken out of the
+//! response before it is parsed, see [`extract`], and are available through
+//! [`Fetch::binary`](crate::types::Fetch::binary) and
+//! [`Fetch::binary_size`](crate::types::Fetch::binary_size).
+
+/// A `BINARY` item of a `FETCH` response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum BinaryAttribute {
+    /// `BINARY[<section>]`: the content of a section without its content transfer encoding, or
+    /// `None` for `NIL`.
+    Section {
+        section: Vec<u32>,
+        data: Option<Vec<u8>>,
+    },
+    /// `BINARY.SIZE[<section>]`: the size of the section without its content transfer encoding.
+    Size { section: Vec<u32>, size: u64 },
+}
+
+/// The result of [`extract`].
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Extracted {
+    /// A `FETCH` response with `BINARY` items, which ends after `len` bytes. `text` is the
+    /// response without these items, or `None` if there are no other items, as the parser does
+    /// not accept an empty list. `seq` is the sequence number of the message.
+    Binary {
+        seq: u32,
+        text: Option<Vec<u8>>,
+        len: usize,
+        attributes: Vec<BinaryAttribute>,
+    },
+    /// The response is incomplete, at least `needed` bytes are needed in total.
+    Incomplete(usize),
+    /// Not a `FETCH` response with `BINARY` items, or a malformed one. It is left to the parser.
+    None,
+}
+
+/// Takes the `BINARY` items out of a `FETCH` response at the start of `buf`.
+pub(crate) fn extract(buf: &[u8]) -> Extracted {
+    let mut scanner = Scanner { buf, pos: 0 };
+    // Other responses are left to the parser without scanning them.
+    let Ok(seq) = scanner.fetch_start() else {
+        return Extracted::None;
+    };
+    if !has_binary_item(buf) {
+        return Extracted::None;
+    }
+    match scanner.fetch_items() {
+        Ok((_, attributes)) if attributes.is_empty() => Extracted::None,
+        Ok((text, attributes)) => Extracted::Binary {
+            seq,
+            text,
+            len: scanner.pos,
+            attributes,
+        },
+        Err(Stop::Incomplete(needed)) => Extracted::Incomplete(needed),
+        Err(Stop::Invalid) => Extracted::None,
+    }
+}
+
+enum Stop {
+    /// At least this many bytes are needed in total.
+    Incomplete(usize),
+    Invalid,
+}
+
+type Scan<T> = std::result::Result<T, Stop>;
+
+/// Splits a response into its tokens, only as far as needed to find the `BINARY` items.
+struct Scanner<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    /// The start of a `FETCH` response up to its list of items, returns the sequence number.
+    fn fetch_start(&mut self) -> Scan<u32> {
+        self.tag(b"* ")?;
+        let seq = u32::try_from(self.number()?).map_err(|_| Stop::Invalid)?;
+        self.tag(b" FETCH (")?;
+        Ok(seq)
+    }
+
+    /// Returns the response without the `BINARY` items, if any other items are left, and the
+    /// items.
+    fn fetch_items(&mut self) -> Scan<(Option<Vec<u8>>, Vec<BinaryAttribute>)> {
+        let mut text = self.buf[..self.pos].to_vec();
+        let mut attributes = Vec::new();
+        let mut first = true;
+        let mut kept = false;
+        while self.peek()? != b')' {
+            if !first {
+                self.tag(b" ")?;
+            }
+            first = false;
+            let start = self.pos;
+            let name = self.atom()?;
+            self.tag(b" ")?;
+            if let Some(section) = binary_section(name, b"BINARY[") {
+                let data = match self.peek()? {
+                    b'N' | b'n' => {
+                        self.tag(b"NIL")?;
+                        None
+                    }
+                    b'"' => Some(self.quoted()?),
+                    _ => Some(self.literal()?.to_vec()),
+                };
+                attributes.push(BinaryAttribute::Section { section, data });
+            } else if let Some(section) = binary_section(name, b"BINARY.SIZE[") {
+                let size = self.number()?;
+                attributes.push(BinaryAttribute::Size { section, size });
+            } else {
+                self.skip_value()?;
+                if kept {
+                    text.push(b' ');
+                }
+                kept = true;
+                text.extend_from_slice(&self.buf[start..self.pos]);
+            }
+        }
+        self.tag(b")\r\n")?;
+        text.extend_from_slice(b")\r\n");
+        Ok((kept.then_some(text), attributes))
+    }
+
+    fn peek(&self) -> Scan<u8> {
+        self.buf
+            .get(self.pos)
+            .copied()
+            .ok_or(Stop::Incomplete(self.pos + 1))
+    }
+
+    /// Consumes `tag`, ignoring the case of letters.
+    fn tag(&mut self, tag: &[u8]) -> Scan<()> {
+        for expected in tag {
+            if !self.peek()?.eq_ignore_ascii_case(expected) {
+                return Err(Stop::Invalid);
+            }
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    fn number(&mut self) -> Scan<u64> {
+        let start = self.pos;
+        while self.peek()?.is_ascii_digit() {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.buf[start..self.pos])
+            .ok()
+            .and_then(|digits| digits.parse().ok())
+            .ok_or(Stop::Invalid)
+    }
+
+    /// An atom, which may contain a bracketed section with spaces, e.g.
+    /// `BODY[HEADER.FIELDS (DATE FROM)]<0>`.
+    fn atom(&mut self) -> Scan<&'a [u8]> {
+        let start = self.pos;
+        let mut brackets = 0;
+        loop {
+            match self.peek()? {
+                b'[' => brackets += 1,
+                b']' if brackets > 0 => brackets -= 1,
+                b'"' if brackets > 0 => {
+                    self.quoted()?;
+                    continue;
+                }
+                b' ' | b')' if brackets == 0 => break,
+                b'{' | b'(' | b'"' if brackets == 0 => return Err(Stop::Invalid),
+                b'\r' | b'\n' => return Err(Stop::Invalid),
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Err(Stop::Invalid);
+        }
+        Ok(&self.buf[start..self.pos])
+    }
+
+    /// A quoted string, returned without quotes and escapes.
+    fn quoted(&mut self) -> Scan<Vec<u8>> {
+        self.tag(b"\"")?;
+        let mut string = Vec::new();
+        loop {
+            match self.peek()? {
+                b'"' => break,
+                b'\\' => {
+                    self.pos += 1;
+                    string.push(self.peek()?);
+                }
+                b'\r' | b'\n' => return Err(Stop::Invalid),
+                byte => string.push(byte),
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        Ok(string)
+    }
+
+    /// A literal `{<n>}` or a literal8 `~{<n>}`.
+    fn literal(&mut self) -> Scan<&'a [u8]> {
+        if self.peek()? == b'~' {
+            self.pos += 1;
+        }
+        self.tag(b"{")?;
+        let len = usize::try_from(self.number()?).map_err(|_| Stop::Invalid)?;
+        self.tag(b"}\r\n")?;
+        let start = self.pos;
+        let end = start.checked_add(len).ok_or(Stop::Invalid)?;
+        if end > self.buf.len() {
+            return Err(Stop::Incomplete(end));
+        }
+        self.pos = end;
+        Ok(&self.buf[start..end])
+    }
+
+    /// Skips a value of any kind.
+    fn skip_value(&mut self) -> Scan<()> {
+        match self.peek()? {
+            b'(' => {
+                self.pos += 1;
+                while self.peek()? != b')' {
+                    // Lists of lists, such as the addresses of an envelope, have no spaces.
+                    if self.peek()? == b' ' {
+                        self.pos += 1;
+                    }
+                    self.skip_value()?;
+                }
+                self.pos += 1;
+            }
+            b'"' => {
+                self.quoted()?;
+            }
+            b'{' | b'~' => {
+                self.literal()?;
+            }
+            _ => {
+                self.atom()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether the response at the start of `buf` names a `BINARY` item. Only the text of the
+/// response is searched: literals are skipped, and the search ends with the first line that does
+/// not announce one.
+fn has_binary_item(buf: &[u8]) -> bool {
+    let names_item = |text: &[u8]| {
+        text.windows(8).any(|word| {
+            matches!(word[0], b' ' | b'(')
+                && (word[1..].eq_ignore_ascii_case(b"BINARY[")
+                    || word[1..].eq_ignore_ascii_case(b"BINARY."))
+        })
+    };
+    let mut pos = 0;
+    while let Some(rest) = buf.get(pos..) {
+        let Some(end) = rest.windows(2).position(|crlf| crlf == b"\r\n") else {
+            // The response is incomplete, the rest of it is searched once it arrived.
+            return names_item(rest);
+        };
+        let line = &rest[..end];
+        if names_item(line) {
+            return true;
+        }
+        let Some(len) = literal_len(line) else {
+            return false;
+        };
+        pos = pos.saturating_add(end + 2).saturating_add(len);
+    }
+    false
+}
+
+/// The length of the literal announced at the end of `line`, if any.
+fn literal_len(line: &[u8]) -> Option<usize> {
+    let line = line.strip_suffix(b"}")?;
+    let start = line.iter().rposition(|&b| b == b'{')? + 1;
+    std::str::from_utf8(&line[start..]).ok()?.parse().ok()
+}
+
+/// Parses the part numbers of `name` if it is `<prefix><section>]`, optionally followed by the
+/// origin of a partial fetch.
+fn binary_section(name: &[u8], prefix: &[u8]) -> Option<Vec<u32>> {
+    if name.len() < prefix.len() || !name[..prefix.len()].eq_ignore_ascii_case(prefix) {
+        return None;
+    }
+    let rest = &name[prefix.len()..];
+    let end = rest.iter().position(|&b| b == b']')?;
+    let origin = &rest[end + 1..];
+    let partial = origin
+        .strip_prefix(b"<")
+        .and_then(|origin| origin.strip_suffix(b">"))
+        .is_some_and(|offset| offset.iter().all(u8::is_ascii_digit));
+    if !origin.is_empty() && !partial {
+        return None;
+    }
+    let section = std::str::from_utf8(&rest[..end]).ok()?;
+    if section.is_empty() {
+        return Some(Vec::new());
+    }
+    section.split('.').map(|part| part.parse().ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_binary() {
+        let response = b"* 3 FETCH (UID 7 BINARY[1.2] ~{4}\r\n\0\x01\r\n \
+            BINARY.SIZE[1.2] 4 BODY[HEADER.FIELDS (SUBJECT)] {11}\r\nSubject: x\n \
+            BINARY[] \"a\\\"b\" BINARY[3]<0> NIL FLAGS (\\Seen) X-LIST ((1)(2)))\r\nA0001 OK";
+        let len = response.len() - b"A0001 OK".len();
+        assert_eq!(
+            extract(response),
+            Extracted::Binary {
+                seq: 3,
+                text: Some(
+                    b"* 3 FETCH (UID 7 BODY[HEADER.FIELDS (SUBJECT)] {11}\r\nSubject: x\n \
+                    FLAGS (\\Seen) X-LIST ((1)(2)))\r\n"
+                        .to_vec()
+                ),
+                len,
+                attributes: vec![
+                    BinaryAttribute::Section {
+                        section: vec![1, 2],
+                        data: Some(b"\0\x01\r\n".to_vec()),
+                    },
+                    BinaryAttribute::Size {
+                        section: vec![1, 2],
+                        size: 4,
+                    },
+                    BinaryAttribute::Section {
+                        section: vec![],
+                        data: Some(b"a\"b".to_vec()),
+                    },
+                    BinaryAttribute::Section {
+                        section: vec![3],
+                        data: None,
+                    },
+                ],
+            }
+        );
+
+        // Only `BINARY` items.
+        assert_eq!(
+            extract(b"* 4 FETCH (BINARY[1] {2}\r\nab)\r\n"),
+            Extracted::Binary {
+                seq: 4,
+                text: None,
+                len: 31,
+                attributes: vec![BinaryAttribute::Section {
+                    section: vec![1],
+                    data: Some(b"ab".to_vec()),
+                }],
+            }
+        );
+
+        assert_eq!(
+            extract(b"* 3 FETCH (BINARY[1] ~{10}\r\n\0\0"),
+            Extracted::Incomplete(38)
+        );
+        assert_eq!(extract(b"* 3 FETCH (UID 7)\r\n"), Extracted::None);
+        assert_eq!(extract(b"* 3 FETCH (BODY[] {5}\r\nBINAR"), Extracted::None);
+        assert_eq!(extract(b"* 3 EXISTS\r\n"), Extracted::None);
+        assert_eq!(extract(b"A0001 OK done\r\n"), Extracted::None);
+    }
+
+    #[test]
+    fn binary_item_search() {
+        assert!(has_binary_item(
+            b"* 3 FETCH (UID 7 BINARY[1] {2}\r\nab)\r\n"
+        ));
+        assert!(has_binary_item(
+            b"* 3 FETCH (BODY[] {3}\r\nabc BINARY.SIZE[1] 2)\r\n"
+        ));
+        assert!(has_binary_item(b"* 3 FETCH (UID 7 binary[1]"));
+        // Literals and later responses are not searched.
+        assert!(!has_binary_item(
+            b"* 3 FETCH (BODY[] {10}\r\n BINARY[1] FLAGS ())\r\n"
+        ));
+        assert!(!has_binary_item(
+            b"* 3 FETCH (UID 7)\r\n* 4 FETCH (BINARY[1] {2}\r\nab)\r\n"
+        ));
+        assert!(!has_binary_item(b"* 3 FETCH (BODY[] {10}\r\nBINARY"));
+    }
+}
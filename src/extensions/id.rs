@@ -1,16 +1,12 @@
 //! IMAP ID extension specified in [RFC2971](https://datatracker.ietf.org/doc/html/rfc2971)
 
-use async_channel as channel;
-use futures::io;
-use futures::prelude::*;
 use imap_proto::{self, RequestId, Response};
 use std::collections::HashMap;
 
-use crate::types::ResponseData;
-use crate::types::*;
 use crate::{
     error::Result,
-    parse::{filter, handle_unilateral},
+    parse::{handle_unilateral, next_command_response, ResponseStream},
+    unsolicited::UnsolicitedSender,
 };
 
 fn escape(s: &str) -> String {
@@ -35,17 +31,13 @@ pub(crate) fn format_identification<'a, 'b>(
         .join(" ")
 }
 
-pub(crate) async fn parse_id<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
+pub(crate) async fn parse_id<T: ResponseStream>(
     stream: &mut T,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
 ) -> Result<Option<HashMap<String, String>>> {
     let mut id = None;
-    while let Some(resp) = stream
-        .take_while(|res| filter(res, &command_tag))
-        .next()
-        .await
-    {
+    while let Some(resp) = next_command_response(stream, &command_tag).await {
         let resp = resp?;
         match resp.parsed() {
             Response::Id(res) => {
@@ -56,7 +48,7 @@ pub(crate) async fn parse_id<T: Stream<Item = io::Result<ResponseData>> + Unpin>
                 })
             }
             _ => {
-                handle_unilateral(resp, unsolicited.clone()).await;
+                handle_unilateral(resp, unsolicited.clone()).await?;
             }
         }
     }
@@ -2,7 +2,7 @@
 
 use std::fmt;
 use std::pin::Pin;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 #[cfg(feature = "runtime-async-std")]
 use async_std::{
@@ -20,9 +20,9 @@ use tokio::{
 };
 
 use crate::client::Session;
-use crate::error::Result;
-use crate::parse::handle_unilateral;
-use crate::types::ResponseData;
+use crate::error::{Error, Result};
+use crate::parse::{handle_unilateral, unsolicited_response};
+use crate::types::{coalesce, MailboxEvent, ResponseData};
 
 /// `Handle` allows a client to block waiting for changes to the remote mailbox.
 ///
@@ -51,7 +51,7 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Stream for Handle<T> {
     type Item = std::io::Result<ResponseData>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        self.as_mut().session().get_stream().poll_next(cx)
+        Pin::new(&mut self.session).get_stream().poll_next(cx)
     }
 }
 
@@ -65,8 +65,6 @@ pub struct IdleStream<'a, St> {
 impl<St: Unpin> Unpin for IdleStream<'_, St> {}
 
 impl<'a, St: Stream + Unpin> IdleStream<'a, St> {
-    unsafe_pinned!(stream: &'a mut St);
-
     pub(crate) fn new(stream: &'a mut St) -> Self {
         IdleStream { stream }
     }
@@ -82,7 +80,7 @@ impl<St: Stream + Unpin> Stream for IdleStream<'_, St> {
     type Item = St::Item;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        self.stream().poll_next(cx)
+        Pin::new(&mut *self.get_mut().stream).poll_next(cx)
     }
 }
 
@@ -93,10 +91,14 @@ pub enum IdleResponse {
     ManualInterrupt,
     /// The idle connection timed out, because of the user set timeout.
     Timeout,
-    /// The server has indicated that some new action has happened.
-    NewData(ResponseData),
+    /// The server reported changes to the selected mailbox. Events that arrive in quick
+    /// succession are returned together, with superseded events merged.
+    NewData(Vec<MailboxEvent>),
 }
 
+/// How long to wait for more events once one arrived, so that bursts are returned together.
+const COALESCE_WINDOW: Duration = Duration::from_millis(50);
+
 // Make it possible to access the inner connection and modify its settings, such as read/write
 // timeouts.
 impl<T: Read + Write + Unpin + fmt::Debug> AsMut<T> for Handle<T> {
@@ -106,8 +108,6 @@ impl<T: Read + Write + Unpin + fmt::Debug> AsMut<T> for Handle<T> {
 }
 
 impl<T: Read + Write + Unpin + fmt::Debug + Send> Handle<T> {
-    unsafe_pinned!(session: Session<T>);
-
     pub(crate) fn new(session: Session<T>) -> Handle<T> {
         Handle { session, id: None }
     }
@@ -142,35 +142,75 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Handle<T> {
         );
 
         let sender = self.session.unsolicited_responses_tx.clone();
+        // An error or `BYE` read by an earlier call that returned events first.
+        let earlier = self.session.conn.read_error.take().or_else(|| {
+            self.session
+                .conn
+                .bye
+                .is_some()
+                .then(|| self.session.conn.closed(None))
+        });
 
         let interrupt = stop_token::StopSource::new();
         let raw_stream = IdleStream::new(self);
         let mut interruptible_stream = raw_stream.timeout_at(interrupt.token());
 
         let fut = async move {
+            if let Some(err) = earlier {
+                return Err(err);
+            }
+            let mut events = Vec::new();
             loop {
-                let Ok(res) = timeout(dur, interruptible_stream.next()).await else {
-                    return Ok(IdleResponse::Timeout);
+                // Once there is an event, only wait a moment for more.
+                let wait = if events.is_empty() {
+                    dur
+                } else {
+                    COALESCE_WINDOW
+                };
+                let Ok(res) = timeout(wait, interruptible_stream.next()).await else {
+                    if events.is_empty() {
+                        return Ok(IdleResponse::Timeout);
+                    }
+                    return Ok(IdleResponse::NewData(events));
                 };
 
                 let Some(Ok(resp)) = res else {
-                    return Ok(IdleResponse::ManualInterrupt);
+                    if events.is_empty() {
+                        return Ok(IdleResponse::ManualInterrupt);
+                    }
+                    return Ok(IdleResponse::NewData(events));
                 };
 
-                let resp = resp?;
-                match resp.parsed() {
-                    Response::Data {
-                        status: Status::Ok, ..
-                    } => {
-                        // all good continue
+                let resp = match resp {
+                    Ok(resp) => resp,
+                    Err(err) if !events.is_empty() => {
+                        // The events are returned first, the next call returns the error.
+                        let handle = interruptible_stream.into_inner().stream;
+                        handle.session.conn.read_error = Some(err.into());
+                        return Ok(IdleResponse::NewData(events));
                     }
+                    Err(err) => return Err(err.into()),
+                };
+                match resp.parsed() {
                     Response::Continue { .. } => {
                         // continuation, wait for it
                     }
-                    Response::Done { .. } => {
-                        handle_unilateral(resp, sender.clone()).await;
+                    Response::Data {
+                        status: Status::Bye,
+                        code,
+                        information,
+                    } => {
+                        let err = Error::bye(code.as_ref(), information.as_deref());
+                        handle_unilateral(resp, sender.clone()).await?;
+                        if !events.is_empty() {
+                            return Ok(IdleResponse::NewData(events));
+                        }
+                        return Err(err);
                     }
-                    _ => return Ok(IdleResponse::NewData(resp)),
+                    _ => match MailboxEvent::try_from(unsolicited_response(resp)) {
+                        Ok(event) => coalesce(&mut events, event),
+                        Err(response) => sender.send(response).await?,
+                    },
                 }
             }
         };
@@ -182,12 +222,26 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Handle<T> {
     pub async fn init(&mut self) -> Result<()> {
         let id = self.session.run_command("IDLE").await?;
         self.id = Some(id);
-        while let Some(res) = self.session.stream.next().await {
-            let res = res?;
+        while let Some(res) = self.session.conn.next().await {
+            let res = res.map_err(|err| self.session.conn.closed(Some(err)))?;
             match res.parsed() {
                 Response::Continue { .. } => {
+                    // The server may stay silent until `DONE`, which is not a timeout.
+                    self.session
+                        .conn
+                        .pending
+                        .retain(|tag| Some(tag) != self.id.as_ref());
                     return Ok(());
                 }
+                Response::Data {
+                    status: Status::Bye,
+                    code,
+                    information,
+                } => {
+                    let err = Error::bye(code.as_ref(), information.as_deref());
+                    handle_unilateral(res, self.session.unsolicited_responses_tx.clone()).await?;
+                    return Err(err);
+                }
                 Response::Done {
                     tag,
                     status,
@@ -203,10 +257,10 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Handle<T> {
                             .into());
                         }
                     }
-                    handle_unilateral(res, self.session.unsolicited_responses_tx.clone()).await;
+                    handle_unilateral(res, self.session.unsolicited_responses_tx.clone()).await?;
                 }
                 _ => {
-                    handle_unilateral(res, self.session.unsolicited_responses_tx.clone()).await;
+                    handle_unilateral(res, self.session.unsolicited_responses_tx.clone()).await?;
                 }
             }
         }
@@ -222,6 +276,8 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Handle<T> {
             "Cannot call DONE on a non initialized idle connection"
         );
         self.session.run_command_untagged("DONE").await?;
+        let id = self.id.clone().expect("invalid setup");
+        self.session.conn.pending.push(id);
         let sender = self.session.unsolicited_responses_tx.clone();
         self.session
             .check_done_ok(&self.id.expect("invalid setup"), Some(sender))
@@ -230,3 +286,395 @@ impl<T: Read + Write + Unpin + fmt::Debug + Send> Handle<T> {
         Ok(self.session)
     }
 }
+
+/// Returns the stream of [`Session::idle_stream`].
+pub(crate) fn keepalive_stream<T: Read + Write + Unpin + fmt::Debug + Send>(
+    session: &mut Session<T>,
+    keepalive: Duration,
+) -> impl Stream<Item = Result<MailboxEvent>> + Unpin + Send + '_ {
+    let state = Keepalive {
+        session,
+        keepalive,
+        supports_idle: None,
+        idle_started: Instant::now(),
+        events: Vec::new(),
+    };
+    Box::pin(futures::stream::unfold(Some(state), |state| async move {
+        let mut state = state?;
+        match state.next_event().await {
+            Ok(event) => Some((Ok(event), Some(state))),
+            // The connection is unusable after an error, so the stream ends.
+            Err(err) => Some((Err(err), None)),
+        }
+    }))
+}
+
+/// The state of [`Session::idle_stream`].
+struct Keepalive<'a, T: Read + Write + Unpin + fmt::Debug + Send> {
+    session: &'a mut Session<T>,
+    keepalive: Duration,
+    /// Whether the server supports `IDLE`, once the capabilities have been checked.
+    supports_idle: Option<bool>,
+    /// When the running `IDLE` command was sent.
+    idle_started: Instant,
+    /// Events read but not returned yet.
+    events: Vec<MailboxEvent>,
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send> Keepalive<'_, T> {
+    async fn next_event(&mut self) -> Result<MailboxEvent> {
+        loop {
+            if !self.events.is_empty() {
+                return Ok(self.events.remove(0));
+            }
+
+            let supports_idle = match self.supports_idle {
+                Some(supports_idle) => supports_idle,
+                None => {
+                    let supports_idle = self.session.capabilities().await?.has_str("IDLE");
+                    self.supports_idle = Some(supports_idle);
+                    supports_idle
+                }
+            };
+            if supports_idle {
+                self.idle().await?;
+            } else {
+                crate::runtime::sleep(self.keepalive).await;
+                let id = self.session.run_command("NOOP").await?;
+                self.read_until_done(&id).await?;
+            }
+        }
+    }
+
+    /// Starts `IDLE` if needed, then reads the next response. Ends the `IDLE` once `keepalive`
+    /// has passed since it was started.
+    async fn idle(&mut self) -> Result<()> {
+        if self.session.conn.idling.is_none() {
+            let id = self.session.run_command("IDLE").await?;
+            // Recorded right away, so that the next command ends the `IDLE` even if the stream is
+            // dropped while waiting for the continuation.
+            self.session.conn.idling = Some(id.clone());
+            loop {
+                let response = self.next_response().await?;
+                match response.parsed() {
+                    Response::Continue { .. } => break,
+                    Response::Done {
+                        tag,
+                        status,
+                        code,
+                        information,
+                    } if *tag == id => {
+                        // Not idling after all, the next call starts over.
+                        self.session.conn.idling = None;
+                        self.session.conn.check_status_ok(
+                            status,
+                            code.as_ref(),
+                            information.as_deref(),
+                        )?;
+                        return Ok(());
+                    }
+                    _ => self.push(response).await?,
+                }
+            }
+            // The server may stay silent until `DONE`, which is not a timeout.
+            self.session.conn.pending.retain(|tag| *tag != id);
+            self.idle_started = Instant::now();
+        }
+
+        let remaining = self.keepalive.saturating_sub(self.idle_started.elapsed());
+        match timeout(remaining, self.next_response()).await {
+            Ok(response) => {
+                self.push_idle(response?).await?;
+                // Wait a moment for more events, so that bursts are returned together.
+                while !self.events.is_empty() && self.session.conn.idling.is_some() {
+                    match timeout(COALESCE_WINDOW, self.next_response()).await {
+                        Ok(response) => self.push_idle(response?).await?,
+                        Err(_) => break,
+                    }
+                }
+            }
+            Err(_) => {
+                let id = self
+                    .session
+                    .conn
+                    .idling
+                    .take()
+                    .expect("idling until DONE is sent");
+                self.session.run_command_untagged("DONE").await?;
+                self.session.conn.pending.push(id.clone());
+                self.read_until_done(&id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn push_idle(&mut self, response: ResponseData) -> Result<()> {
+        match response.parsed() {
+            // The server ended the `IDLE` by itself.
+            Response::Done { tag, .. } if Some(tag) == self.session.conn.idling.as_ref() => {
+                self.session.conn.idling = None;
+                Ok(())
+            }
+            _ => self.push(response).await,
+        }
+    }
+
+    /// Reads the responses to the command with tag `id` until it is completed.
+    async fn read_until_done(&mut self, id: &RequestId) -> Result<()> {
+        loop {
+            let response = self.next_response().await?;
+            match response.parsed() {
+                Response::Done {
+                    tag,
+                    status,
+                    code,
+                    information,
+                } if tag == id => {
+                    return self.session.conn.check_status_ok(
+                        status,
+                        code.as_ref(),
+                        information.as_deref(),
+                    );
+                }
+                _ => self.push(response).await?,
+            }
+        }
+    }
+
+    async fn next_response(&mut self) -> Result<ResponseData> {
+        self.session.conn.next_response().await
+    }
+
+    /// Adds the event described by `response`, other responses are handled as unsolicited
+    /// responses.
+    async fn push(&mut self, response: ResponseData) -> Result<()> {
+        match response.parsed() {
+            Response::Continue { .. } => Ok(()),
+            _ => match MailboxEvent::try_from(unsolicited_response(response)) {
+                Ok(event) => {
+                    coalesce(&mut self.events, event);
+                    Ok(())
+                }
+                Err(response) => self.session.unsolicited_responses_tx.send(response).await,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock_session;
+    use crate::mock_stream::MockStream;
+    use crate::types::{Flag, UnsolicitedResponse};
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn idle_stream() {
+        let response = b"* CAPABILITY IMAP4rev1 IDLE\r\n\
+            A0001 OK Capability completed\r\n\
+            + idling\r\n\
+            * OK Still here\r\n\
+            * 3 EXISTS\r\n\
+            * 4 EXISTS\r\n\
+            * 2 FETCH (FLAGS (\\Seen))\r\n\
+            * OK [ALERT] Disk almost full\r\n\
+            A0002 OK Idle terminated\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        {
+            let events: Vec<_> = session
+                .idle_stream(Duration::from_secs(60))
+                .take(2)
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(
+                events,
+                vec![
+                    MailboxEvent::NewMessages(4),
+                    MailboxEvent::FlagsChanged {
+                        seq: 2,
+                        flags: vec![Flag::Seen],
+                        modseq: None
+                    },
+                ]
+            );
+        }
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\nA0002 IDLE\r\n".to_vec()
+        );
+        assert_eq!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Notice {
+                code: None,
+                text: "Still here".to_string()
+            }
+        );
+        assert_eq!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Alert("Disk almost full".to_string())
+        );
+
+        // An `IDLE` left running by a dropped stream is ended by the next command.
+        let response = b"* CAPABILITY IMAP4rev1 IDLE\r\n\
+            A0001 OK Capability completed\r\n\
+            + idling\r\n\
+            * 3 EXISTS\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response).with_stall());
+        {
+            let mut events = session.idle_stream(Duration::from_secs(60));
+            assert_eq!(
+                events.next().await.unwrap().unwrap(),
+                MailboxEvent::NewMessages(3)
+            );
+        }
+        session.stream.inner = MockStream::new(
+            b"A0002 OK Idle terminated\r\n\
+            A0003 OK Noop completed\r\n"
+                .to_vec(),
+        );
+        session.noop().await.unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"DONE\r\nA0003 NOOP\r\n".to_vec()
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn idle_stream_dropped_before_continuation() {
+        let response = b"* CAPABILITY IMAP4rev1 IDLE\r\n\
+            A0001 OK Capability completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response.clone()).with_stall());
+        assert!(session
+            .idle_stream(Duration::from_secs(60))
+            .next()
+            .now_or_never()
+            .is_none());
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\nA0002 IDLE\r\n".to_vec()
+        );
+
+        // The next command waits for the continuation before ending the `IDLE`.
+        session.stream.inner = MockStream::new(
+            b"+ idling\r\n\
+            A0002 OK Idle terminated\r\n\
+            A0003 OK Noop completed\r\n"
+                .to_vec(),
+        );
+        session.noop().await.unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"DONE\r\nA0003 NOOP\r\n".to_vec()
+        );
+
+        // An `IDLE` the server refuses needs no `DONE`.
+        let mut session = mock_session!(MockStream::new(response).with_stall());
+        assert!(session
+            .idle_stream(Duration::from_secs(60))
+            .next()
+            .now_or_never()
+            .is_none());
+        session.stream.inner = MockStream::new(
+            b"A0002 BAD Not now\r\n\
+            A0003 OK Noop completed\r\n"
+                .to_vec(),
+        );
+        session.noop().await.unwrap();
+        assert_eq!(session.stream.inner.written_buf, b"A0003 NOOP\r\n".to_vec());
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn idle_stream_noop_fallback() {
+        let response = b"* CAPABILITY IMAP4rev1\r\n\
+            A0001 OK Capability completed\r\n\
+            A0002 OK Noop completed\r\n\
+            * 4 EXISTS\r\n\
+            A0003 OK Noop completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let mut events = session.idle_stream(Duration::from_millis(1));
+        assert_eq!(
+            events.next().await.unwrap().unwrap(),
+            MailboxEvent::NewMessages(4)
+        );
+        drop(events);
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\nA0002 NOOP\r\nA0003 NOOP\r\n".to_vec()
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn idle_events() {
+        let response = b"+ idling\r\n\
+            * 3 EXISTS\r\n\
+            * 1 EXPUNGE\r\n\
+            * 2 EXISTS\r\n"
+            .to_vec();
+        let session = mock_session!(MockStream::new(response).with_stall());
+        let mut handle = session.idle();
+        handle.init().await.unwrap();
+        let (idle, _stop) = handle.wait();
+        assert_eq!(
+            idle.await.unwrap(),
+            IdleResponse::NewData(vec![
+                MailboxEvent::NewMessages(3),
+                MailboxEvent::Expunged(1),
+                MailboxEvent::NewMessages(2)
+            ])
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn idle_events_before_bye() {
+        let response = b"+ idling\r\n\
+            * 3 EXISTS\r\n\
+            * BYE Shutting down\r\n"
+            .to_vec();
+        let session = mock_session!(MockStream::new(response));
+        let mut handle = session.idle();
+        handle.init().await.unwrap();
+        let (idle, _stop) = handle.wait();
+        assert_eq!(
+            idle.await.unwrap(),
+            IdleResponse::NewData(vec![MailboxEvent::NewMessages(3)])
+        );
+        let (idle, _stop) = handle.wait();
+        let err = idle.await.unwrap_err();
+        assert!(
+            matches!(&err, Error::Bye { text, .. } if text == "Shutting down"),
+            "Unexpected error: {err:?}"
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn idle_events_before_error() {
+        let response = b"+ idling\r\n\
+            * 3 EXISTS\r\n"
+            .to_vec();
+        let session = mock_session!(MockStream::new(response));
+        let mut handle = session.idle();
+        handle.init().await.unwrap();
+        let (idle, _stop) = handle.wait();
+        assert_eq!(
+            idle.await.unwrap(),
+            IdleResponse::NewData(vec![MailboxEvent::NewMessages(3)])
+        );
+
+        // The error is returned even if the connection could be read again.
+        handle.session.stream.inner = MockStream::new(b"* 4 EXISTS\r\n".to_vec()).with_stall();
+        let (idle, _stop) = handle.wait();
+        let err = idle.await.unwrap_err();
+        assert!(matches!(&err, Error::Io(_)), "Unexpected error: {err:?}");
+    }
+}
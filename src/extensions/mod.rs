@@ -4,3 +4,5 @@ pub mod idle;
 pub mod quota;
 
 pub mod id;
+
+pub(crate) mod binary;
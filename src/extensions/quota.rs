@@ -1,36 +1,29 @@
 //! Adds support for the GETQUOTA and GETQUOTAROOT commands specificed in [RFC2087](https://tools.ietf.org/html/rfc2087).
 
-use async_channel as channel;
-use futures::io;
-use futures::prelude::*;
 use imap_proto::{self, RequestId, Response};
 
-use crate::types::*;
 use crate::{
     error::Result,
-    parse::{filter, handle_unilateral},
+    parse::{handle_unilateral, next_command_response, ResponseStream},
+    unsolicited::UnsolicitedSender,
 };
 use crate::{
     error::{Error, ParseError},
-    types::{Quota, QuotaRoot, ResponseData},
+    types::{Quota, QuotaRoot},
 };
 
-pub(crate) async fn parse_get_quota<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
+pub(crate) async fn parse_get_quota<T: ResponseStream>(
     stream: &mut T,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
 ) -> Result<Quota> {
     let mut quota = None;
-    while let Some(resp) = stream
-        .take_while(|res| filter(res, &command_tag))
-        .next()
-        .await
-    {
+    while let Some(resp) = next_command_response(stream, &command_tag).await {
         let resp = resp?;
         match resp.parsed() {
             Response::Quota(q) => quota = Some(q.clone().into()),
             _ => {
-                handle_unilateral(resp, unsolicited.clone()).await;
+                handle_unilateral(resp, unsolicited.clone()).await?;
             }
         }
     }
@@ -43,19 +36,15 @@ pub(crate) async fn parse_get_quota<T: Stream<Item = io::Result<ResponseData>> +
     }
 }
 
-pub(crate) async fn parse_get_quota_root<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
+pub(crate) async fn parse_get_quota_root<T: ResponseStream>(
     stream: &mut T,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
 ) -> Result<(Vec<QuotaRoot>, Vec<Quota>)> {
     let mut roots: Vec<QuotaRoot> = Vec::new();
     let mut quotas: Vec<Quota> = Vec::new();
 
-    while let Some(resp) = stream
-        .take_while(|res| filter(res, &command_tag))
-        .next()
-        .await
-    {
+    while let Some(resp) = next_command_response(stream, &command_tag).await {
         let resp = resp?;
         match resp.parsed() {
             Response::QuotaRoot(qr) => {
@@ -65,7 +54,7 @@ pub(crate) async fn parse_get_quota_root<T: Stream<Item = io::Result<ResponseDat
                 quotas.push(q.clone().into());
             }
             _ => {
-                handle_unilateral(resp, unsolicited.clone()).await;
+                handle_unilateral(resp, unsolicited.clone()).await?;
             }
         }
     }
@@ -0,0 +1,360 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use async_channel as channel;
+#[cfg(feature = "runtime-async-std")]
+use async_std::io::{Read, Write};
+use futures::channel::oneshot;
+use futures::future::{self, BoxFuture, Either, FutureExt};
+use futures::{StreamExt, TryStreamExt};
+#[cfg(feature = "runtime-tokio")]
+use tokio::io::{AsyncRead as Read, AsyncWrite as Write};
+
+use crate::client::Session;
+use crate::error::{Error, Result};
+use crate::parse::handle_unilateral;
+use crate::types::{Fetch, Mailbox, Uid, UnsolicitedResponse};
+
+/// A job run by the driver. Returns whether the driver should keep running.
+type Job<T> = Box<dyn for<'a> FnOnce(&'a mut Session<T>) -> BoxFuture<'a, bool> + Send>;
+
+/// A cloneable handle to a [`Session`] that is owned by a [`SessionDriver`].
+///
+/// Commands issued through any clone of the handle are queued and run by the driver one after
+/// another, so several tasks can share one session without locking it. While no command is
+/// running, the driver keeps reading the connection and passes unsolicited responses on, see
+/// [`SessionHandle::unsolicited_responses`].
+///
+/// The driver is a future that has to be spawned on the runtime in use:
+///
+/// ```ignore
+/// let (handle, driver) = SessionHandle::new(session);
+/// tokio::spawn(driver); // or async_std::task::spawn(driver)
+///
+/// let inbox = handle.status("INBOX", "(MESSAGES UNSEEN)").await?;
+/// ```
+pub struct SessionHandle<T: Read + Write + Unpin + fmt::Debug + Send + 'static> {
+    jobs: channel::Sender<Job<T>>,
+    unsolicited_responses: channel::Receiver<UnsolicitedResponse>,
+}
+
+/// The future that owns the connection of a [`SessionHandle`] and runs its commands.
+///
+/// It resolves with the session once all handles have been dropped or after
+/// [`SessionHandle::logout`], and with an error if the connection fails.
+#[must_use = "the driver has to be spawned to run the commands of the handle"]
+pub struct SessionDriver<T: Read + Write + Unpin + fmt::Debug + Send + 'static> {
+    future: BoxFuture<'static, Result<Session<T>>>,
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send + 'static> SessionHandle<T> {
+    /// Moves the session into a new [`SessionDriver`] and returns a handle to it.
+    pub fn new(session: Session<T>) -> (Self, SessionDriver<T>) {
+        let (jobs, job_rx) = channel::unbounded();
+        let handle = SessionHandle {
+            jobs,
+            unsolicited_responses: session.unsolicited_responses.clone(),
+        };
+        let driver = SessionDriver {
+            future: drive(session, job_rx).boxed(),
+        };
+        (handle, driver)
+    }
+
+    /// Runs `f` on the session, once the commands queued before have completed.
+    ///
+    /// Streams returned by the session borrow it, so they have to be consumed within `f`:
+    ///
+    /// ```ignore
+    /// let subjects = handle
+    ///     .run(|session| {
+    ///         async move {
+    ///             let fetches = session.uid_fetch("1:*", "ENVELOPE").await?;
+    ///             fetches.try_collect::<Vec<_>>().await
+    ///         }
+    ///         .boxed()
+    ///     })
+    ///     .await??;
+    /// ```
+    ///
+    /// Returns [`Error::ConnectionLost`] if the driver stopped before running `f`.
+    pub async fn run<R, F>(&self, f: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: for<'a> FnOnce(&'a mut Session<T>) -> BoxFuture<'a, R> + Send + 'static,
+    {
+        self.submit(f, true).await
+    }
+
+    async fn submit<R, F>(&self, f: F, keep_running: bool) -> Result<R>
+    where
+        R: Send + 'static,
+        F: for<'a> FnOnce(&'a mut Session<T>) -> BoxFuture<'a, R> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job: Job<T> = Box::new(move |session| {
+            async move {
+                // The caller may have given up waiting.
+                let _ = tx.send(f(session).await);
+                keep_running
+            }
+            .boxed()
+        });
+        self.jobs
+            .send(job)
+            .await
+            .map_err(|_| Error::ConnectionLost)?;
+        rx.await.map_err(|_| Error::ConnectionLost)
+    }
+
+    /// Unsolicited responses received while running commands or while waiting for them. The
+    /// receiver is shared with the session, so each response is received by only one of its
+    /// clones.
+    pub fn unsolicited_responses(&self) -> &channel::Receiver<UnsolicitedResponse> {
+        &self.unsolicited_responses
+    }
+
+    /// See [`Session::noop`].
+    pub async fn noop(&self) -> Result<()> {
+        self.run(|session| session.noop().boxed()).await?
+    }
+
+    /// See [`Session::select`].
+    pub async fn select<S: Into<String>>(&self, mailbox_name: S) -> Result<Mailbox> {
+        let mailbox_name = mailbox_name.into();
+        self.run(move |session| async move { session.select(mailbox_name).await }.boxed())
+            .await?
+    }
+
+    /// See [`Session::examine`].
+    pub async fn examine<S: Into<String>>(&self, mailbox_name: S) -> Result<Mailbox> {
+        let mailbox_name = mailbox_name.into();
+        self.run(move |session| async move { session.examine(mailbox_name).await }.boxed())
+            .await?
+    }
+
+    /// See [`Session::status`].
+    pub async fn status<S1: Into<String>, S2: Into<String>>(
+        &self,
+        mailbox_name: S1,
+        data_items: S2,
+    ) -> Result<Mailbox> {
+        let (mailbox_name, data_items) = (mailbox_name.into(), data_items.into());
+        self.run(move |session| {
+            async move { session.status(mailbox_name, data_items).await }.boxed()
+        })
+        .await?
+    }
+
+    /// See [`Session::fetch`]. All messages are collected before they are returned.
+    pub async fn fetch<S1: Into<String>, S2: Into<String>>(
+        &self,
+        sequence_set: S1,
+        query: S2,
+    ) -> Result<Vec<Fetch>> {
+        let (sequence_set, query) = (sequence_set.into(), query.into());
+        self.run(move |session| {
+            async move {
+                let fetches = session.fetch(sequence_set, query).await?;
+                fetches.try_collect().await
+            }
+            .boxed()
+        })
+        .await?
+    }
+
+    /// See [`Session::uid_fetch`]. All messages are collected before they are returned.
+    pub async fn uid_fetch<S1: Into<String>, S2: Into<String>>(
+        &self,
+        uid_set: S1,
+        query: S2,
+    ) -> Result<Vec<Fetch>> {
+        let (uid_set, query) = (uid_set.into(), query.into());
+        self.run(move |session| {
+            async move {
+                let fetches = session.uid_fetch(uid_set, query).await?;
+                fetches.try_collect().await
+            }
+            .boxed()
+        })
+        .await?
+    }
+
+    /// See [`Session::uid_store`]. All messages are collected before they are returned.
+    pub async fn uid_store<S1: Into<String>, S2: Into<String>>(
+        &self,
+        uid_set: S1,
+        query: S2,
+    ) -> Result<Vec<Fetch>> {
+        let (uid_set, query) = (uid_set.into(), query.into());
+        self.run(move |session| {
+            async move {
+                let fetches = session.uid_store(uid_set, query).await?;
+                fetches.try_collect().await
+            }
+            .boxed()
+        })
+        .await?
+    }
+
+    /// See [`Session::uid_search`].
+    pub async fn uid_search<S: Into<String>>(&self, query: S) -> Result<HashSet<Uid>> {
+        let query = query.into();
+        self.run(move |session| async move { session.uid_search(query).await }.boxed())
+            .await?
+    }
+
+    /// Logs out once the commands queued before have completed, see [`Session::logout`]. The
+    /// driver then stops, and commands issued afterwards fail with [`Error::ConnectionLost`].
+    pub async fn logout(&self) -> Result<()> {
+        self.submit(|session| session.logout().boxed(), false)
+            .await?
+    }
+}
+
+async fn drive<T: Read + Write + Unpin + fmt::Debug + Send + 'static>(
+    mut session: Session<T>,
+    jobs: channel::Receiver<Job<T>>,
+) -> Result<Session<T>> {
+    loop {
+        // Queued jobs go first, a response is only waited for when there is nothing to do.
+        let job = jobs.recv();
+        futures::pin_mut!(job);
+        let next = match future::select(job, session.conn.next()).await {
+            Either::Left((job, _)) => Either::Left(job),
+            Either::Right((response, _)) => Either::Right(response),
+        };
+        match next {
+            Either::Left(Ok(job)) => {
+                if !job(&mut session).await {
+                    return Ok(session);
+                }
+                // Responses to the job that it did not read must not be taken as unsolicited.
+                session.finish_pending().await?;
+                // Let the callers take their results before reading the connection again.
+                yield_now().await;
+            }
+            // All handles were dropped.
+            Either::Left(Err(_)) => return Ok(session),
+            Either::Right(Some(response)) => {
+                let unsolicited = session.unsolicited_responses_tx.clone();
+                // There is no command to fail if the channel is full.
+                let response = response.map_err(|err| session.conn.closed(Some(err)))?;
+                let _ = handle_unilateral(response, unsolicited).await;
+            }
+            Either::Right(None) => return Err(session.conn.closed(None)),
+        }
+    }
+}
+
+/// Returns `Pending` once, so that other futures run before this one continues.
+async fn yield_now() {
+    let mut yielded = false;
+    future::poll_fn(|cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send + 'static> Future for SessionDriver<T> {
+    type Output = Result<Session<T>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.future.poll_unpin(cx)
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send + 'static> Clone for SessionHandle<T> {
+    fn clone(&self) -> Self {
+        SessionHandle {
+            jobs: self.jobs.clone(),
+            unsolicited_responses: self.unsolicited_responses.clone(),
+        }
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send + 'static> fmt::Debug for SessionHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionHandle")
+            .field("queued", &self.jobs.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send + 'static> fmt::Debug for SessionDriver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionDriver").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock_session;
+    use crate::mock_stream::MockStream;
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn session_handle() {
+        fn assert_shareable<H: Clone + Send + Sync>(_: &H) {}
+
+        let response = b"A0001 OK Noop completed\r\n\
+            * STATUS INBOX (MESSAGES 17)\r\n\
+            A0002 OK Status completed\r\n\
+            * 18 EXISTS\r\n"
+            .to_vec();
+        let session = mock_session!(MockStream::new(response));
+        let (handle, driver) = SessionHandle::new(session);
+        assert_shareable(&handle);
+
+        let commands = async {
+            let other = handle.clone();
+            let (noop, status) = futures::join!(handle.noop(), other.status("INBOX", "(MESSAGES)"));
+            noop.unwrap();
+            assert_eq!(status.unwrap().exists, 17);
+
+            // Read by the driver while no command is running.
+            assert_eq!(
+                handle.unsolicited_responses().recv().await.unwrap(),
+                UnsolicitedResponse::Exists(18)
+            );
+            assert!(matches!(handle.noop().await, Err(Error::ConnectionLost)));
+        };
+        let ((), driver) = futures::join!(commands, driver);
+        assert!(matches!(driver, Err(Error::Io(_))));
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn session_handle_logout() {
+        let response = b"* BYE Logging out\r\n\
+            A0001 OK Logout completed\r\n"
+            .to_vec();
+        let session = mock_session!(MockStream::new(response));
+        let (handle, driver) = SessionHandle::new(session);
+        let commands = async {
+            handle.logout().await.unwrap();
+            assert!(matches!(handle.noop().await, Err(Error::ConnectionLost)));
+        };
+        let ((), driver) = futures::join!(commands, driver);
+        let session = driver.unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 LOGOUT\r\n".to_vec()
+        );
+
+        // The driver also stops once all handles are dropped.
+        let (handle, driver) = SessionHandle::new(session);
+        drop(handle);
+        assert!(driver.await.is_ok());
+    }
+}
@@ -7,11 +7,13 @@ use bytes::BytesMut;
 use futures::stream::Stream;
 use futures::task::{Context, Poll};
 use futures::{io, ready};
+use imap_proto::Response;
 use nom::Needed;
 #[cfg(feature = "runtime-tokio")]
 use tokio::io::{AsyncRead as Read, AsyncWrite as Write, AsyncWriteExt};
 
-use crate::types::{Request, ResponseData};
+use crate::extensions::binary::{extract as extract_binary, Extracted};
+use crate::types::{RawResponse, Request, ResponseData};
 
 /// Wraps a stream, and parses incoming data as imap server messages. Writes outgoing data
 /// as imap client messages.
@@ -26,6 +28,19 @@ pub struct ImapStream<R: Read + Write> {
     decode_needs: usize,
     /// The buffer.
     buffer: Buffer,
+    /// A literal that is read with [`ImapStream::poll_read_literal`] instead of being buffered.
+    literal: Option<StreamedLiteral>,
+}
+
+/// The body of a `FETCH` response that is passed through instead of being buffered, see
+/// [`ImapStream::poll_literal_start`].
+#[derive(Debug)]
+struct StreamedLiteral {
+    /// The response up to the literal. It is completed with `NIL` in place of the literal and
+    /// parsed as usual once the literal was read.
+    prefix: Vec<u8>,
+    /// The number of bytes of the literal that were not read yet.
+    remaining: u64,
 }
 
 impl<R: Read + Write + Unpin> ImapStream<R> {
@@ -35,6 +50,7 @@ impl<R: Read + Write + Unpin> ImapStream<R> {
             inner,
             buffer: Buffer::new(),
             decode_needs: 0,
+            literal: None,
         }
     }
 
@@ -47,7 +63,7 @@ impl<R: Read + Write + Unpin> ImapStream<R> {
 
         if let Some(tag) = msg.0 {
             self.inner.write_all(tag.as_bytes()).await?;
-            self.inner.write(b" ").await?;
+            self.inner.write_all(b" ").await?;
         }
         self.inner.write_all(&msg.1).await?;
         self.inner.write_all(b"\r\n").await?;
@@ -68,6 +84,124 @@ impl<R: Read + Write + Unpin> ImapStream<R> {
         &mut self.inner
     }
 
+    /// Reads until the first line of the next response is buffered, and checks whether it starts
+    /// a `FETCH` response whose first literal is a body section, such as `BODY[]`.
+    ///
+    /// If so, the line is consumed and the length of the literal is returned. The literal is then
+    /// read with [`ImapStream::poll_read_literal`] instead of being buffered, so that bodies of any
+    /// size can be read with bounded memory. The rest of the response is returned by the next
+    /// call to `poll_next`, with `NIL` in place of the body.
+    ///
+    /// Otherwise, `None` is returned and the response is left for `poll_next`.
+    pub(crate) fn poll_literal_start(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<Option<u64>>> {
+        ready!(self.poll_skip_literal(cx))?;
+        loop {
+            let data = &self.buffer.block[..self.buffer.used()];
+            if let Some(end) = data.windows(2).position(|window| window == b"\r\n") {
+                let Some((start, len)) = body_literal_start(&data[..end]) else {
+                    return Poll::Ready(Ok(None));
+                };
+                let prefix = data[..start].to_vec();
+                let rest = data[end + 2..].to_vec();
+                self.buffer.reset_with_data(&rest);
+                self.decode_needs = 0;
+                self.literal = Some(StreamedLiteral {
+                    prefix,
+                    remaining: len,
+                });
+                if len == 0 {
+                    self.end_literal();
+                }
+                return Poll::Ready(Ok(Some(len)));
+            }
+            if ready!(self.poll_fill(cx))? == 0 {
+                // Reported by `poll_next`.
+                return Poll::Ready(Ok(None));
+            }
+        }
+    }
+
+    /// Reads from the literal found by [`ImapStream::poll_literal_start`], returning 0 once it
+    /// was read completely.
+    pub(crate) fn poll_read_literal(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let Some(literal) = &mut self.literal else {
+            return Poll::Ready(Ok(0));
+        };
+        let max = buf
+            .len()
+            .min(usize::try_from(literal.remaining).unwrap_or(usize::MAX));
+        if max == 0 {
+            return Poll::Ready(Ok(0));
+        }
+        let num_bytes = if self.buffer.used() > 0 {
+            // Data read together with the start of the literal.
+            let num_bytes = max.min(self.buffer.used());
+            buf[..num_bytes].copy_from_slice(&self.buffer.block[..num_bytes]);
+            let rest = self.buffer.block[num_bytes..self.buffer.used()].to_vec();
+            self.buffer.reset_with_data(&rest);
+            num_bytes
+        } else {
+            let num_bytes = ready!(poll_read(&mut self.inner, cx, &mut buf[..max]))?;
+            if num_bytes == 0 {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed within a literal",
+                )));
+            }
+            num_bytes
+        };
+        literal.remaining -= num_bytes as u64;
+        if literal.remaining == 0 {
+            self.end_literal();
+        }
+        Poll::Ready(Ok(num_bytes))
+    }
+
+    /// Discards the rest of a literal that was not read, e.g. because the reader was dropped.
+    fn poll_skip_literal(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut discard = [0; 8 * 1024];
+        while self.literal.is_some() {
+            ready!(self.poll_read_literal(cx, &mut discard))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Puts the rest of the response back in front of the buffered data, with `NIL` in place of
+    /// the literal that was read.
+    fn end_literal(&mut self) {
+        if let Some(literal) = self.literal.take() {
+            let mut data = literal.prefix;
+            data.extend_from_slice(b"NIL");
+            data.extend_from_slice(&self.buffer.block[..self.buffer.used()]);
+            self.buffer.reset_with_data(&data);
+            self.decode_needs = 0;
+        }
+    }
+
+    /// Reads more data into the buffer, returning the number of bytes read, 0 at EOF.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        self.buffer.ensure_capacity(self.decode_needs)?;
+        let buf = self.buffer.free_as_mut_slice();
+
+        // The buffer should have at least one byte free
+        // before we try reading into it
+        // so we can treat 0 bytes read as EOF.
+        // This is guaranteed by `ensure_capacity()` above
+        // even if it is called with 0 as an argument.
+        debug_assert!(!buf.is_empty());
+
+        let num_bytes_read = ready!(poll_read(&mut self.inner, cx, buf))?;
+        self.buffer.extend_used(num_bytes_read);
+        Poll::Ready(Ok(num_bytes_read))
+    }
+
     /// Attempts to decode a single response from the buffer.
     ///
     /// Returns `None` if the buffer does not contain enough data.
@@ -78,11 +212,47 @@ impl<R: Read + Write + Unpin> ImapStream<R> {
             return Ok(None);
         }
 
+        match extract_binary(&self.buffer.block[..self.buffer.used()]) {
+            Extracted::Binary {
+                seq,
+                text,
+                len,
+                attributes,
+            } => {
+                let rest = self.buffer.block[len..self.buffer.used()].to_vec();
+                self.buffer.reset_with_data(&rest);
+                self.decode_needs = 0;
+                let raw = RawResponse {
+                    bytes: text.as_deref().map(BytesMut::from).unwrap_or_default(),
+                    binary: attributes,
+                };
+                return ResponseData::try_new(raw, |raw| {
+                    if text.is_none() {
+                        return Ok(Response::Fetch(seq, Vec::new()));
+                    }
+                    match imap_proto::parser::parse_response(&raw.bytes) {
+                        Ok((_, response)) => Ok(response),
+                        Err(err) => Err(io::Error::other(format!(
+                            "{:?} during parsing of {:?}",
+                            err, raw.bytes
+                        ))),
+                    }
+                })
+                .map(Some);
+            }
+            Extracted::Incomplete(needed) => {
+                log::trace!("decode: incomplete data, need minimum {} bytes", needed);
+                self.decode_needs = needed;
+                return Ok(None);
+            }
+            Extracted::None => {}
+        }
+
         let block = self.buffer.take_block();
         // Be aware, now self.buffer is invalid until block is returned or reset!
 
-        let res = ResponseData::try_new_or_recover(block, |buf| {
-            let buf = &buf[..self.buffer.used()];
+        let res = ResponseData::try_new_or_recover(block.into(), |raw| {
+            let buf = &raw.bytes[..self.buffer.used()];
             log::trace!("decode: input: {:?}", std::str::from_utf8(buf));
             match imap_proto::parser::parse_response(buf) {
                 Ok((remaining, response)) => {
@@ -103,17 +273,17 @@ impl<R: Read + Write + Unpin> ImapStream<R> {
                 }
                 Err(other) => {
                     self.decode_needs = 0;
-                    Err(Some(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("{:?} during parsing of {:?}", other, buf),
-                    )))
+                    Err(Some(io::Error::other(format!(
+                        "{:?} during parsing of {:?}",
+                        other, buf
+                    ))))
                 }
             }
         });
         match res {
             Ok(response) => Ok(Some(response)),
             Err((heads, err)) => {
-                self.buffer.return_block(heads);
+                self.buffer.return_block(heads.bytes);
                 match err {
                     Some(err) => Err(err),
                     None => Ok(None),
@@ -200,10 +370,7 @@ impl Buffer {
             n => min_size + (Self::BLOCK_SIZE - n),
         };
         if new_size > Self::MAX_CAPACITY {
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                "incoming data too large",
-            ))
+            Err(io::Error::other("incoming data too large"))
         } else {
             self.block.resize(new_size, 0);
             Ok(())
@@ -264,32 +431,12 @@ impl<R: Read + Write + Unpin> Stream for ImapStream<R> {
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = &mut *self;
+        ready!(this.poll_skip_literal(cx))?;
         if let Some(response) = this.decode()? {
             return Poll::Ready(Some(Ok(response)));
         }
         loop {
-            this.buffer.ensure_capacity(this.decode_needs)?;
-            let buf = this.buffer.free_as_mut_slice();
-
-            // The buffer should have at least one byte free
-            // before we try reading into it
-            // so we can treat 0 bytes read as EOF.
-            // This is guaranteed by `ensure_capacity()` above
-            // even if it is called with 0 as an argument.
-            debug_assert!(!buf.is_empty());
-
-            #[cfg(feature = "runtime-async-std")]
-            let num_bytes_read = ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
-
-            #[cfg(feature = "runtime-tokio")]
-            let num_bytes_read = {
-                let buf = &mut tokio::io::ReadBuf::new(buf);
-                let start = buf.filled().len();
-                ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
-                buf.filled().len() - start
-            };
-
-            if num_bytes_read == 0 {
+            if ready!(this.poll_fill(cx))? == 0 {
                 if this.buffer.used() > 0 {
                     return Poll::Ready(Some(Err(io::Error::new(
                         io::ErrorKind::UnexpectedEof,
@@ -298,7 +445,6 @@ impl<R: Read + Write + Unpin> Stream for ImapStream<R> {
                 }
                 return Poll::Ready(None);
             }
-            this.buffer.extend_used(num_bytes_read);
             if let Some(response) = this.decode()? {
                 return Poll::Ready(Some(Ok(response)));
             }
@@ -306,6 +452,49 @@ impl<R: Read + Write + Unpin> Stream for ImapStream<R> {
     }
 }
 
+/// Reads from `inner` into `buf`, returning the number of bytes read.
+fn poll_read<R: Read + Unpin>(
+    inner: &mut R,
+    cx: &mut Context<'_>,
+    buf: &mut [u8],
+) -> Poll<io::Result<usize>> {
+    #[cfg(feature = "runtime-async-std")]
+    let num_bytes_read = ready!(Pin::new(inner).poll_read(cx, buf))?;
+
+    #[cfg(feature = "runtime-tokio")]
+    let num_bytes_read = {
+        let buf = &mut tokio::io::ReadBuf::new(buf);
+        ready!(Pin::new(inner).poll_read(cx, buf))?;
+        buf.filled().len()
+    };
+
+    Poll::Ready(Ok(num_bytes_read))
+}
+
+/// If `line`, the first line of a response, starts a `FETCH` response and ends with the literal
+/// of a body section, returns where the literal starts and its length.
+fn body_literal_start(line: &[u8]) -> Option<(usize, u64)> {
+    let mut words = line.splitn(4, |&b| b == b' ');
+    if words.next()? != b"*" || !words.next()?.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    if !words.next()?.eq_ignore_ascii_case(b"FETCH") {
+        return None;
+    }
+    let start = line.iter().rposition(|&b| b == b'{')?;
+    let len = std::str::from_utf8(line[start + 1..].strip_suffix(b"}")?)
+        .ok()?
+        .parse()
+        .ok()?;
+    // `BODY[<section>] {n}`, or `BODY[<section>]<<origin>> {n}` for a partial fetch.
+    let section = line[..start].strip_suffix(b" ")?;
+    if section.ends_with(b"]") || section.ends_with(b">") {
+        Some((start, len))
+    } else {
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -458,6 +647,20 @@ mod tests {
         assert_eq!(buf.block.len(), Buffer::BLOCK_SIZE);
     }
 
+    #[test]
+    fn test_body_literal_start() {
+        let line = b"* 12 FETCH (UID 5 BODY[] {1024}";
+        assert_eq!(body_literal_start(line), Some((25, 1024)));
+        let line = b"* 12 FETCH (BODY[1.2]<4096> {100}";
+        assert_eq!(body_literal_start(line), Some((28, 100)));
+        assert_eq!(body_literal_start(b"* 12 FETCH (UID 5 BODY[]"), None);
+        assert_eq!(
+            body_literal_start(b"* 12 FETCH (ENVELOPE (\"date\" {5}"),
+            None
+        );
+        assert_eq!(body_literal_start(b"* LIST () \"/\" {5}"), None);
+    }
+
     #[test]
     fn test_buffer_debug() {
         assert_eq!(
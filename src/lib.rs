@@ -14,12 +14,15 @@
 //! Pass the stream to [`Client::new()`].
 //! This gives you an unauthenticated [`Client`].
 //!
-//! Then read the server greeting:
+//! Then read the server greeting using [`Client::read_greeting()`]:
 //! ```ignore
-//! let _greeting = client
-//!     .read_response().await?
-//!     .expect("unexpected end of stream, expected greeting");
+//! let client = match client.read_greeting().await? {
+//!     Greeting::Ok { client, .. } => client,
+//!     Greeting::PreAuth { .. } => bail!("unexpected PREAUTH greeting"),
+//! };
 //! ```
+//! Servers such as `imapd` spawned over a local pipe may greet with `PREAUTH`,
+//! in which case an authenticated [`Session`] is returned right away.
 //!
 //! ## STARTTLS
 //!
@@ -81,22 +84,36 @@ compile_error!("one of 'runtime-async-std' or 'runtime-tokio' features must be e
 
 #[cfg(all(feature = "runtime-tokio", feature = "runtime-async-std"))]
 compile_error!("only one of 'runtime-async-std' or 'runtime-tokio' features must be enabled");
-#[macro_use]
-extern crate pin_utils;
 
 // Reexport imap_proto for easier access.
 pub use imap_proto;
 
 mod authenticator;
+mod body;
 mod client;
 pub mod error;
 pub mod extensions;
+mod handle;
 mod imap_stream;
 mod parse;
+mod pipeline;
+mod pool;
+mod reconnect;
+mod runtime;
+mod selected;
 pub mod types;
+mod unsolicited;
+mod watcher;
 
-pub use crate::authenticator::Authenticator;
+pub use crate::authenticator::{AsyncAuthenticator, Authenticator};
+pub use crate::body::BodyReader;
 pub use crate::client::*;
+pub use crate::handle::{SessionDriver, SessionHandle};
+pub use crate::pipeline::{Pipeline, PipelineResponse};
+pub use crate::pool::{PoolConfig, PooledSession, SessionPool};
+pub use crate::reconnect::{ReconnectConfig, ReconnectEvent, ReconnectingSession};
+pub use crate::selected::{SelectedIdleHandle, SelectedSession};
+pub use crate::unsolicited::{OverflowPolicy, UnsolicitedHandler};
 
 #[cfg(test)]
 mod mock_stream;
@@ -16,6 +16,7 @@ pub struct MockStream {
     err_on_read: bool,
     eof_on_read: bool,
     read_delay: usize,
+    stall_on_eof: bool,
 }
 
 impl MockStream {
@@ -42,6 +43,12 @@ impl MockStream {
         self.read_delay = 1;
         self
     }
+
+    /// Never completes reads once `read_buf` is consumed, like a server that stopped responding.
+    pub fn with_stall(mut self) -> MockStream {
+        self.stall_on_eof = true;
+        self
+    }
 }
 
 #[cfg(feature = "runtime-tokio")]
@@ -55,9 +62,12 @@ impl Read for MockStream {
             return Poll::Ready(Ok(()));
         }
         if self.err_on_read {
-            return Poll::Ready(Err(Error::new(ErrorKind::Other, "MockStream Error")));
+            return Poll::Ready(Err(Error::other("MockStream Error")));
         }
         if self.read_pos >= self.read_buf.len() {
+            if self.stall_on_eof {
+                return Poll::Pending;
+            }
             return Poll::Ready(Err(Error::new(ErrorKind::UnexpectedEof, "EOF")));
         }
         let mut write_len = min(buf.remaining(), self.read_buf.len() - self.read_pos);
@@ -103,9 +113,12 @@ impl Read for MockStream {
             return Poll::Ready(Ok(0));
         }
         if self.err_on_read {
-            return Poll::Ready(Err(Error::new(ErrorKind::Other, "MockStream Error")));
+            return Poll::Ready(Err(Error::other("MockStream Error")));
         }
         if self.read_pos >= self.read_buf.len() {
+            if self.stall_on_eof {
+                return Poll::Pending;
+            }
             return Poll::Ready(Err(Error::new(ErrorKind::UnexpectedEof, "EOF")));
         }
         let mut write_len = min(buf.len(), self.read_buf.len() - self.read_pos);
@@ -1,106 +1,119 @@
 use std::collections::HashSet;
+use std::fmt;
 
-use async_channel as channel;
+#[cfg(feature = "runtime-async-std")]
+use async_std::io::{Read, Write};
 use futures::io;
 use futures::prelude::*;
 use futures::stream::Stream;
 use imap_proto::{self, MailboxDatum, Metadata, RequestId, Response};
+#[cfg(feature = "runtime-tokio")]
+use tokio::io::{AsyncRead as Read, AsyncWrite as Write};
 
+use crate::client::Connection;
 use crate::error::{Error, Result};
 use crate::types::ResponseData;
 use crate::types::*;
+use crate::unsolicited::UnsolicitedSender;
 
-pub(crate) fn parse_names<T: Stream<Item = io::Result<ResponseData>> + Unpin + Send>(
+/// A stream of responses from the server.
+pub(crate) trait ResponseStream: Stream<Item = io::Result<ResponseData>> + Unpin {
+    /// The error to return when reading a response failed with `err`.
+    fn read_error(&self, err: io::Error) -> Error;
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug> ResponseStream for Connection<T> {
+    fn read_error(&self, err: io::Error) -> Error {
+        self.closed(Some(err))
+    }
+}
+
+/// Reads the next response to the command with `command_tag`, or returns `None` once the command
+/// is completed.
+pub(crate) async fn next_command_response<T: ResponseStream>(
     stream: &mut T,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    command_tag: &RequestId,
+) -> Option<Result<ResponseData>> {
+    match stream.next().await? {
+        Ok(resp) => match resp.parsed() {
+            Response::Done { tag, .. } if tag == command_tag => None,
+            _ => Some(Ok(resp)),
+        },
+        Err(err) => Some(Err(stream.read_error(err))),
+    }
+}
+
+/// The items that `item` takes from the responses to the command with `command_tag`. The
+/// responses it does not take are handled as unsolicited responses.
+fn command_items<'a, T: ResponseStream + Send, I: Send + 'a>(
+    stream: &'a mut T,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
-) -> impl Stream<Item = Result<Name>> + '_ + Send + Unpin {
-    use futures::{FutureExt, StreamExt};
-
-    StreamExt::filter_map(
-        StreamExt::take_while(stream, move |res| filter(res, &command_tag)),
-        move |resp| {
-            let unsolicited = unsolicited.clone();
-            async move {
-                match resp {
-                    Ok(resp) => match resp.parsed() {
-                        Response::MailboxData(MailboxDatum::List { .. }) => {
-                            let name = Name::from_mailbox_data(resp);
-                            Some(Ok(name))
-                        }
-                        _ => {
-                            handle_unilateral(resp, unsolicited).await;
-                            None
+    item: fn(ResponseData) -> std::result::Result<I, ResponseData>,
+) -> impl Stream<Item = Result<I>> + 'a + Send + Unpin {
+    Box::pin(futures::stream::unfold(stream, move |stream| {
+        let unsolicited = unsolicited.clone();
+        let command_tag = command_tag.clone();
+        async move {
+            loop {
+                let resp = match next_command_response(stream, &command_tag).await? {
+                    Ok(resp) => resp,
+                    Err(err) => return Some((Err(err), stream)),
+                };
+                match item(resp) {
+                    Ok(item) => return Some((Ok(item), stream)),
+                    Err(resp) => {
+                        if let Err(err) = handle_unilateral(resp, unsolicited.clone()).await {
+                            return Some((Err(err), stream));
                         }
-                    },
-                    Err(err) => Some(Err(err.into())),
+                    }
                 }
             }
-            .boxed()
-        },
-    )
-}
-
-pub(crate) fn filter(
-    res: &io::Result<ResponseData>,
-    command_tag: &RequestId,
-) -> impl Future<Output = bool> {
-    let val = filter_sync(res, command_tag);
-    futures::future::ready(val)
+        }
+    }))
 }
 
-pub(crate) fn filter_sync(res: &io::Result<ResponseData>, command_tag: &RequestId) -> bool {
-    match res {
-        Ok(res) => match res.parsed() {
-            Response::Done { tag, .. } => tag != command_tag,
-            _ => true,
-        },
-        Err(_err) => {
-            // Do not filter out the errors such as unexpected EOF.
-            true
+pub(crate) fn parse_names<T: ResponseStream + Send>(
+    stream: &mut T,
+    unsolicited: UnsolicitedSender,
+    command_tag: RequestId,
+) -> impl Stream<Item = Result<Name>> + '_ + Send + Unpin {
+    command_items(stream, unsolicited, command_tag, |resp| {
+        if matches!(
+            resp.parsed(),
+            Response::MailboxData(MailboxDatum::List { .. })
+        ) {
+            Ok(Name::from_mailbox_data(resp))
+        } else {
+            Err(resp)
         }
-    }
+    })
 }
 
-pub(crate) fn parse_fetches<T: Stream<Item = io::Result<ResponseData>> + Unpin + Send>(
+pub(crate) fn parse_fetches<T: ResponseStream + Send>(
     stream: &mut T,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
 ) -> impl Stream<Item = Result<Fetch>> + '_ + Send + Unpin {
-    use futures::{FutureExt, StreamExt};
-
-    StreamExt::filter_map(
-        StreamExt::take_while(stream, move |res| filter(res, &command_tag)),
-        move |resp| {
-            let unsolicited = unsolicited.clone();
-
-            async move {
-                match resp {
-                    Ok(resp) => match resp.parsed() {
-                        Response::Fetch(..) => Some(Ok(Fetch::new(resp))),
-                        _ => {
-                            handle_unilateral(resp, unsolicited).await;
-                            None
-                        }
-                    },
-                    Err(err) => Some(Err(err.into())),
-                }
-            }
-            .boxed()
-        },
-    )
+    command_items(stream, unsolicited, command_tag, |resp| {
+        if matches!(resp.parsed(), Response::Fetch(..)) {
+            Ok(Fetch::new(resp))
+        } else {
+            Err(resp)
+        }
+    })
 }
 
-pub(crate) async fn parse_status<T: Stream<Item = io::Result<ResponseData>> + Unpin + Send>(
+pub(crate) async fn parse_status<T: ResponseStream + Send>(
     stream: &mut T,
     expected_mailbox: &str,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
 ) -> Result<Mailbox> {
     let mut mbox = Mailbox::default();
 
     while let Some(resp) = stream.next().await {
-        let resp = resp?;
+        let resp = resp.map_err(|err| stream.read_error(err))?;
         match resp.parsed() {
             Response::Done {
                 tag,
@@ -114,50 +127,23 @@ pub(crate) async fn parse_status<T: Stream<Item = io::Result<ResponseData>> + Un
                     Status::Ok => {
                         break;
                     }
-                    Status::Bad => {
-                        return Err(Error::Bad(format!(
-                            "code: {:?}, info: {:?}",
-                            code, information
-                        )))
-                    }
-                    Status::No => {
-                        return Err(Error::No(format!(
-                            "code: {:?}, info: {:?}",
-                            code, information
-                        )))
-                    }
+                    Status::Bad => return Err(Error::bad(code.as_ref(), information.as_deref())),
+                    Status::No => return Err(Error::no(code.as_ref(), information.as_deref())),
                     _ => {
-                        return Err(Error::Io(io::Error::new(
-                            io::ErrorKind::Other,
-                            format!(
-                                "status: {:?}, code: {:?}, information: {:?}",
-                                status, code, information
-                            ),
-                        )));
+                        return Err(Error::Io(io::Error::other(format!(
+                            "status: {:?}, code: {:?}, information: {:?}",
+                            status, code, information
+                        ))));
                     }
                 }
             }
             Response::MailboxData(MailboxDatum::Status { mailbox, status })
                 if mailbox == expected_mailbox =>
             {
-                for attribute in status {
-                    match attribute {
-                        StatusAttribute::HighestModSeq(highest_modseq) => {
-                            mbox.highest_modseq = Some(*highest_modseq)
-                        }
-                        StatusAttribute::Messages(exists) => mbox.exists = *exists,
-                        StatusAttribute::Recent(recent) => mbox.recent = *recent,
-                        StatusAttribute::UidNext(uid_next) => mbox.uid_next = Some(*uid_next),
-                        StatusAttribute::UidValidity(uid_validity) => {
-                            mbox.uid_validity = Some(*uid_validity)
-                        }
-                        StatusAttribute::Unseen(unseen) => mbox.unseen = Some(*unseen),
-                        _ => {}
-                    }
-                }
+                apply_status_attributes(&mut mbox, status);
             }
             _ => {
-                handle_unilateral(resp, unsolicited.clone()).await;
+                handle_unilateral(resp, unsolicited.clone()).await?;
             }
         }
     }
@@ -165,46 +151,43 @@ pub(crate) async fn parse_status<T: Stream<Item = io::Result<ResponseData>> + Un
     Ok(mbox)
 }
 
-pub(crate) fn parse_expunge<T: Stream<Item = io::Result<ResponseData>> + Unpin + Send>(
+pub(crate) fn apply_status_attributes(mbox: &mut Mailbox, status: &[StatusAttribute]) {
+    for attribute in status {
+        match attribute {
+            StatusAttribute::HighestModSeq(highest_modseq) => {
+                mbox.highest_modseq = Some(*highest_modseq)
+            }
+            StatusAttribute::Messages(exists) => mbox.exists = *exists,
+            StatusAttribute::Recent(recent) => mbox.recent = *recent,
+            StatusAttribute::UidNext(uid_next) => mbox.uid_next = Some(*uid_next),
+            StatusAttribute::UidValidity(uid_validity) => mbox.uid_validity = Some(*uid_validity),
+            StatusAttribute::Unseen(unseen) => mbox.unseen = Some(*unseen),
+            _ => {}
+        }
+    }
+}
+
+pub(crate) fn parse_expunge<T: ResponseStream + Send>(
     stream: &mut T,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
 ) -> impl Stream<Item = Result<u32>> + '_ + Send {
-    use futures::StreamExt;
-
-    StreamExt::filter_map(
-        StreamExt::take_while(stream, move |res| filter(res, &command_tag)),
-        move |resp| {
-            let unsolicited = unsolicited.clone();
-
-            async move {
-                match resp {
-                    Ok(resp) => match resp.parsed() {
-                        Response::Expunge(id) => Some(Ok(*id)),
-                        _ => {
-                            handle_unilateral(resp, unsolicited).await;
-                            None
-                        }
-                    },
-                    Err(err) => Some(Err(err.into())),
-                }
-            }
-        },
-    )
+    command_items(stream, unsolicited, command_tag, |resp| {
+        match resp.parsed() {
+            Response::Expunge(id) => Ok(*id),
+            _ => Err(resp),
+        }
+    })
 }
 
-pub(crate) async fn parse_capabilities<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
+pub(crate) async fn parse_capabilities<T: ResponseStream>(
     stream: &mut T,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
 ) -> Result<Capabilities> {
     let mut caps: HashSet<Capability> = HashSet::new();
 
-    while let Some(resp) = stream
-        .take_while(|res| filter(res, &command_tag))
-        .next()
-        .await
-    {
+    while let Some(resp) = next_command_response(stream, &command_tag).await {
         let resp = resp?;
         match resp.parsed() {
             Response::Capabilities(cs) => {
@@ -213,7 +196,7 @@ pub(crate) async fn parse_capabilities<T: Stream<Item = io::Result<ResponseData>
                 }
             }
             _ => {
-                handle_unilateral(resp, unsolicited.clone()).await;
+                handle_unilateral(resp, unsolicited.clone()).await?;
             }
         }
     }
@@ -221,32 +204,28 @@ pub(crate) async fn parse_capabilities<T: Stream<Item = io::Result<ResponseData>
     Ok(Capabilities(caps))
 }
 
-pub(crate) async fn parse_noop<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
+pub(crate) async fn parse_noop<T: ResponseStream>(
     stream: &mut T,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
 ) -> Result<()> {
-    while let Some(resp) = stream
-        .take_while(|res| filter(res, &command_tag))
-        .next()
-        .await
-    {
+    while let Some(resp) = next_command_response(stream, &command_tag).await {
         let resp = resp?;
-        handle_unilateral(resp, unsolicited.clone()).await;
+        handle_unilateral(resp, unsolicited.clone()).await?;
     }
 
     Ok(())
 }
 
-pub(crate) async fn parse_mailbox<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
+pub(crate) async fn parse_mailbox<T: ResponseStream>(
     stream: &mut T,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
 ) -> Result<Mailbox> {
     let mut mailbox = Mailbox::default();
 
     while let Some(resp) = stream.next().await {
-        let resp = resp?;
+        let resp = resp.map_err(|err| stream.read_error(err))?;
         match resp.parsed() {
             Response::Done {
                 tag,
@@ -258,28 +237,27 @@ pub(crate) async fn parse_mailbox<T: Stream<Item = io::Result<ResponseData>> + U
                 use imap_proto::Status;
                 match status {
                     Status::Ok => {
+                        // e.g. `OK [READ-WRITE] SELECT completed`
+                        let (code, _) =
+                            ResponseCode::from_status(code.as_ref(), information.as_deref());
+                        match code {
+                            Some(ResponseCode::Alert) => {
+                                handle_unilateral(resp, unsolicited).await?
+                            }
+                            Some(code) => {
+                                apply_mailbox_code(&mut mailbox, &code);
+                            }
+                            None => {}
+                        }
                         break;
                     }
-                    Status::Bad => {
-                        return Err(Error::Bad(format!(
-                            "code: {:?}, info: {:?}",
-                            code, information
-                        )))
-                    }
-                    Status::No => {
-                        return Err(Error::No(format!(
-                            "code: {:?}, info: {:?}",
-                            code, information
-                        )))
-                    }
+                    Status::Bad => return Err(Error::bad(code.as_ref(), information.as_deref())),
+                    Status::No => return Err(Error::no(code.as_ref(), information.as_deref())),
                     _ => {
-                        return Err(Error::Io(io::Error::new(
-                            io::ErrorKind::Other,
-                            format!(
-                                "status: {:?}, code: {:?}, information: {:?}",
-                                status, code, information
-                            ),
-                        )));
+                        return Err(Error::Io(io::Error::other(format!(
+                            "status: {:?}, code: {:?}, information: {:?}",
+                            status, code, information
+                        ))));
                     }
                 }
             }
@@ -292,53 +270,35 @@ pub(crate) async fn parse_mailbox<T: Stream<Item = io::Result<ResponseData>> + U
 
                 match status {
                     Status::Ok => {
-                        use imap_proto::ResponseCode;
-                        match code {
-                            Some(ResponseCode::UidValidity(uid)) => {
-                                mailbox.uid_validity = Some(*uid);
-                            }
-                            Some(ResponseCode::UidNext(unext)) => {
-                                mailbox.uid_next = Some(*unext);
-                            }
-                            Some(ResponseCode::HighestModSeq(highest_modseq)) => {
-                                mailbox.highest_modseq = Some(*highest_modseq);
-                            }
-                            Some(ResponseCode::Unseen(n)) => {
-                                mailbox.unseen = Some(*n);
-                            }
-                            Some(ResponseCode::PermanentFlags(flags)) => {
-                                mailbox
-                                    .permanent_flags
-                                    .extend(flags.iter().map(|s| (*s).to_string()).map(Flag::from));
-                            }
-                            _ => {}
+                        let (code, _) =
+                            ResponseCode::from_status(code.as_ref(), information.as_deref());
+                        // Responses before `CLOSED` belong to the previously selected mailbox.
+                        if code == Some(ResponseCode::Closed) {
+                            mailbox = Mailbox::default();
+                        }
+                        let applied = code
+                            .as_ref()
+                            .is_some_and(|code| apply_mailbox_code(&mut mailbox, code));
+                        if !applied {
+                            handle_unilateral(resp, unsolicited.clone()).await?;
                         }
                     }
-                    Status::Bad => {
-                        return Err(Error::Bad(format!(
-                            "code: {:?}, info: {:?}",
-                            code, information
-                        )))
-                    }
-                    Status::No => {
-                        return Err(Error::No(format!(
-                            "code: {:?}, info: {:?}",
-                            code, information
-                        )))
+                    Status::Bad => return Err(Error::bad(code.as_ref(), information.as_deref())),
+                    // `* NO [UIDNOTSTICKY] Non-persistent UIDs` is a warning about the mailbox.
+                    Status::No if matches!(code, Some(imap_proto::ResponseCode::UidNotSticky)) => {
+                        mailbox.uid_not_sticky = true;
                     }
+                    Status::No => return Err(Error::no(code.as_ref(), information.as_deref())),
                     _ => {
-                        return Err(Error::Io(io::Error::new(
-                            io::ErrorKind::Other,
-                            format!(
-                                "status: {:?}, code: {:?}, information: {:?}",
-                                status, code, information
-                            ),
-                        )));
+                        return Err(Error::Io(io::Error::other(format!(
+                            "status: {:?}, code: {:?}, information: {:?}",
+                            status, code, information
+                        ))));
                     }
                 }
             }
             Response::MailboxData(m) => match m {
-                MailboxDatum::Status { .. } => handle_unilateral(resp, unsolicited.clone()).await,
+                MailboxDatum::Status { .. } => handle_unilateral(resp, unsolicited.clone()).await?,
                 MailboxDatum::Exists(e) => {
                     mailbox.exists = *e;
                 }
@@ -358,7 +318,7 @@ pub(crate) async fn parse_mailbox<T: Stream<Item = io::Result<ResponseData>> + U
                 _ => {}
             },
             _ => {
-                handle_unilateral(resp, unsolicited.clone()).await;
+                handle_unilateral(resp, unsolicited.clone()).await?;
             }
         }
     }
@@ -366,18 +326,37 @@ pub(crate) async fn parse_mailbox<T: Stream<Item = io::Result<ResponseData>> + U
     Ok(mailbox)
 }
 
-pub(crate) async fn parse_ids<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
+/// Applies a response code sent while selecting a mailbox. Returns `false` for codes that do not
+/// describe the mailbox.
+fn apply_mailbox_code(mailbox: &mut Mailbox, code: &ResponseCode) -> bool {
+    match code {
+        ResponseCode::UidValidity(uid) => mailbox.uid_validity = Some(*uid),
+        ResponseCode::UidNext(unext) => mailbox.uid_next = Some(*unext),
+        ResponseCode::HighestModSeq(highest_modseq) => {
+            mailbox.highest_modseq = Some(*highest_modseq)
+        }
+        ResponseCode::Unseen(n) => mailbox.unseen = Some(*n),
+        ResponseCode::PermanentFlags(flags) => mailbox
+            .permanent_flags
+            .extend(flags.iter().cloned().map(Flag::from)),
+        ResponseCode::ReadOnly => mailbox.access_mode = Some(AccessMode::ReadOnly),
+        ResponseCode::ReadWrite => mailbox.access_mode = Some(AccessMode::ReadWrite),
+        ResponseCode::UidNotSticky => mailbox.uid_not_sticky = true,
+        ResponseCode::NoModSeq => mailbox.no_modseq = true,
+        ResponseCode::MailboxId(id) => mailbox.mailbox_id = Some(id.clone()),
+        _ => return false,
+    }
+    true
+}
+
+pub(crate) async fn parse_ids<T: ResponseStream>(
     stream: &mut T,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
 ) -> Result<HashSet<u32>> {
     let mut ids: HashSet<u32> = HashSet::new();
 
-    while let Some(resp) = stream
-        .take_while(|res| filter(res, &command_tag))
-        .next()
-        .await
-    {
+    while let Some(resp) = next_command_response(stream, &command_tag).await {
         let resp = resp?;
         match resp.parsed() {
             Response::MailboxData(MailboxDatum::Search(cs)) => {
@@ -386,7 +365,7 @@ pub(crate) async fn parse_ids<T: Stream<Item = io::Result<ResponseData>> + Unpin
                 }
             }
             _ => {
-                handle_unilateral(resp, unsolicited.clone()).await;
+                handle_unilateral(resp, unsolicited.clone()).await?;
             }
         }
     }
@@ -395,18 +374,14 @@ pub(crate) async fn parse_ids<T: Stream<Item = io::Result<ResponseData>> + Unpin
 }
 
 /// Parses [GETMETADATA](https://www.rfc-editor.org/info/rfc5464) response.
-pub(crate) async fn parse_metadata<T: Stream<Item = io::Result<ResponseData>> + Unpin>(
+pub(crate) async fn parse_metadata<T: ResponseStream>(
     stream: &mut T,
     mailbox_name: &str,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
+    unsolicited: UnsolicitedSender,
     command_tag: RequestId,
 ) -> Result<Vec<Metadata>> {
     let mut res_values = Vec::new();
-    while let Some(resp) = stream
-        .take_while(|res| filter(res, &command_tag))
-        .next()
-        .await
-    {
+    while let Some(resp) = next_command_response(stream, &command_tag).await {
         let resp = resp?;
         match resp.parsed() {
             // METADATA Response with Values
@@ -421,7 +396,7 @@ pub(crate) async fn parse_metadata<T: Stream<Item = io::Result<ResponseData>> +
             // [Unsolicited METADATA Response without Values](https://datatracker.ietf.org/doc/html/rfc5464.html#section-4.4.2),
             // they go to unsolicited channel with other unsolicited responses.
             _ => {
-                handle_unilateral(resp, unsolicited.clone()).await;
+                handle_unilateral(resp, unsolicited.clone()).await?;
             }
         }
     }
@@ -432,47 +407,50 @@ pub(crate) async fn parse_metadata<T: Stream<Item = io::Result<ResponseData>> +
 // (see Section 7 of RFC 3501):
 pub(crate) async fn handle_unilateral(
     res: ResponseData,
-    unsolicited: channel::Sender<UnsolicitedResponse>,
-) {
-    // ignore these if they are not being consumed
-    if unsolicited.is_full() {
-        return;
-    }
+    unsolicited: UnsolicitedSender,
+) -> Result<()> {
+    unsolicited.send(unsolicited_response(res)).await
+}
 
+/// Converts a response that is not part of the result of a command.
+pub(crate) fn unsolicited_response(res: ResponseData) -> UnsolicitedResponse {
     match res.parsed() {
         Response::MailboxData(MailboxDatum::Status { mailbox, status }) => {
-            unsolicited
-                .send(UnsolicitedResponse::Status {
-                    mailbox: (mailbox.as_ref()).into(),
-                    attributes: status.to_vec(),
-                })
-                .await
-                .expect("Channel closed unexpectedly");
-        }
-        Response::MailboxData(MailboxDatum::Recent(n)) => {
-            unsolicited
-                .send(UnsolicitedResponse::Recent(*n))
-                .await
-                .expect("Channel closed unexpectedly");
+            UnsolicitedResponse::Status {
+                mailbox: (mailbox.as_ref()).into(),
+                attributes: status.to_vec(),
+            }
         }
-        Response::MailboxData(MailboxDatum::Exists(n)) => {
-            unsolicited
-                .send(UnsolicitedResponse::Exists(*n))
-                .await
-                .expect("Channel closed unexpectedly");
+        Response::MailboxData(MailboxDatum::Recent(n)) => UnsolicitedResponse::Recent(*n),
+        Response::MailboxData(MailboxDatum::Exists(n)) => UnsolicitedResponse::Exists(*n),
+        Response::Expunge(n) => UnsolicitedResponse::Expunge(*n),
+        Response::Data {
+            status: imap_proto::Status::Bye,
+            code,
+            information,
+        } => {
+            let (code, text) = ResponseCode::from_status(code.as_ref(), information.as_deref());
+            UnsolicitedResponse::Bye { code, text }
         }
-        Response::Expunge(n) => {
-            unsolicited
-                .send(UnsolicitedResponse::Expunge(*n))
-                .await
-                .expect("Channel closed unexpectedly");
+        Response::Data {
+            code: Some(imap_proto::ResponseCode::Alert),
+            information,
+            ..
         }
-        _ => {
-            unsolicited
-                .send(UnsolicitedResponse::Other(res))
-                .await
-                .expect("Channel closed unexpectedly");
+        | Response::Done {
+            code: Some(imap_proto::ResponseCode::Alert),
+            information,
+            ..
+        } => UnsolicitedResponse::Alert(information.as_deref().unwrap_or_default().to_string()),
+        Response::Data {
+            status: imap_proto::Status::Ok,
+            code,
+            information,
+        } => {
+            let (code, text) = ResponseCode::from_status(code.as_ref(), information.as_deref());
+            UnsolicitedResponse::Notice { code, text }
         }
+        _ => UnsolicitedResponse::Other(res),
     }
 }
 
@@ -482,12 +460,21 @@ mod tests {
     use async_channel::bounded;
     use bytes::BytesMut;
 
+    impl<I: Iterator<Item = io::Result<ResponseData>> + Unpin> ResponseStream
+        for async_std::stream::FromIter<I>
+    {
+        fn read_error(&self, err: io::Error) -> Error {
+            Error::Io(err)
+        }
+    }
+
     fn input_stream(data: &[&str]) -> Vec<io::Result<ResponseData>> {
         data.iter()
             .map(|line| {
                 let block = BytesMut::from(line.as_bytes());
-                ResponseData::try_new(block, |bytes| -> io::Result<_> {
-                    let (remaining, response) = imap_proto::parser::parse_response(bytes).unwrap();
+                ResponseData::try_new(block.into(), |raw| -> io::Result<_> {
+                    let (remaining, response) =
+                        imap_proto::parser::parse_response(&raw.bytes).unwrap();
                     assert_eq!(remaining.len(), 0);
                     Ok(response)
                 })
@@ -505,7 +492,9 @@ mod tests {
         let mut stream = async_std::stream::from_iter(responses);
         let (send, recv) = bounded(10);
         let id = RequestId("A0001".into());
-        let capabilities = parse_capabilities(&mut stream, send, id).await.unwrap();
+        let capabilities = parse_capabilities(&mut stream, send.into(), id)
+            .await
+            .unwrap();
         // shouldn't be any unexpected responses parsed
         assert!(recv.is_empty());
         assert_eq!(capabilities.len(), 4);
@@ -524,7 +513,9 @@ mod tests {
 
         let (send, recv) = bounded(10);
         let id = RequestId("A0001".into());
-        let capabilities = parse_capabilities(&mut stream, send, id).await.unwrap();
+        let capabilities = parse_capabilities(&mut stream, send.into(), id)
+            .await
+            .unwrap();
 
         // shouldn't be any unexpected responses parsed
         assert!(recv.is_empty());
@@ -543,7 +534,7 @@ mod tests {
         let mut stream = async_std::stream::from_iter(responses);
 
         let id = RequestId("A0001".into());
-        parse_capabilities(&mut stream, send.clone(), id)
+        parse_capabilities(&mut stream, send.clone().into(), id)
             .await
             .unwrap();
         assert!(recv.is_empty());
@@ -557,7 +548,7 @@ mod tests {
         let mut stream = async_std::stream::from_iter(responses);
 
         let id = RequestId("A0001".into());
-        let names: Vec<_> = parse_names(&mut stream, send, id)
+        let names: Vec<_> = parse_names(&mut stream, send.into(), id)
             .try_collect::<Vec<Name>>()
             .await
             .unwrap();
@@ -579,7 +570,7 @@ mod tests {
         let mut stream = async_std::stream::from_iter(responses);
         let id = RequestId("a".into());
 
-        let fetches = parse_fetches(&mut stream, send, id)
+        let fetches = parse_fetches(&mut stream, send.into(), id)
             .try_collect::<Vec<_>>()
             .await
             .unwrap();
@@ -598,7 +589,7 @@ mod tests {
         let mut stream = async_std::stream::from_iter(responses);
         let id = RequestId("a".into());
 
-        let fetches = parse_fetches(&mut stream, send, id)
+        let fetches = parse_fetches(&mut stream, send.into(), id)
             .try_collect::<Vec<_>>()
             .await
             .unwrap();
@@ -626,7 +617,7 @@ mod tests {
         let mut stream = async_std::stream::from_iter(responses);
         let id = RequestId("a".into());
 
-        let fetches = parse_fetches(&mut stream, send, id)
+        let fetches = parse_fetches(&mut stream, send.into(), id)
             .try_collect::<Vec<_>>()
             .await
             .unwrap();
@@ -648,7 +639,7 @@ mod tests {
         let mut stream = async_std::stream::from_iter(responses);
 
         let id = RequestId("A0001".into());
-        let names = parse_names(&mut stream, send, id)
+        let names = parse_names(&mut stream, send.into(), id)
             .try_collect::<Vec<_>>()
             .await
             .unwrap();
@@ -678,7 +669,9 @@ mod tests {
         let expected_capabilities = &["IMAP4rev1", "STARTTLS", "AUTH=GSSAPI", "LOGINDISABLED"];
 
         let id = RequestId("A0001".into());
-        let capabilities = parse_capabilities(&mut stream, send, id).await.unwrap();
+        let capabilities = parse_capabilities(&mut stream, send.into(), id)
+            .await
+            .unwrap();
 
         assert_eq!(capabilities.len(), 4);
         for e in expected_capabilities {
@@ -712,7 +705,7 @@ mod tests {
         let mut stream = async_std::stream::from_iter(responses);
 
         let id = RequestId("A0001".into());
-        let ids = parse_ids(&mut stream, send, id).await.unwrap();
+        let ids = parse_ids(&mut stream, send.into(), id).await.unwrap();
 
         assert_eq!(ids, [23, 42, 4711].iter().cloned().collect());
 
@@ -742,7 +735,7 @@ mod tests {
         let mut stream = async_std::stream::from_iter(responses);
 
         let id = RequestId("A0001".into());
-        let ids = parse_ids(&mut stream, send, id).await.unwrap();
+        let ids = parse_ids(&mut stream, send.into(), id).await.unwrap();
 
         assert!(recv.is_empty());
         let ids: HashSet<u32> = ids.iter().cloned().collect();
@@ -773,7 +766,7 @@ mod tests {
         let mut stream = async_std::stream::from_iter(responses);
 
         let id = RequestId("A0001".into());
-        let ids = parse_ids(&mut stream, send, id).await.unwrap();
+        let ids = parse_ids(&mut stream, send.into(), id).await.unwrap();
 
         assert!(recv.is_empty());
         let ids: HashSet<u32> = ids.iter().cloned().collect();
@@ -790,9 +783,72 @@ mod tests {
         let mut stream = async_std::stream::from_iter(responses);
 
         let id = RequestId("A0003".into());
-        let mailbox = parse_mailbox(&mut stream, send, id).await;
+        let mailbox = parse_mailbox(&mut stream, send.into(), id).await;
         assert!(recv.is_empty());
 
-        assert!(matches!(mailbox, Err(Error::No(_))));
+        assert!(matches!(mailbox, Err(Error::No { .. })));
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn parse_mailbox_access_and_extensions() {
+        let (send, recv) = bounded(10);
+        let responses = input_stream(&[
+            "* 2 EXISTS\r\n",
+            "* NO [UIDNOTSTICKY] Non-persistent UIDs\r\n",
+            "* OK [NOMODSEQ] Sorry, this mailbox format doesn't support modsequences\r\n",
+            "* OK [MAILBOXID (F2212ea87-6097-4256-9d51-71338625)] Ok\r\n",
+            "A0001 OK [READ-WRITE] Select completed.\r\n",
+        ]);
+        let mut stream = async_std::stream::from_iter(responses);
+
+        let id = RequestId("A0001".into());
+        let mailbox = parse_mailbox(&mut stream, send.into(), id).await.unwrap();
+        assert!(recv.is_empty());
+
+        assert_eq!(mailbox.exists, 2);
+        assert_eq!(mailbox.access_mode, Some(AccessMode::ReadWrite));
+        assert!(mailbox.uid_not_sticky);
+        assert!(mailbox.no_modseq);
+        assert_eq!(
+            mailbox.mailbox_id.as_deref(),
+            Some("F2212ea87-6097-4256-9d51-71338625")
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn parse_mailbox_alert_and_notice() {
+        let (send, recv) = bounded(10);
+        let responses = input_stream(&[
+            "* 7 EXISTS\r\n",
+            "* OK [UIDVALIDITY 9] UIDs valid\r\n",
+            "* OK [CLOSED] Previous mailbox closed\r\n",
+            "* 3 EXISTS\r\n",
+            "* OK [ALERT] Mailbox will be migrated tonight\r\n",
+            "A0001 OK [HIGHESTMODSEQ 42] Select completed.\r\n",
+        ]);
+        let mut stream = async_std::stream::from_iter(responses);
+
+        let id = RequestId("A0001".into());
+        let mailbox = parse_mailbox(&mut stream, send.into(), id).await.unwrap();
+        assert_eq!(mailbox.exists, 3);
+        assert_eq!(mailbox.uid_validity, None);
+        assert_eq!(mailbox.highest_modseq, Some(42));
+
+        match recv.recv().await.unwrap() {
+            UnsolicitedResponse::Notice { code, text } => {
+                assert_eq!(code, Some(ResponseCode::Closed));
+                assert_eq!(text, "Previous mailbox closed");
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+        match recv.recv().await.unwrap() {
+            UnsolicitedResponse::Alert(text) => {
+                assert_eq!(text, "Mailbox will be migrated tonight")
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+        assert!(recv.is_empty());
     }
 }
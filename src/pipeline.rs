@@ -0,0 +1,563 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+#[cfg(feature = "runtime-async-std")]
+use async_std::io::{Read, Write};
+use imap_proto::{AttributeValue, MailboxDatum, RequestId, Response};
+#[cfg(feature = "runtime-tokio")]
+use tokio::io::{AsyncRead as Read, AsyncWrite as Write};
+
+use crate::client::{validate_str, Session};
+use crate::error::{Error, ParseError, Result};
+use crate::parse::{apply_status_attributes, handle_unilateral};
+use crate::types::{Fetch, Mailbox};
+
+/// A batch of independent commands that are sent to the server at once, see
+/// [`Session::pipeline`].
+///
+/// Only commands whose responses can be told apart are supported: `STATUS`, `FETCH` and
+/// `STORE`, with or without `UID`. Following
+/// [RFC 3501 section 5.5](https://tools.ietf.org/html/rfc3501#section-5.5), [`Pipeline::run`]
+/// refuses batches whose result would depend on the order in which the server executes them:
+///
+/// - a `STORE` together with any other `FETCH` or `STORE`, as the flags returned depend on
+///   whether the `STORE` ran first,
+/// - commands using sequence numbers together with `UID` commands, as the server may send
+///   `EXPUNGE` responses during the latter, which renumber the messages,
+/// - `FETCH` commands whose sets overlap, as the `FETCH` responses are assigned to the commands
+///   by their sequence number or, for `UID FETCH`, their UID.
+#[must_use = "the commands are only sent by `Pipeline::run`"]
+pub struct Pipeline<'a, T: Read + Write + Unpin + fmt::Debug + Send> {
+    session: &'a mut Session<T>,
+    commands: Vec<PipelinedCommand>,
+}
+
+#[derive(Debug)]
+enum PipelinedCommand {
+    Status {
+        mailbox: String,
+        data_items: String,
+    },
+    Fetch {
+        uid: bool,
+        set: String,
+        query: String,
+    },
+    Store {
+        uid: bool,
+        set: String,
+        query: String,
+    },
+}
+
+impl PipelinedCommand {
+    fn to_command(&self) -> Result<String> {
+        let uid_prefix = |uid: bool| if uid { "UID " } else { "" };
+        Ok(match self {
+            PipelinedCommand::Status {
+                mailbox,
+                data_items,
+            } => format!("STATUS {} {}", validate_str(mailbox)?, data_items),
+            PipelinedCommand::Fetch { uid, set, query } => {
+                format!("{}FETCH {} {}", uid_prefix(*uid), set, query)
+            }
+            PipelinedCommand::Store { uid, set, query } => {
+                format!("{}STORE {} {}", uid_prefix(*uid), set, query)
+            }
+        })
+    }
+
+    fn uid(&self) -> Option<bool> {
+        match self {
+            PipelinedCommand::Status { .. } => None,
+            PipelinedCommand::Fetch { uid, .. } | PipelinedCommand::Store { uid, .. } => Some(*uid),
+        }
+    }
+
+    /// The sequence numbers or UIDs of a `FETCH` or `STORE` command, `None` if they are unknown,
+    /// e.g. for the `$` of [RFC 5182](https://tools.ietf.org/html/rfc5182).
+    fn set(&self) -> Option<Vec<RangeInclusive<u32>>> {
+        match self {
+            PipelinedCommand::Status { .. } => None,
+            PipelinedCommand::Fetch { set, .. } | PipelinedCommand::Store { set, .. } => {
+                parse_set(set)
+            }
+        }
+    }
+
+    /// Whether the `FETCH` response for the message with `seq` and `uid` belongs to this command.
+    fn wants_fetch(&self, seq: u32, uid: Option<u32>) -> bool {
+        let number = match self.uid() {
+            None => return false,
+            Some(true) => match uid {
+                Some(uid) => uid,
+                // The server includes the UID in every response to a `UID` command.
+                None => return false,
+            },
+            Some(false) => seq,
+        };
+        // Without a known set, the command is the only one that fetches.
+        self.set()
+            .is_none_or(|set| set.iter().any(|range| range.contains(&number)))
+    }
+
+    fn empty_response(&self) -> PipelineResponse {
+        match self {
+            PipelinedCommand::Status { .. } => PipelineResponse::Status(Mailbox::default()),
+            PipelinedCommand::Fetch { .. } | PipelinedCommand::Store { .. } => {
+                PipelineResponse::Fetches(Vec::new())
+            }
+        }
+    }
+}
+
+/// The result of a command sent with a [`Pipeline`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum PipelineResponse {
+    /// The result of [`Pipeline::status`], see [`Session::status`].
+    Status(Mailbox),
+    /// The messages returned by [`Pipeline::fetch`], [`Pipeline::uid_fetch`], [`Pipeline::store`]
+    /// or [`Pipeline::uid_store`].
+    Fetches(Vec<Fetch>),
+}
+
+impl<'a, T: Read + Write + Unpin + fmt::Debug + Send> Pipeline<'a, T> {
+    pub(crate) fn new(session: &'a mut Session<T>) -> Self {
+        Pipeline {
+            session,
+            commands: Vec::new(),
+        }
+    }
+
+    /// Adds a `STATUS` command, see [`Session::status`].
+    pub fn status<S1: AsRef<str>, S2: AsRef<str>>(
+        mut self,
+        mailbox_name: S1,
+        data_items: S2,
+    ) -> Self {
+        self.commands.push(PipelinedCommand::Status {
+            mailbox: mailbox_name.as_ref().to_string(),
+            data_items: data_items.as_ref().to_string(),
+        });
+        self
+    }
+
+    /// Adds a `FETCH` command, see [`Session::fetch`].
+    pub fn fetch<S1: AsRef<str>, S2: AsRef<str>>(mut self, sequence_set: S1, query: S2) -> Self {
+        self.commands.push(PipelinedCommand::Fetch {
+            uid: false,
+            set: sequence_set.as_ref().to_string(),
+            query: query.as_ref().to_string(),
+        });
+        self
+    }
+
+    /// Adds a `UID FETCH` command, see [`Session::uid_fetch`].
+    pub fn uid_fetch<S1: AsRef<str>, S2: AsRef<str>>(mut self, uid_set: S1, query: S2) -> Self {
+        self.commands.push(PipelinedCommand::Fetch {
+            uid: true,
+            set: uid_set.as_ref().to_string(),
+            query: query.as_ref().to_string(),
+        });
+        self
+    }
+
+    /// Adds a `STORE` command, see [`Session::store`].
+    pub fn store<S1: AsRef<str>, S2: AsRef<str>>(mut self, sequence_set: S1, query: S2) -> Self {
+        self.commands.push(PipelinedCommand::Store {
+            uid: false,
+            set: sequence_set.as_ref().to_string(),
+            query: query.as_ref().to_string(),
+        });
+        self
+    }
+
+    /// Adds a `UID STORE` command, see [`Session::uid_store`].
+    pub fn uid_store<S1: AsRef<str>, S2: AsRef<str>>(mut self, uid_set: S1, query: S2) -> Self {
+        self.commands.push(PipelinedCommand::Store {
+            uid: true,
+            set: uid_set.as_ref().to_string(),
+            query: query.as_ref().to_string(),
+        });
+        self
+    }
+
+    /// Checks that the commands can be executed in any order.
+    fn check_ambiguity(&self) -> Result<()> {
+        let message_commands = self
+            .commands
+            .iter()
+            .filter(|command| command.uid().is_some())
+            .count();
+        let has_store = self
+            .commands
+            .iter()
+            .any(|command| matches!(command, PipelinedCommand::Store { .. }));
+        if has_store && message_commands > 1 {
+            return Err(Error::Pipeline(
+                "STORE can not be pipelined with other FETCH or STORE commands".to_string(),
+            ));
+        }
+
+        let uid = self.commands.iter().filter_map(PipelinedCommand::uid);
+        if uid.clone().any(|uid| uid) && uid.clone().any(|uid| !uid) {
+            return Err(Error::Pipeline(
+                "UID commands can not be pipelined with commands using sequence numbers"
+                    .to_string(),
+            ));
+        }
+
+        if message_commands > 1 {
+            let mut ranges = Vec::new();
+            for command in self.commands.iter().filter(|c| c.uid().is_some()) {
+                let set = command.set().ok_or_else(|| {
+                    Error::Pipeline(format!(
+                        "the set of {:?} is needed to tell its responses apart",
+                        command
+                    ))
+                })?;
+                for range in set {
+                    if ranges.iter().any(|other: &RangeInclusive<u32>| {
+                        range.start() <= other.end() && other.start() <= range.end()
+                    }) {
+                        return Err(Error::Pipeline(
+                            "FETCH commands with overlapping sets can not be pipelined".to_string(),
+                        ));
+                    }
+                    ranges.push(range);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends all commands, then reads the responses until every command is completed.
+    ///
+    /// Returns the result of each command in the order in which they were added. The outer
+    /// error is returned if the batch is refused, see [`Pipeline`], in which case nothing is
+    /// sent, or if reading the responses fails. Unsolicited responses are handled as for any
+    /// other command. A `STATUS` command that completes without a `STATUS` response for its
+    /// mailbox fails with [`ParseError::ExpectedResponseNotFound`].
+    pub async fn run(self) -> Result<Vec<Result<PipelineResponse>>> {
+        self.check_ambiguity()?;
+        let commands = self
+            .commands
+            .iter()
+            .map(PipelinedCommand::to_command)
+            .collect::<Result<Vec<_>>>()?;
+
+        self.session.finish_pending().await?;
+        let mut tags: Vec<RequestId> = Vec::with_capacity(commands.len());
+        for (command, pipelined) in commands.iter().zip(&self.commands) {
+            let tag = self.session.conn.run_command(command).await?;
+            if let PipelinedCommand::Fetch { uid, set, .. }
+            | PipelinedCommand::Store { uid, set, .. } = pipelined
+            {
+                self.session.conn.expect_fetches(&tag, *uid, set);
+            }
+            tags.push(tag);
+        }
+
+        let mut results: Vec<Option<Result<PipelineResponse>>> =
+            self.commands.iter().map(|_| None).collect();
+        let mut responses: Vec<PipelineResponse> = self
+            .commands
+            .iter()
+            .map(PipelinedCommand::empty_response)
+            .collect();
+        // Whether a `STATUS` command received its untagged `STATUS` response.
+        let mut answered = vec![false; self.commands.len()];
+        let unsolicited = self.session.unsolicited_responses_tx.clone();
+
+        while results.iter().any(Option::is_none) {
+            let conn = &mut self.session.conn;
+            let resp = conn.next_response().await?;
+            // Untagged data belongs to the outstanding command that asked for it.
+            let outstanding = |index: &usize| results[*index].is_none();
+            let target = match resp.parsed() {
+                Response::Done {
+                    tag,
+                    status,
+                    code,
+                    information,
+                } => match tags.iter().position(|t| t == tag) {
+                    Some(index) if results[index].is_none() => {
+                        let result = conn
+                            .check_status_ok(status, code.as_ref(), information.as_deref())
+                            .and_then(|()| match &self.commands[index] {
+                                PipelinedCommand::Status { mailbox, .. } if !answered[index] => {
+                                    Err(Error::Parse(ParseError::ExpectedResponseNotFound(
+                                        format!("STATUS {}", mailbox),
+                                    )))
+                                }
+                                _ => Ok(std::mem::replace(
+                                    &mut responses[index],
+                                    self.commands[index].empty_response(),
+                                )),
+                            });
+                        results[index] = Some(result);
+                        continue;
+                    }
+                    _ => None,
+                },
+                Response::MailboxData(MailboxDatum::Status { mailbox, .. }) => (0..tags.len())
+                    .filter(outstanding)
+                    .find(|index| match &self.commands[*index] {
+                        PipelinedCommand::Status { mailbox: name, .. } => {
+                            same_mailbox(name, mailbox)
+                        }
+                        _ => false,
+                    }),
+                Response::Fetch(seq, attributes) => {
+                    let uid = attributes.iter().find_map(|attribute| match attribute {
+                        AttributeValue::Uid(uid) => Some(*uid),
+                        _ => None,
+                    });
+                    (0..tags.len())
+                        .filter(outstanding)
+                        .find(|index| self.commands[*index].wants_fetch(*seq, uid))
+                }
+                _ => None,
+            };
+
+            match (target.map(|index| &mut responses[index]), resp.parsed()) {
+                (
+                    Some(PipelineResponse::Status(mbox)),
+                    Response::MailboxData(MailboxDatum::Status { status, .. }),
+                ) => {
+                    answered[target.expect("routed to a command")] = true;
+                    apply_status_attributes(mbox, status)
+                }
+                (Some(PipelineResponse::Fetches(fetches)), Response::Fetch(..)) => {
+                    fetches.push(Fetch::new(resp))
+                }
+                _ => {
+                    if let Err(err) = handle_unilateral(resp, unsolicited.clone()).await {
+                        // The rest of the batch is read before the error is returned, so that
+                        // the session can be used again.
+                        self.session.finish_pending().await?;
+                        return Err(err);
+                    }
+                }
+            }
+        }
+
+        Ok(results.into_iter().flatten().collect())
+    }
+}
+
+/// Whether the mailbox names are the same, given that `INBOX` is case-insensitive.
+fn same_mailbox(a: &str, b: &str) -> bool {
+    a == b || (a.eq_ignore_ascii_case("INBOX") && b.eq_ignore_ascii_case("INBOX"))
+}
+
+/// Parses a sequence set such as `1,3:5,7:*`, where `*` is taken as the largest number possible.
+pub(crate) fn parse_set(set: &str) -> Option<Vec<RangeInclusive<u32>>> {
+    let number = |n: &str| match n {
+        "*" => Some(u32::MAX),
+        n => n.parse().ok(),
+    };
+    set.split(',')
+        .map(|member| match member.split_once(':') {
+            Some((a, b)) => {
+                let (a, b) = (number(a)?, number(b)?);
+                Some(a.min(b)..=a.max(b))
+            }
+            None => number(member).map(|n| n..=n),
+        })
+        .collect()
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send> fmt::Debug for Pipeline<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("commands", &self.commands)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock_session;
+    use crate::mock_stream::MockStream;
+    use crate::types::{Flag, UnsolicitedResponse};
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn pipeline() {
+        let response = b"* STATUS Sent (MESSAGES 3)\r\n\
+            * 1 FETCH (FLAGS (\\Seen))\r\n\
+            * 5 EXISTS\r\n\
+            A0002 NO [NONEXISTENT] No such mailbox\r\n\
+            * STATUS INBOX (MESSAGES 17 UNSEEN 2)\r\n\
+            * 2 FETCH (FLAGS ())\r\n\
+            A0003 OK Fetch completed\r\n\
+            A0001 OK Status completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let results = session
+            .pipeline()
+            .status("INBOX", "(MESSAGES UNSEEN)")
+            .status("Sent", "(MESSAGES)")
+            .fetch("1:2", "FLAGS")
+            .run()
+            .await
+            .unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 STATUS \"INBOX\" (MESSAGES UNSEEN)\r\n\
+            A0002 STATUS \"Sent\" (MESSAGES)\r\n\
+            A0003 FETCH 1:2 FLAGS\r\n"
+                .to_vec()
+        );
+
+        let mut results = results.into_iter();
+        match results.next().unwrap().unwrap() {
+            PipelineResponse::Status(mailbox) => {
+                assert_eq!(mailbox.exists, 17);
+                assert_eq!(mailbox.unseen, Some(2));
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+        // The untagged STATUS arrived before the command failed.
+        assert!(results.next().unwrap().unwrap_err().is_nonexistent());
+        match results.next().unwrap().unwrap() {
+            PipelineResponse::Fetches(fetches) => {
+                assert_eq!(
+                    fetches.iter().map(|f| f.message).collect::<Vec<_>>(),
+                    [1, 2]
+                );
+                assert_eq!(fetches[0].flags().collect::<Vec<_>>(), [Flag::Seen]);
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert!(results.next().is_none());
+        assert_eq!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Exists(5)
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn pipeline_status_names() {
+        let response = b"* STATUS inbox (MESSAGES 2)\r\n\
+            A0001 OK Status completed\r\n\
+            * STATUS Archive (MESSAGES 4)\r\n\
+            A0002 OK Status completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let results = session
+            .pipeline()
+            .status("INBOX", "(MESSAGES)")
+            .status("archive", "(MESSAGES)")
+            .run()
+            .await
+            .unwrap();
+        let mut results = results.into_iter();
+        match results.next().unwrap().unwrap() {
+            PipelineResponse::Status(mailbox) => assert_eq!(mailbox.exists, 2),
+            other => panic!("unexpected response {:?}", other),
+        }
+        // Other mailbox names are case-sensitive, so the response is not taken as the result.
+        assert!(matches!(
+            results.next().unwrap(),
+            Err(Error::Parse(ParseError::ExpectedResponseNotFound(_)))
+        ));
+        assert!(matches!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Status { mailbox, .. } if mailbox == "Archive"
+        ));
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn pipeline_ambiguous() {
+        let mut session = mock_session!(MockStream::new(Vec::new()));
+        let store_and_fetch = session
+            .pipeline()
+            .store("1", "+FLAGS (\\Seen)")
+            .fetch("1", "FLAGS")
+            .run()
+            .await;
+        assert!(matches!(store_and_fetch, Err(Error::Pipeline(_))));
+
+        let uid_and_seq = session
+            .pipeline()
+            .uid_fetch("10", "FLAGS")
+            .fetch("1", "FLAGS")
+            .run()
+            .await;
+        assert!(matches!(uid_and_seq, Err(Error::Pipeline(_))));
+
+        let overlapping = session
+            .pipeline()
+            .fetch("1:3", "FLAGS")
+            .fetch("2", "FLAGS")
+            .run()
+            .await;
+        assert!(matches!(overlapping, Err(Error::Pipeline(_))));
+
+        assert!(session.stream.inner.written_buf.is_empty());
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn pipeline_fetches() {
+        let response = b"* 3 FETCH (FLAGS ())\r\n\
+            * 1 FETCH (FLAGS (\\Seen))\r\n\
+            * 7 FETCH (FLAGS (\\Deleted))\r\n\
+            A0001 OK Fetch completed\r\n\
+            * 2 FETCH (FLAGS (\\Flagged))\r\n\
+            A0002 OK Fetch completed\r\n\
+            * 1 FETCH (UID 12 FLAGS ())\r\n\
+            * 4 FETCH (UID 3 FLAGS (\\Seen))\r\n\
+            A0004 OK Fetch completed\r\n\
+            A0003 OK Fetch completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let results = session
+            .pipeline()
+            .fetch("1", "FLAGS")
+            .fetch("2:3", "FLAGS")
+            .run()
+            .await
+            .unwrap();
+        let messages = results
+            .into_iter()
+            .map(|result| match result.unwrap() {
+                PipelineResponse::Fetches(fetches) => {
+                    fetches.iter().map(|f| f.message).collect::<Vec<_>>()
+                }
+                other => panic!("unexpected response {:?}", other),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(messages, [vec![1], vec![3, 2]]);
+        // Neither command asked for message 7.
+        assert!(matches!(
+            session.unsolicited_responses.try_recv().unwrap(),
+            UnsolicitedResponse::Other(_)
+        ));
+
+        let results = session
+            .pipeline()
+            .uid_fetch("1:5", "FLAGS")
+            .uid_fetch("10:*", "FLAGS")
+            .run()
+            .await
+            .unwrap();
+        let uids = results
+            .into_iter()
+            .map(|result| match result.unwrap() {
+                PipelineResponse::Fetches(fetches) => {
+                    fetches.iter().map(|f| f.uid.unwrap()).collect::<Vec<_>>()
+                }
+                other => panic!("unexpected response {:?}", other),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(uids, [vec![3], vec![12]]);
+        assert!(session.unsolicited_responses.try_recv().is_err());
+    }
+}
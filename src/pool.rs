@@ -0,0 +1,380 @@
+use std::fmt;
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_channel as channel;
+#[cfg(feature = "runtime-async-std")]
+use async_std::io::{Read, Write};
+use futures::future::{BoxFuture, FutureExt};
+#[cfg(feature = "runtime-tokio")]
+use tokio::io::{AsyncRead as Read, AsyncWrite as Write};
+
+use crate::client::Session;
+use crate::error::{Error, Result};
+
+type Factory<T> = Box<dyn Fn() -> BoxFuture<'static, Result<Session<T>>> + Send + Sync>;
+
+/// Settings of a [`SessionPool`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// The maximum number of sessions, idle or checked out. [`SessionPool::get`] waits while
+    /// all of them are checked out.
+    pub max_connections: usize,
+    /// Idle sessions that were not used for this long are closed.
+    pub idle_timeout: Duration,
+    /// Idle sessions that were not used for this long are checked with a `NOOP` before they
+    /// are handed out again.
+    pub health_check_after: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_connections: 4,
+            idle_timeout: Duration::from_secs(5 * 60),
+            health_check_after: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A pool of authenticated sessions to the same account, for serving concurrent requests.
+///
+/// Sessions are created on demand by a user-supplied factory, which connects and logs in, up to
+/// [`PoolConfig::max_connections`]. Sessions that are returned to the pool are reused, after a
+/// health check if they were idle for a while, and closed once they were idle for too long.
+///
+/// ```ignore
+/// let pool = SessionPool::new(PoolConfig::default(), || async {
+///     let tcp_stream = TcpStream::connect(("imap.example.org", 143)).await?;
+///     let client = Client::new(tcp_stream);
+///     // ... read the greeting, STARTTLS, ...
+///     client.login("alice", "password").await.map_err(|(err, _client)| err)
+/// });
+///
+/// let mut session = pool.get().await?;
+/// session.select("INBOX").await?;
+/// ```
+///
+/// Cloning the pool is cheap, all clones share the same sessions.
+pub struct SessionPool<T: Read + Write + Unpin + fmt::Debug + Send + 'static> {
+    inner: Arc<PoolInner<T>>,
+}
+
+struct PoolInner<T: Read + Write + Unpin + fmt::Debug + Send + 'static> {
+    factory: Factory<T>,
+    config: PoolConfig,
+    idle: Mutex<Vec<IdleSession<T>>>,
+    /// Holds one token per session that can still be checked out.
+    permits: (channel::Sender<()>, channel::Receiver<()>),
+}
+
+struct IdleSession<T: Read + Write + Unpin + fmt::Debug + Send + 'static> {
+    session: Session<T>,
+    selected: Option<String>,
+    since: Instant,
+}
+
+/// A session checked out from a [`SessionPool`], returned to it when dropped.
+///
+/// It dereferences to the [`Session`]. Mailboxes should be selected with
+/// [`PooledSession::select`], which skips selecting the mailbox that is already selected.
+/// Selecting, examining or closing a mailbox through the session directly bypasses this, so
+/// [`PooledSession::forget_selected`] has to be called afterwards.
+pub struct PooledSession<T: Read + Write + Unpin + fmt::Debug + Send + 'static> {
+    session: Option<Session<T>>,
+    selected: Option<String>,
+    pool: Arc<PoolInner<T>>,
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send + 'static> SessionPool<T> {
+    /// Creates an empty pool. `factory` is called whenever a new session is needed.
+    pub fn new<F, Fut>(config: PoolConfig, factory: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Session<T>>> + Send + 'static,
+    {
+        let permits = channel::bounded(config.max_connections.max(1));
+        for _ in 0..config.max_connections.max(1) {
+            permits
+                .0
+                .try_send(())
+                .expect("channel has room for all permits");
+        }
+        SessionPool {
+            inner: Arc::new(PoolInner {
+                factory: Box::new(move || factory().boxed()),
+                config,
+                idle: Mutex::new(Vec::new()),
+                permits,
+            }),
+        }
+    }
+
+    /// Checks out a session, waiting while all sessions are in use.
+    ///
+    /// The most recently used idle session is preferred. A new session is created if there is
+    /// none, or if all idle sessions failed the health check.
+    pub async fn get(&self) -> Result<PooledSession<T>> {
+        let inner = &self.inner;
+        inner
+            .permits
+            .1
+            .recv()
+            .await
+            .map_err(|_| Error::ConnectionLost)?;
+        // Given back if checking out fails.
+        let mut permit = Permit(Some(inner.permits.0.clone()));
+
+        while let Some(idle) = inner.pop_idle() {
+            let mut session = idle.session;
+            if idle.since.elapsed() >= inner.config.health_check_after
+                && session.noop().await.is_err()
+            {
+                continue;
+            }
+            permit.0 = None;
+            return Ok(PooledSession {
+                session: Some(session),
+                selected: idle.selected,
+                pool: inner.clone(),
+            });
+        }
+
+        let session = (inner.factory)().await?;
+        permit.0 = None;
+        Ok(PooledSession {
+            session: Some(session),
+            selected: None,
+            pool: inner.clone(),
+        })
+    }
+
+    /// Closes the sessions that have been idle for longer than [`PoolConfig::idle_timeout`].
+    ///
+    /// This also happens when checking out a session, call it periodically to close idle
+    /// connections of a pool that is not used.
+    pub fn evict_idle(&self) {
+        let timeout = self.inner.config.idle_timeout;
+        self.inner
+            .lock_idle()
+            .retain(|idle| idle.since.elapsed() < timeout);
+    }
+
+    /// The number of sessions that are currently idle in the pool.
+    pub fn idle_count(&self) -> usize {
+        self.inner.lock_idle().len()
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send + 'static> PoolInner<T> {
+    fn lock_idle(&self) -> std::sync::MutexGuard<'_, Vec<IdleSession<T>>> {
+        self.idle.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Takes the most recently used session that has not timed out, closing the others.
+    fn pop_idle(&self) -> Option<IdleSession<T>> {
+        let mut idle = self.lock_idle();
+        let timeout = self.config.idle_timeout;
+        idle.retain(|idle| idle.since.elapsed() < timeout);
+        idle.pop()
+    }
+}
+
+/// Puts the token back into the pool when dropped.
+struct Permit(Option<channel::Sender<()>>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(permits) = self.0.take() {
+            let _ = permits.try_send(());
+        }
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send + 'static> PooledSession<T> {
+    /// Selects a mailbox, see [`Session::select`], unless it is already selected.
+    ///
+    /// The [`Mailbox`](crate::types::Mailbox) returned by the server is not passed on, as it
+    /// would be out of date once the selection is reused. Use [`Session::select`] directly,
+    /// followed by [`PooledSession::forget_selected`], where it is needed, or track the mailbox
+    /// with [`Session::set_track_selected_mailbox`].
+    pub async fn select<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<()> {
+        let mailbox_name = mailbox_name.as_ref();
+        if self.selected.as_deref() == Some(mailbox_name) {
+            return Ok(());
+        }
+
+        self.selected = None;
+        self.deref_mut().select(mailbox_name).await?;
+        self.selected = Some(mailbox_name.to_string());
+        Ok(())
+    }
+
+    /// The name of the mailbox selected with [`PooledSession::select`], if any.
+    pub fn selected(&self) -> Option<&str> {
+        self.selected.as_deref()
+    }
+
+    /// Forgets which mailbox is selected, so that the next [`PooledSession::select`] selects it
+    /// again.
+    pub fn forget_selected(&mut self) {
+        self.selected = None;
+    }
+
+    /// Closes the session instead of returning it to the pool, e.g. after an error that left it
+    /// in an unknown state.
+    pub fn discard(mut self) {
+        self.session = None;
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send + 'static> Drop for PooledSession<T> {
+    fn drop(&mut self) {
+        if let Some(session) = self.session.take() {
+            // A session the server said goodbye to, that timed out or whose connection failed is
+            // of no use anymore.
+            if session.conn.bye.is_none() && !session.conn.poisoned && !session.conn.failed {
+                self.pool.lock_idle().push(IdleSession {
+                    session,
+                    selected: self.selected.take(),
+                    since: Instant::now(),
+                });
+            }
+        }
+        let _ = self.pool.permits.0.try_send(());
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send + 'static> Deref for PooledSession<T> {
+    type Target = Session<T>;
+
+    fn deref(&self) -> &Session<T> {
+        self.session
+            .as_ref()
+            .expect("session is only taken on drop")
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send + 'static> DerefMut for PooledSession<T> {
+    fn deref_mut(&mut self) -> &mut Session<T> {
+        self.session
+            .as_mut()
+            .expect("session is only taken on drop")
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send + 'static> Clone for SessionPool<T> {
+    fn clone(&self) -> Self {
+        SessionPool {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send + 'static> fmt::Debug for SessionPool<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionPool")
+            .field("config", &self.inner.config)
+            .field("idle", &self.idle_count())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send + 'static> fmt::Debug for PooledSession<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledSession")
+            .field("session", &self.session)
+            .field("selected", &self.selected())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock_session;
+    use crate::mock_stream::MockStream;
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn session_pool() {
+        let responses = [
+            b"* 3 EXISTS\r\n\
+            A0001 OK [READ-WRITE] Select completed\r\n\
+            A0002 OK Noop completed\r\n"
+                .to_vec(),
+            Vec::new(),
+        ];
+        let sessions = std::sync::Mutex::new(
+            responses
+                .into_iter()
+                .map(|response| mock_session!(MockStream::new(response)))
+                .collect::<Vec<_>>(),
+        );
+        let config = PoolConfig {
+            max_connections: 1,
+            health_check_after: Duration::ZERO,
+            ..PoolConfig::default()
+        };
+        let pool = SessionPool::new(config, move || {
+            let session = sessions.lock().unwrap().remove(0);
+            async move { Ok(session) }
+        });
+
+        let mut session = pool.get().await.unwrap();
+        session.select("INBOX").await.unwrap();
+        assert!(pool.get().now_or_never().is_none());
+        drop(session);
+        assert_eq!(pool.idle_count(), 1);
+
+        // The idle session is checked and reused, with INBOX still selected.
+        let mut session = pool.get().await.unwrap();
+        assert_eq!(session.selected(), Some("INBOX"));
+        session.select("INBOX").await.unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 SELECT \"INBOX\"\r\nA0002 NOOP\r\n".to_vec()
+        );
+
+        // A session failing the health check is replaced.
+        drop(session);
+        let session = pool.get().await.unwrap();
+        assert_eq!(session.selected(), None);
+        assert!(session.stream.inner.written_buf.is_empty());
+        session.discard();
+        assert_eq!(pool.idle_count(), 0);
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn failed_session_not_reused() {
+        let sessions = std::sync::Mutex::new(vec![
+            mock_session!(MockStream::default()),
+            mock_session!(MockStream::new(b"A0001 OK Noop completed\r\n".to_vec())),
+        ]);
+        let config = PoolConfig {
+            max_connections: 1,
+            ..PoolConfig::default()
+        };
+        let pool = SessionPool::new(config, move || {
+            let session = sessions.lock().unwrap().remove(0);
+            async move { Ok(session) }
+        });
+
+        // The connection is closed before the command completes.
+        let mut session = pool.get().await.unwrap();
+        assert!(matches!(
+            session.noop().await,
+            Err(Error::ConnectionLost) | Err(Error::Io(_))
+        ));
+        drop(session);
+        assert_eq!(pool.idle_count(), 0);
+
+        let mut session = pool.get().await.unwrap();
+        session.noop().await.unwrap();
+        drop(session);
+        assert_eq!(pool.idle_count(), 1);
+    }
+}
@@ -0,0 +1,548 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use async_channel as channel;
+#[cfg(feature = "runtime-async-std")]
+use async_std::io::{Read, Write};
+use futures::future::{BoxFuture, FutureExt};
+use futures::TryStreamExt;
+#[cfg(feature = "runtime-tokio")]
+use tokio::io::{AsyncRead as Read, AsyncWrite as Write};
+
+use crate::client::Session;
+use crate::error::{Error, Result};
+use crate::runtime::sleep;
+use crate::types::{Fetch, Mailbox, Uid, UnsolicitedResponse};
+
+type Factory<T> = Box<dyn Fn() -> BoxFuture<'static, Result<Session<T>>> + Send + Sync>;
+
+/// Settings of a [`ReconnectingSession`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReconnectConfig {
+    /// The delay before the second attempt to reconnect. The first attempt is made right away.
+    pub initial_backoff: Duration,
+    /// The delay between attempts doubles up to this value.
+    pub max_backoff: Duration,
+    /// How often to try reconnecting and running an idempotent command before giving up and
+    /// returning the error. Connecting and running the command share this budget, so the server
+    /// is contacted at most this often for each command.
+    pub max_attempts: u32,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            max_attempts: 8,
+        }
+    }
+}
+
+/// Sent on [`ReconnectingSession::reconnect_events`] after the session was re-established.
+///
+/// Anything derived from the previous connection, like sequence numbers, may be outdated and
+/// should be synchronized again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ReconnectEvent {
+    /// The number of attempts it took to reconnect.
+    pub attempts: u32,
+    /// The mailbox that was selected again, as returned by `SELECT` or `EXAMINE`.
+    pub mailbox: Option<Mailbox>,
+}
+
+/// A [`Session`] that reconnects when the connection is lost.
+///
+/// Sessions are created by a user-supplied factory, which connects and logs in. When a command
+/// fails with a transport error, i.e. [`Error::Io`], [`Error::ConnectionLost`], [`Error::Bye`]
+/// or [`Error::Poisoned`], the session is dropped and the next command reconnects with exponential
+/// backoff, see [`ReconnectConfig`]. Extensions enabled with [`ReconnectingSession::enable`]
+/// and the mailbox selected with [`ReconnectingSession::select`] or
+/// [`ReconnectingSession::examine`] are restored, and a [`ReconnectEvent`] is sent.
+///
+/// Only commands run with [`ReconnectingSession::run_idempotent`], and the methods using it,
+/// are retried after reconnecting. Other commands, e.g. `APPEND` or `EXPUNGE`, may have been
+/// executed before the connection was lost, so they are run with
+/// [`ReconnectingSession::run`], which returns the error instead.
+///
+/// ```ignore
+/// let mut session = ReconnectingSession::new(ReconnectConfig::default(), || async {
+///     let tcp_stream = TcpStream::connect(("imap.example.org", 143)).await?;
+///     let client = Client::new(tcp_stream);
+///     // ... read the greeting, STARTTLS, ...
+///     client.login("alice", "password").await.map_err(|(err, _client)| err)
+/// });
+/// session.select("INBOX").await?;
+/// ```
+pub struct ReconnectingSession<T: Read + Write + Unpin + fmt::Debug + Send + 'static> {
+    factory: Factory<T>,
+    config: ReconnectConfig,
+    session: Option<Session<T>>,
+    /// Whether a session was established before, so that the next one is a reconnect.
+    connected_before: bool,
+    enabled: Vec<String>,
+    /// The selected mailbox and whether it was examined.
+    selected: Option<(String, bool)>,
+    events: (
+        channel::Sender<ReconnectEvent>,
+        channel::Receiver<ReconnectEvent>,
+    ),
+}
+
+fn is_transport_error(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::Io(_) | Error::ConnectionLost | Error::Bye { .. } | Error::Poisoned
+    )
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send + 'static> ReconnectingSession<T> {
+    /// Creates a session that connects with `factory` when the first command is run.
+    pub fn new<F, Fut>(config: ReconnectConfig, factory: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Session<T>>> + Send + 'static,
+    {
+        ReconnectingSession {
+            factory: Box::new(move || factory().boxed()),
+            config,
+            session: None,
+            connected_before: false,
+            enabled: Vec::new(),
+            selected: None,
+            events: channel::unbounded(),
+        }
+    }
+
+    /// Events sent each time the session reconnected.
+    pub fn reconnect_events(&self) -> &channel::Receiver<ReconnectEvent> {
+        &self.events.1
+    }
+
+    /// The unsolicited responses of the current session, see [`Session::unsolicited_responses`].
+    /// Each session has its own channel, so the receiver has to be fetched again after a
+    /// [`ReconnectEvent`].
+    pub fn unsolicited_responses(&self) -> Option<&channel::Receiver<UnsolicitedResponse>> {
+        self.session
+            .as_ref()
+            .map(|session| &session.unsolicited_responses)
+    }
+
+    /// Whether there is a session that has not failed.
+    pub fn is_connected(&self) -> bool {
+        self.session.is_some()
+    }
+
+    /// Returns the session, connecting first if there is none. `attempts` counts the attempts
+    /// made so far for the current command; connecting counts towards it, and an existing
+    /// session counts as one attempt.
+    async fn connected(&mut self, attempts: &mut u32) -> Result<&mut Session<T>> {
+        if self.session.is_none() {
+            self.reconnect(attempts).await?;
+        } else {
+            *attempts += 1;
+        }
+        Ok(self.session.as_mut().expect("just connected"))
+    }
+
+    async fn reconnect(&mut self, attempts: &mut u32) -> Result<()> {
+        let mut backoff = self.config.initial_backoff;
+        let started = *attempts;
+        let (session, mailbox) = loop {
+            *attempts += 1;
+            match self.restore().await {
+                Ok(restored) => break restored,
+                Err(err) if err.is_auth_failure() || *attempts >= self.config.max_attempts => {
+                    return Err(err)
+                }
+                Err(_) => {
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.config.max_backoff);
+                }
+            }
+        };
+        self.session = Some(session);
+        if self.connected_before {
+            let attempts = *attempts - started;
+            let _ = self.events.0.try_send(ReconnectEvent { attempts, mailbox });
+        }
+        self.connected_before = true;
+        Ok(())
+    }
+
+    /// Creates a new session with the extensions enabled and the mailbox selected.
+    async fn restore(&self) -> Result<(Session<T>, Option<Mailbox>)> {
+        let mut session = (self.factory)().await?;
+        if !self.enabled.is_empty() {
+            session
+                .run_command_and_check_ok(format!("ENABLE {}", self.enabled.join(" ")))
+                .await?;
+        }
+        let mailbox = match &self.selected {
+            Some((name, false)) => Some(session.select(name).await?),
+            Some((name, true)) => Some(session.examine(name).await?),
+            None => None,
+        };
+        Ok((session, mailbox))
+    }
+
+    /// Runs `f` on the session, connecting first if needed. The command is not retried; if it
+    /// fails with a transport error, the next command reconnects.
+    pub async fn run<R, F>(&mut self, f: F) -> Result<R>
+    where
+        F: for<'a> FnOnce(&'a mut Session<T>) -> BoxFuture<'a, Result<R>>,
+    {
+        let session = self.connected(&mut 0).await?;
+        let result = f(session).await;
+        if result.as_ref().is_err_and(is_transport_error) {
+            self.session = None;
+        }
+        result
+    }
+
+    /// Runs `f` on the session, reconnecting and running it again if it fails with a transport
+    /// error. `f` must be safe to run more than once, e.g. `FETCH` or `STATUS`.
+    ///
+    /// ```ignore
+    /// let fetches = session
+    ///     .run_idempotent(|session| {
+    ///         async move {
+    ///             let fetches = session.uid_fetch("1:*", "FLAGS").await?;
+    ///             fetches.try_collect::<Vec<_>>().await
+    ///         }
+    ///         .boxed()
+    ///     })
+    ///     .await?;
+    /// ```
+    pub async fn run_idempotent<R, F>(&mut self, mut f: F) -> Result<R>
+    where
+        F: for<'a> FnMut(&'a mut Session<T>) -> BoxFuture<'a, Result<R>>,
+    {
+        let mut attempts = 0;
+        loop {
+            let session = self.connected(&mut attempts).await?;
+            match f(session).await {
+                Err(err) if is_transport_error(&err) => {
+                    self.session = None;
+                    if attempts >= self.config.max_attempts {
+                        return Err(err);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Enables extensions, see [RFC 5161](https://tools.ietf.org/html/rfc5161). They are
+    /// enabled again after reconnecting.
+    pub async fn enable<S: AsRef<str>>(&mut self, extensions: &[S]) -> Result<()> {
+        let extensions: Vec<String> = extensions.iter().map(|e| e.as_ref().to_string()).collect();
+        let command = format!("ENABLE {}", extensions.join(" "));
+        self.run_idempotent(move |session| {
+            let command = command.clone();
+            async move { session.run_command_and_check_ok(command).await }.boxed()
+        })
+        .await?;
+        for extension in extensions {
+            if !self.enabled.contains(&extension) {
+                self.enabled.push(extension);
+            }
+        }
+        Ok(())
+    }
+
+    /// Selects a mailbox, see [`Session::select`]. It is selected again after reconnecting.
+    pub async fn select<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<Mailbox> {
+        self.select_or_examine(mailbox_name.as_ref(), false).await
+    }
+
+    /// Examines a mailbox, see [`Session::examine`]. It is examined again after reconnecting.
+    pub async fn examine<S: AsRef<str>>(&mut self, mailbox_name: S) -> Result<Mailbox> {
+        self.select_or_examine(mailbox_name.as_ref(), true).await
+    }
+
+    async fn select_or_examine(&mut self, mailbox_name: &str, examine: bool) -> Result<Mailbox> {
+        // A failed `SELECT` or `EXAMINE` leaves no mailbox selected.
+        self.selected = None;
+        let name = mailbox_name.to_string();
+        let mailbox = self
+            .run_idempotent(move |session| {
+                let name = name.clone();
+                async move {
+                    if examine {
+                        session.examine(name).await
+                    } else {
+                        session.select(name).await
+                    }
+                }
+                .boxed()
+            })
+            .await?;
+        self.selected = Some((mailbox_name.to_string(), examine));
+        Ok(mailbox)
+    }
+
+    /// Closes the selected mailbox, see [`Session::close`].
+    pub async fn close(&mut self) -> Result<()> {
+        self.selected = None;
+        self.run(|session| session.close().boxed()).await
+    }
+
+    /// Closes the selected mailbox without expunging it, see [`Session::unselect`].
+    pub async fn unselect(&mut self) -> Result<()> {
+        self.selected = None;
+        self.run(|session| session.unselect().boxed()).await
+    }
+
+    /// See [`Session::noop`].
+    pub async fn noop(&mut self) -> Result<()> {
+        self.run_idempotent(|session| session.noop().boxed()).await
+    }
+
+    /// See [`Session::status`].
+    pub async fn status<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        mailbox_name: S1,
+        data_items: S2,
+    ) -> Result<Mailbox> {
+        let command = (
+            mailbox_name.as_ref().to_string(),
+            data_items.as_ref().to_string(),
+        );
+        self.run_idempotent(move |session| {
+            let (mailbox_name, data_items) = command.clone();
+            async move { session.status(mailbox_name, data_items).await }.boxed()
+        })
+        .await
+    }
+
+    /// See [`Session::uid_fetch`]. All messages are collected before they are returned.
+    pub async fn uid_fetch<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        uid_set: S1,
+        query: S2,
+    ) -> Result<Vec<Fetch>> {
+        let command = (uid_set.as_ref().to_string(), query.as_ref().to_string());
+        self.run_idempotent(move |session| {
+            let (uid_set, query) = command.clone();
+            async move { session.uid_fetch(uid_set, query).await?.try_collect().await }.boxed()
+        })
+        .await
+    }
+
+    /// Downloads a message, see [`Session::download_message`]. If the connection fails, it
+    /// reconnects and resumes the download where it stopped. The number of attempts is only
+    /// limited while no progress is made.
+    pub async fn download_message<W: Write + Unpin>(
+        &mut self,
+        uid: Uid,
+        mut writer: W,
+    ) -> Result<u64> {
+        let mut offset = 0;
+        let mut attempts = 0;
+        loop {
+            let session = self.connected(&mut attempts).await?;
+            let written = offset;
+            match session.download_from(uid, &mut offset, &mut writer).await {
+                Err(err) if is_transport_error(&err) => {
+                    self.session = None;
+                    if offset > written {
+                        attempts = 1;
+                    } else if attempts >= self.config.max_attempts {
+                        return Err(err);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// See [`Session::uid_search`].
+    pub async fn uid_search<S: AsRef<str>>(&mut self, query: S) -> Result<HashSet<Uid>> {
+        let query = query.as_ref().to_string();
+        self.run_idempotent(move |session| {
+            let query = query.clone();
+            async move { session.uid_search(query).await }.boxed()
+        })
+        .await
+    }
+
+    /// Logs out and drops the session, see [`Session::logout`]. The next command connects
+    /// again, without restoring any state.
+    pub async fn logout(&mut self) -> Result<()> {
+        self.enabled.clear();
+        self.selected = None;
+        self.connected_before = false;
+        match self.session.take() {
+            Some(mut session) => session.logout().await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send + 'static> fmt::Debug for ReconnectingSession<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReconnectingSession")
+            .field("config", &self.config)
+            .field("session", &self.session)
+            .field("enabled", &self.enabled)
+            .field("selected", &self.selected)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{mock_session, DOWNLOAD_CHUNK_SIZE};
+    use crate::mock_stream::MockStream;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn reconnecting_session() {
+        let responses = [
+            b"* ENABLED CONDSTORE\r\n\
+            A0001 OK Enabled\r\n\
+            * 3 EXISTS\r\n\
+            A0002 OK [READ-WRITE] Select completed\r\n"
+                .to_vec(),
+            b"* ENABLED CONDSTORE\r\n\
+            A0001 OK Enabled\r\n\
+            * 4 EXISTS\r\n\
+            A0002 OK [READ-WRITE] Select completed\r\n\
+            * 1 FETCH (UID 7)\r\n\
+            A0003 OK Fetch completed\r\n"
+                .to_vec(),
+        ];
+        let sessions = std::sync::Mutex::new(
+            responses
+                .into_iter()
+                .map(|response| mock_session!(MockStream::new(response)))
+                .collect::<Vec<_>>(),
+        );
+        let config = ReconnectConfig {
+            initial_backoff: Duration::ZERO,
+            ..ReconnectConfig::default()
+        };
+        let mut session = ReconnectingSession::new(config, move || {
+            let session = sessions.lock().unwrap().remove(0);
+            async move { Ok(session) }
+        });
+
+        session.enable(&["CONDSTORE"]).await.unwrap();
+        assert_eq!(session.select("INBOX").await.unwrap().exists, 3);
+        assert!(session.reconnect_events().is_empty());
+
+        // The first connection ends, the fetch is retried on a new one.
+        let fetches = session.uid_fetch("1:*", "UID").await.unwrap();
+        assert_eq!(fetches[0].uid, Some(7));
+        let event = session.reconnect_events().try_recv().unwrap();
+        assert_eq!(event.attempts, 1);
+        assert_eq!(event.mailbox.unwrap().exists, 4);
+
+        let written = session
+            .run(|session| async move { Ok(session.stream.inner.written_buf.clone()) }.boxed())
+            .await
+            .unwrap();
+        assert_eq!(
+            written,
+            b"A0001 ENABLE CONDSTORE\r\n\
+            A0002 SELECT \"INBOX\"\r\n\
+            A0003 UID FETCH 1:* UID\r\n"
+                .to_vec()
+        );
+
+        // Commands that are not idempotent are not retried.
+        let result = session
+            .run(|session| {
+                async move { session.expunge().await?.try_collect::<Vec<_>>().await }.boxed()
+            })
+            .await;
+        assert!(matches!(result, Err(Error::Io(_))));
+        assert!(!session.is_connected());
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn reconnecting_attempts() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let config = ReconnectConfig {
+            initial_backoff: Duration::ZERO,
+            max_attempts: 4,
+            ..ReconnectConfig::default()
+        };
+        let factory_calls = calls.clone();
+        let mut session = ReconnectingSession::new(config, move || {
+            // Every other connection fails, the others end right away.
+            let failed = factory_calls
+                .fetch_add(1, Ordering::SeqCst)
+                .is_multiple_of(2);
+            async move {
+                if failed {
+                    Err(Error::ConnectionLost)
+                } else {
+                    Ok(mock_session!(MockStream::new(Vec::new())))
+                }
+            }
+        });
+
+        let result = session.uid_fetch("1:*", "UID").await;
+        assert!(matches!(result, Err(Error::Io(_))));
+        // Connecting and fetching share the attempts.
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn reconnecting_download() {
+        let size = DOWNLOAD_CHUNK_SIZE as usize + 10;
+        let message = "x".repeat(size);
+        // The first connection ends after the first chunk.
+        let first = format!(
+            "* 1 FETCH (UID 7 RFC822.SIZE {size})\r\n\
+            A0001 OK Fetch completed\r\n\
+            * 1 FETCH (UID 7 BODY[]<0> {{{}}}\r\n{})\r\n\
+            A0002 OK Fetch completed\r\n",
+            DOWNLOAD_CHUNK_SIZE,
+            &message[..DOWNLOAD_CHUNK_SIZE as usize]
+        );
+        let second = format!(
+            "* 1 FETCH (UID 7 RFC822.SIZE {size})\r\n\
+            A0001 OK Fetch completed\r\n\
+            * 1 FETCH (UID 7 BODY[]<{}> {{10}}\r\nxxxxxxxxxx)\r\n\
+            A0002 OK Fetch completed\r\n",
+            DOWNLOAD_CHUNK_SIZE
+        );
+        let sessions = std::sync::Mutex::new(
+            [first, second]
+                .into_iter()
+                .map(|response| mock_session!(MockStream::new(response.into_bytes())))
+                .collect::<Vec<_>>(),
+        );
+        let mut session = ReconnectingSession::new(ReconnectConfig::default(), move || {
+            let session = sessions.lock().unwrap().remove(0);
+            async move { Ok(session) }
+        });
+
+        let mut downloaded = Vec::new();
+        let downloaded_size = session.download_message(7, &mut downloaded).await.unwrap();
+        assert_eq!(downloaded_size, size as u64);
+        assert_eq!(downloaded, message.as_bytes());
+
+        let written = session
+            .run(|session| async move { Ok(session.stream.inner.written_buf.clone()) }.boxed())
+            .await
+            .unwrap();
+        assert_eq!(
+            written,
+            b"A0001 UID FETCH 7 (UID RFC822.SIZE)\r\n\
+            A0002 UID FETCH 7 (UID BODY.PEEK[]<1048576.1048576>)\r\n"
+                .to_vec()
+        );
+    }
+}
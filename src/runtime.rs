@@ -0,0 +1,40 @@
+//! Helpers for the parts that differ between the supported runtimes.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// Waits for `duration` to pass.
+pub(crate) async fn sleep(duration: Duration) {
+    #[cfg(feature = "runtime-tokio")]
+    tokio::time::sleep(duration).await;
+
+    // `async_std::task::sleep` needs the default features of async-std, unlike `timeout`.
+    #[cfg(feature = "runtime-async-std")]
+    let _ = async_std::future::timeout(duration, futures::future::pending::<()>()).await;
+}
+
+/// A future that completes once `duration` has passed, for use in `poll` functions.
+pub(crate) struct Timer(Pin<Box<dyn Future<Output = ()> + Send + Sync>>);
+
+impl Timer {
+    pub(crate) fn new(duration: Duration) -> Self {
+        Timer(Box::pin(sleep(duration)))
+    }
+}
+
+impl Future for Timer {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.0.as_mut().poll(cx)
+    }
+}
+
+impl fmt::Debug for Timer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Timer").finish_non_exhaustive()
+    }
+}
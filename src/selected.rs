@@ -0,0 +1,378 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+#[cfg(feature = "runtime-async-std")]
+use async_std::io::{Read, Write};
+use futures::stream::Stream;
+#[cfg(feature = "runtime-tokio")]
+use tokio::io::{AsyncRead as Read, AsyncWrite as Write};
+
+use crate::client::Session;
+use crate::error::{Error, Result};
+use crate::extensions::idle::Handle;
+use crate::types::{AccessMode, Fetch, Mailbox, MailboxEvent, Seq, Uid};
+
+/// A [`Session`] in the selected state, returned by [`Session::into_selected`] and
+/// [`Session::into_examined`].
+///
+/// It provides the commands that operate on the messages of the selected mailbox, and hands back
+/// the authenticated [`Session`] when the mailbox is closed or unselected. Commands that are valid
+/// in both states, such as [`Session::status`] or [`Session::list`], are available through
+/// [`SelectedSession::session`].
+///
+/// ```ignore
+/// let mut inbox = session.into_selected("INBOX").await.map_err(|(err, _session)| err)?;
+/// let fetches: Vec<_> = inbox.uid_fetch("1:*", "FLAGS").await?.try_collect().await?;
+/// let session = inbox.close().await.map_err(|(err, _inbox)| err)?;
+/// ```
+pub struct SelectedSession<T: Read + Write + Unpin + fmt::Debug + Send> {
+    session: Session<T>,
+    name: String,
+    mailbox: Mailbox,
+}
+
+/// An `IDLE` started from a [`SelectedSession`], see [`SelectedSession::idle`].
+///
+/// It dereferences to the [`Handle`], and [`SelectedIdleHandle::done`] returns the
+/// [`SelectedSession`] instead of the untyped [`Session`].
+#[derive(Debug)]
+pub struct SelectedIdleHandle<T: Read + Write + Unpin + fmt::Debug + Send> {
+    handle: Handle<T>,
+    name: String,
+    mailbox: Mailbox,
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send> Session<T> {
+    /// Selects a mailbox like [`Session::select`], and moves the session into the selected state.
+    ///
+    /// If selecting fails, no mailbox is selected and the session is returned with the error.
+    pub async fn into_selected<S: AsRef<str>>(
+        mut self,
+        mailbox_name: S,
+    ) -> std::result::Result<SelectedSession<T>, (Error, Session<T>)> {
+        match self.select(mailbox_name.as_ref()).await {
+            Ok(mailbox) => Ok(SelectedSession::new(self, mailbox_name.as_ref(), mailbox)),
+            Err(err) => Err((err, self)),
+        }
+    }
+
+    /// Examines a mailbox like [`Session::examine`], and moves the session into the selected
+    /// state.
+    ///
+    /// If examining fails, no mailbox is selected and the session is returned with the error.
+    pub async fn into_examined<S: AsRef<str>>(
+        mut self,
+        mailbox_name: S,
+    ) -> std::result::Result<SelectedSession<T>, (Error, Session<T>)> {
+        match self.examine(mailbox_name.as_ref()).await {
+            Ok(mailbox) => Ok(SelectedSession::new(self, mailbox_name.as_ref(), mailbox)),
+            Err(err) => Err((err, self)),
+        }
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send> SelectedSession<T> {
+    fn new(session: Session<T>, name: &str, mailbox: Mailbox) -> Self {
+        SelectedSession {
+            session,
+            name: name.to_string(),
+            mailbox,
+        }
+    }
+
+    /// The name of the selected mailbox.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The mailbox as returned by `SELECT` or `EXAMINE`. It is not updated afterwards, see
+    /// [`Session::set_track_selected_mailbox`] for that.
+    pub fn mailbox(&self) -> &Mailbox {
+        &self.mailbox
+    }
+
+    /// Whether the mailbox was opened read-only, e.g. with [`Session::into_examined`].
+    pub fn is_read_only(&self) -> bool {
+        self.mailbox.access_mode == Some(AccessMode::ReadOnly)
+    }
+
+    /// The underlying session, for commands that are valid in any state.
+    ///
+    /// Selecting, examining or closing a mailbox through it bypasses the state tracking of this
+    /// type, use the methods of [`SelectedSession`] instead.
+    pub fn session(&mut self) -> &mut Session<T> {
+        &mut self.session
+    }
+
+    /// Gives up the selected state without closing the mailbox, for use with the untyped API.
+    pub fn into_inner(self) -> Session<T> {
+        self.session
+    }
+
+    /// Selects another mailbox, see [`Session::select`].
+    ///
+    /// If selecting fails, the server has already closed the previous mailbox, so the
+    /// authenticated session is returned with the error.
+    pub async fn select<S: AsRef<str>>(
+        self,
+        mailbox_name: S,
+    ) -> std::result::Result<SelectedSession<T>, (Error, Session<T>)> {
+        self.session.into_selected(mailbox_name).await
+    }
+
+    /// Examines another mailbox, see [`Session::examine`].
+    ///
+    /// If examining fails, the server has already closed the previous mailbox, so the
+    /// authenticated session is returned with the error.
+    pub async fn examine<S: AsRef<str>>(
+        self,
+        mailbox_name: S,
+    ) -> std::result::Result<SelectedSession<T>, (Error, Session<T>)> {
+        self.session.into_examined(mailbox_name).await
+    }
+
+    /// Closes the mailbox, permanently removing the messages flagged as deleted unless it is
+    /// read-only, and returns the authenticated session. See [`Session::close`].
+    pub async fn close(mut self) -> std::result::Result<Session<T>, (Error, SelectedSession<T>)> {
+        match self.session.close().await {
+            Ok(()) => Ok(self.session),
+            Err(err) => Err((err, self)),
+        }
+    }
+
+    /// Closes the mailbox without removing any messages and returns the authenticated session.
+    /// See [`Session::unselect`].
+    pub async fn unselect(
+        mut self,
+    ) -> std::result::Result<Session<T>, (Error, SelectedSession<T>)> {
+        match self.session.unselect().await {
+            Ok(()) => Ok(self.session),
+            Err(err) => Err((err, self)),
+        }
+    }
+
+    /// See [`Session::fetch`].
+    pub async fn fetch<S1, S2>(
+        &mut self,
+        sequence_set: S1,
+        query: S2,
+    ) -> Result<impl Stream<Item = Result<Fetch>> + '_ + Send>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        self.session.fetch(sequence_set, query).await
+    }
+
+    /// See [`Session::uid_fetch`].
+    pub async fn uid_fetch<S1, S2>(
+        &mut self,
+        uid_set: S1,
+        query: S2,
+    ) -> Result<impl Stream<Item = Result<Fetch>> + '_ + Send + Unpin>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        self.session.uid_fetch(uid_set, query).await
+    }
+
+    /// See [`Session::store`].
+    pub async fn store<S1, S2>(
+        &mut self,
+        sequence_set: S1,
+        query: S2,
+    ) -> Result<impl Stream<Item = Result<Fetch>> + '_ + Send>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        self.session.store(sequence_set, query).await
+    }
+
+    /// See [`Session::uid_store`].
+    pub async fn uid_store<S1, S2>(
+        &mut self,
+        uid_set: S1,
+        query: S2,
+    ) -> Result<impl Stream<Item = Result<Fetch>> + '_ + Send>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        self.session.uid_store(uid_set, query).await
+    }
+
+    /// See [`Session::copy`].
+    pub async fn copy<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        sequence_set: S1,
+        mailbox_name: S2,
+    ) -> Result<()> {
+        self.session.copy(sequence_set, mailbox_name).await
+    }
+
+    /// See [`Session::uid_copy`].
+    pub async fn uid_copy<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        uid_set: S1,
+        mailbox_name: S2,
+    ) -> Result<()> {
+        self.session.uid_copy(uid_set, mailbox_name).await
+    }
+
+    /// See [`Session::mv`].
+    pub async fn mv<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        sequence_set: S1,
+        mailbox_name: S2,
+    ) -> Result<()> {
+        self.session.mv(sequence_set, mailbox_name).await
+    }
+
+    /// See [`Session::uid_mv`].
+    pub async fn uid_mv<S1: AsRef<str>, S2: AsRef<str>>(
+        &mut self,
+        uid_set: S1,
+        mailbox_name: S2,
+    ) -> Result<()> {
+        self.session.uid_mv(uid_set, mailbox_name).await
+    }
+
+    /// See [`Session::search`].
+    pub async fn search<S: AsRef<str>>(&mut self, query: S) -> Result<HashSet<Seq>> {
+        self.session.search(query).await
+    }
+
+    /// See [`Session::uid_search`].
+    pub async fn uid_search<S: AsRef<str>>(&mut self, query: S) -> Result<HashSet<Uid>> {
+        self.session.uid_search(query).await
+    }
+
+    /// See [`Session::expunge`].
+    pub async fn expunge(&mut self) -> Result<impl Stream<Item = Result<Seq>> + '_ + Send> {
+        self.session.expunge().await
+    }
+
+    /// See [`Session::uid_expunge`].
+    pub async fn uid_expunge<S: AsRef<str>>(
+        &mut self,
+        uid_set: S,
+    ) -> Result<impl Stream<Item = Result<Uid>> + '_ + Send> {
+        self.session.uid_expunge(uid_set).await
+    }
+
+    /// See [`Session::check`].
+    pub async fn check(&mut self) -> Result<()> {
+        self.session.check().await
+    }
+
+    /// See [`Session::noop`].
+    pub async fn noop(&mut self) -> Result<()> {
+        self.session.noop().await
+    }
+
+    /// See [`Session::idle_stream`].
+    pub fn idle_stream(
+        &mut self,
+        keepalive: Duration,
+    ) -> impl Stream<Item = Result<MailboxEvent>> + Unpin + Send + '_ {
+        self.session.idle_stream(keepalive)
+    }
+
+    /// Waits for changes to the selected mailbox, see [`Session::idle`].
+    pub fn idle(self) -> SelectedIdleHandle<T> {
+        SelectedIdleHandle {
+            handle: self.session.idle(),
+            name: self.name,
+            mailbox: self.mailbox,
+        }
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send> SelectedIdleHandle<T> {
+    /// Stops idling, see [`Handle::done`], and returns the session in the selected state.
+    pub async fn done(self) -> Result<SelectedSession<T>> {
+        let session = self.handle.done().await?;
+        Ok(SelectedSession {
+            session,
+            name: self.name,
+            mailbox: self.mailbox,
+        })
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send> Deref for SelectedIdleHandle<T> {
+    type Target = Handle<T>;
+
+    fn deref(&self) -> &Handle<T> {
+        &self.handle
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send> DerefMut for SelectedIdleHandle<T> {
+    fn deref_mut(&mut self) -> &mut Handle<T> {
+        &mut self.handle
+    }
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send> fmt::Debug for SelectedSession<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SelectedSession")
+            .field("session", &self.session)
+            .field("name", &self.name)
+            .field("mailbox", &self.mailbox)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock_session;
+    use crate::mock_stream::MockStream;
+    use futures::StreamExt;
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn selected_session() {
+        let response = b"A0001 NO Mailbox does not exist\r\n\
+            * 2 EXISTS\r\n\
+            * OK [UIDVALIDITY 7] UIDs valid\r\n\
+            A0002 OK [READ-ONLY] Examine completed\r\n\
+            * 1 FETCH (UID 5)\r\n\
+            A0003 OK Fetch completed\r\n\
+            A0004 OK Close completed\r\n"
+            .to_vec();
+        let session = mock_session!(MockStream::new(response));
+
+        let (err, session) = session.into_selected("Missing").await.unwrap_err();
+        assert!(matches!(err, Error::No { .. }));
+
+        let mut selected = session.into_examined("INBOX").await.unwrap();
+        assert_eq!(selected.name(), "INBOX");
+        assert_eq!(selected.mailbox().exists, 2);
+        assert_eq!(selected.mailbox().uid_validity, Some(7));
+        assert!(selected.is_read_only());
+        let uids: Vec<_> = selected
+            .uid_fetch("1:*", "UID")
+            .await
+            .unwrap()
+            .map(|fetch| fetch.unwrap().uid)
+            .collect()
+            .await;
+        assert_eq!(uids, vec![Some(5)]);
+
+        let session = selected.close().await.unwrap();
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 SELECT \"Missing\"\r\n\
+              A0002 EXAMINE \"INBOX\"\r\n\
+              A0003 UID FETCH 1:* UID\r\n\
+              A0004 CLOSE\r\n"
+                .to_vec()
+        );
+    }
+}
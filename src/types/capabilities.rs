@@ -6,7 +6,7 @@ const IMAP4REV1_CAPABILITY: &str = "IMAP4rev1";
 const AUTH_CAPABILITY_PREFIX: &str = "AUTH=";
 
 /// List of available Capabilities.
-#[derive(Debug, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum Capability {
     /// The crucial imap capability.
     Imap4rev1,
@@ -51,9 +51,14 @@ impl From<&CapabilityRef<'_>> for Capability {
 ///
 /// Client implementations SHOULD NOT require any capability name other than `IMAP4rev1`, and MUST
 /// ignore any unknown capability names.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Capabilities(pub(crate) HashSet<Capability>);
 
 impl Capabilities {
+    pub(crate) fn from_parsed(caps: &[CapabilityRef<'_>]) -> Self {
+        Capabilities(caps.iter().map(Capability::from).collect())
+    }
+
     /// Check if the server has the given capability.
     pub fn has(&self, cap: &Capability) -> bool {
         self.0.contains(cap)
@@ -0,0 +1,154 @@
+//! Decoding of fetched body sections, see [`Fetch::decoded_section`](super::Fetch::decoded_section).
+
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine as _;
+use encoding_rs::{Encoding, UTF_8};
+
+use super::TransferEncoding;
+use crate::error::{Error, Result};
+
+/// Base64 as found in messages, where the padding is sometimes missing.
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &base64::alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Removes the content transfer encoding `encoding` from `data`.
+pub(crate) fn transfer_decode(data: &[u8], encoding: &TransferEncoding) -> Result<Vec<u8>> {
+    match encoding {
+        TransferEncoding::SevenBit | TransferEncoding::EightBit | TransferEncoding::Binary => {
+            Ok(data.to_vec())
+        }
+        TransferEncoding::Base64 => {
+            let data: Vec<u8> = data
+                .iter()
+                .copied()
+                .filter(|byte| !byte.is_ascii_whitespace())
+                .collect();
+            BASE64
+                .decode(data)
+                .map_err(|err| Error::Decode(format!("invalid base64: {}", err)))
+        }
+        TransferEncoding::QuotedPrintable => Ok(quoted_printable(data)),
+        TransferEncoding::Other(other) => Err(Error::Decode(format!(
+            "unsupported content transfer encoding {}",
+            other
+        ))),
+    }
+}
+
+/// Decodes quoted-printable as in [RFC 2045](https://tools.ietf.org/html/rfc2045#section-6.7).
+/// Malformed escapes are kept as they are.
+fn quoted_printable(data: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(data.len());
+    for line in data.split_inclusive(|&byte| byte == b'\n') {
+        let (content, line_break) = match line.strip_suffix(b"\r\n") {
+            Some(content) => (content, &b"\r\n"[..]),
+            None => match line.strip_suffix(b"\n") {
+                Some(content) => (content, &b"\n"[..]),
+                None => (line, &b""[..]),
+            },
+        };
+        // Whitespace at the end of a line was added in transport.
+        let end = content
+            .iter()
+            .rposition(|&byte| byte != b' ' && byte != b'\t')
+            .map_or(0, |pos| pos + 1);
+        let content = &content[..end];
+        // A soft line break, the line continues on the next one.
+        let (content, line_break) = match content.strip_suffix(b"=") {
+            Some(content) => (content, &b""[..]),
+            None => (content, line_break),
+        };
+
+        let mut i = 0;
+        while i < content.len() {
+            let hex = content
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            match (content[i], hex) {
+                (b'=', Some(byte)) => {
+                    decoded.push(byte);
+                    i += 3;
+                }
+                (byte, _) => {
+                    decoded.push(byte);
+                    i += 1;
+                }
+            }
+        }
+        decoded.extend_from_slice(line_break);
+    }
+    decoded
+}
+
+/// Converts `data` in `charset` to UTF-8.
+///
+/// Text without a charset is US-ASCII, but is treated as UTF-8, as is 8-bit text in US-ASCII.
+/// Invalid sequences are replaced with `U+FFFD`.
+pub(crate) fn to_utf8(data: &[u8], charset: Option<&str>) -> Result<String> {
+    let encoding = match charset.map(str::trim) {
+        None => UTF_8,
+        Some(charset)
+            if charset.eq_ignore_ascii_case("us-ascii")
+                || charset.eq_ignore_ascii_case("ascii") =>
+        {
+            UTF_8
+        }
+        Some(charset) => Encoding::for_label(charset.as_bytes())
+            .ok_or_else(|| Error::Decode(format!("unsupported charset {}", charset)))?,
+    };
+    // A byte order mark takes precedence over the charset.
+    let (text, _, _) = encoding.decode(data);
+    Ok(text.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_transfer_encoding() {
+        assert_eq!(
+            transfer_decode(b"SGVsbG8g\r\nd29ybGQ\r\n", &TransferEncoding::Base64).unwrap(),
+            b"Hello world"
+        );
+        assert!(transfer_decode(b"SGV*", &TransferEncoding::Base64).is_err());
+        assert_eq!(
+            transfer_decode(
+                b"caf=E9 =3D ok  \r\nsoft=\r\nbreak=\nand =ZZ\r\n",
+                &TransferEncoding::QuotedPrintable
+            )
+            .unwrap(),
+            b"caf\xe9 = ok\r\nsoftbreakand =ZZ\r\n"
+        );
+        assert_eq!(
+            transfer_decode(b"=C3=A9", &TransferEncoding::EightBit).unwrap(),
+            b"=C3=A9"
+        );
+        assert!(transfer_decode(b"", &TransferEncoding::Other("X-UUENCODE".into())).is_err());
+    }
+
+    #[test]
+    fn convert_charset() {
+        assert_eq!(to_utf8(b"caf\xc3\xa9", Some("UTF-8")).unwrap(), "café");
+        assert_eq!(to_utf8(b"caf\xe9", None).unwrap(), "caf\u{fffd}");
+        assert_eq!(to_utf8(b"caf\xe9", Some("ISO-8859-1")).unwrap(), "café");
+        assert_eq!(to_utf8(b"\xa4 5", Some("iso-8859-15")).unwrap(), "€ 5");
+        assert_eq!(
+            to_utf8(b"\x93quoted\x94 \x80\x81", Some("Windows-1252")).unwrap(),
+            "\u{201c}quoted\u{201d} €\u{81}"
+        );
+        assert_eq!(
+            to_utf8(b"\xff\xfe\xe9\x00!\x00", Some("utf-16")).unwrap(),
+            "é!"
+        );
+        assert_eq!(to_utf8(b"\x00\xe9", Some("UTF-16BE")).unwrap(), "é");
+        assert_eq!(
+            to_utf8(b"\xf0\xd2\xc9\xd7\xc5\xd4", Some("koi8-r")).unwrap(),
+            "Привет"
+        );
+        assert!(to_utf8(b"", Some("x-unknown")).is_err());
+    }
+}
@@ -3,7 +3,10 @@ use imap_proto::types::{
     AttributeValue, BodyStructure, Envelope, MessageSection, Response, SectionPath,
 };
 
-use super::{Flag, Seq, Uid};
+use super::decode::{to_utf8, transfer_decode};
+use super::{Flag, MessagePart, MessageStructure, Seq, Uid};
+use crate::error::Result;
+use crate::extensions::binary::BinaryAttribute;
 use crate::types::ResponseData;
 
 /// Format of Date and Time as defined RFC3501.
@@ -190,6 +193,90 @@ impl Fetch {
         }
     }
 
+    /// Extract the given body section of a `FETCH` response as text, converted to UTF-8 from the
+    /// charset of the part, after removing its content transfer encoding. See
+    /// [`Fetch::decoded_section_bytes`].
+    ///
+    /// ```ignore
+    /// let structure = fetch.message_structure().unwrap();
+    /// let text_part = structure.text_part().unwrap();
+    /// let query = format!("(UID BODY.PEEK[{}])", text_part.section_spec());
+    /// // ... fetch it ...
+    /// let text = fetch.decoded_section(&text_part.section_path(), &structure)?;
+    /// ```
+    pub fn decoded_section(
+        &self,
+        path: &SectionPath,
+        structure: &MessageStructure,
+    ) -> Result<Option<String>> {
+        let part = match section_part(path, structure) {
+            Some(part) => part,
+            None => return Ok(None),
+        };
+        match self.decoded_part(path, part)? {
+            Some(data) => to_utf8(&data, part.charset.as_deref()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Extract the given body section of a `FETCH` response without its content transfer
+    /// encoding, e.g. base64, which is taken from the part of `structure` at `path`. If only
+    /// `BINARY[<section>]` was fetched, it is returned as it is, see [`Fetch::binary`].
+    ///
+    /// Returns `None` if the section was not fetched, or `path` is not a part of `structure`, such
+    /// as the header of a part.
+    pub fn decoded_section_bytes(
+        &self,
+        path: &SectionPath,
+        structure: &MessageStructure,
+    ) -> Result<Option<Vec<u8>>> {
+        match section_part(path, structure) {
+            Some(part) => self.decoded_part(path, part),
+            None => Ok(None),
+        }
+    }
+
+    /// The section at `path`, which is `part`, without its content transfer encoding.
+    fn decoded_part(&self, path: &SectionPath, part: &MessagePart) -> Result<Option<Vec<u8>>> {
+        match self.section(path) {
+            Some(data) => transfer_decode(data, &part.encoding).map(Some),
+            None => Ok(self.binary(&part.section).map(<[u8]>::to_vec)),
+        }
+    }
+
+    /// Extract the content of the given `BINARY[<section>]` of a `FETCH` response, included if
+    /// `BINARY[<section>]` or `BINARY.PEEK[<section>]` was included in the `query` argument to
+    /// `FETCH`. Unlike with [`Fetch::section`], the server removed the content transfer encoding,
+    /// e.g. base64.
+    ///
+    /// `section` are the part numbers, e.g. `&[1, 2]` for `BINARY[1.2]`, or `&[]` for the whole
+    /// message. See [RFC 3516](https://tools.ietf.org/html/rfc3516) for details.
+    pub fn binary(&self, section: &[u32]) -> Option<&[u8]> {
+        self.response
+            .binary()
+            .iter()
+            .find_map(|attribute| match attribute {
+                BinaryAttribute::Section {
+                    section: s,
+                    data: Some(data),
+                } if s == section => Some(data.as_slice()),
+                _ => None,
+            })
+    }
+
+    /// Extract the size of the given section without its content transfer encoding, included if
+    /// `BINARY.SIZE[<section>]` was included in the `query` argument to `FETCH`. See
+    /// [`Fetch::binary`].
+    pub fn binary_size(&self, section: &[u32]) -> Option<u64> {
+        self.response
+            .binary()
+            .iter()
+            .find_map(|attribute| match attribute {
+                BinaryAttribute::Size { section: s, size } if s == section => Some(*size),
+                _ => None,
+            })
+    }
+
     /// Extract the `INTERNALDATE` of a `FETCH` response
     ///
     /// See [section 2.3.3 of RFC 3501](https://tools.ietf.org/html/rfc3501#section-2.3.3) for
@@ -203,17 +290,17 @@ impl Fetch {
                     _ => None,
                 })
                 .next()
-                .and_then(
-                    |date_time| match DateTime::parse_from_str(date_time, DATE_TIME_FORMAT) {
-                        Ok(date_time) => Some(date_time),
-                        Err(_) => None,
-                    },
-                )
+                .and_then(|date_time| DateTime::parse_from_str(date_time, DATE_TIME_FORMAT).ok())
         } else {
             unreachable!()
         }
     }
 
+    /// The parts of the message, flattened from its `BODYSTRUCTURE`, see [`MessageStructure`].
+    pub fn message_structure(&self) -> Option<MessageStructure> {
+        self.bodystructure().map(MessageStructure::new)
+    }
+
     /// Extract the `BODYSTRUCTURE` of a `FETCH` response
     ///
     /// See [section 2.3.6 of RFC 3501](https://tools.ietf.org/html/rfc3501#section-2.3.6) for
@@ -232,3 +319,14 @@ impl Fetch {
         }
     }
 }
+
+/// The part of `structure` that is the content of the section at `path`.
+fn section_part<'a>(
+    path: &SectionPath,
+    structure: &'a MessageStructure,
+) -> Option<&'a MessagePart> {
+    match path {
+        SectionPath::Part(section, None) => structure.part(section),
+        _ => None,
+    }
+}
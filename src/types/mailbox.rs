@@ -38,6 +38,33 @@ pub struct Mailbox {
 
     /// Highest mailbox mod-sequence as defined in [RFC-7162](https://tools.ietf.org/html/rfc7162).
     pub highest_modseq: Option<u64>,
+
+    /// Whether the mailbox was selected read-only or read-write, from the `READ-ONLY` or
+    /// `READ-WRITE` response code.  If this is missing, the server did not say.  Note that a
+    /// mailbox selected with `SELECT` may still be read-only, e.g. a shared folder the user has no
+    /// write access to, in which case changes to the mailbox fail or are silently dropped.
+    pub access_mode: Option<AccessMode>,
+
+    /// The mailbox does not support persistent unique identifiers, see the `UIDNOTSTICKY`
+    /// response code of [RFC 4315](https://tools.ietf.org/html/rfc4315#section-3).
+    pub uid_not_sticky: bool,
+
+    /// The mailbox does not support mod-sequences, see the `NOMODSEQ` response code of [RFC
+    /// 7162](https://tools.ietf.org/html/rfc7162#section-3.1.2.2).
+    pub no_modseq: bool,
+
+    /// Unique identifier of the mailbox that does not change on rename, as defined in [RFC
+    /// 8474](https://tools.ietf.org/html/rfc8474#section-4).
+    pub mailbox_id: Option<String>,
+}
+
+/// Whether a mailbox is selected read-only or read-write.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum AccessMode {
+    /// No changes to the mailbox are allowed, e.g. after `EXAMINE`.
+    ReadOnly,
+    /// The mailbox can be modified.
+    ReadWrite,
 }
 
 impl fmt::Display for Mailbox {
@@ -45,14 +72,19 @@ impl fmt::Display for Mailbox {
         write!(
             f,
             "flags: {:?}, exists: {}, recent: {}, unseen: {:?}, permanent_flags: {:?},\
-             uid_next: {:?}, uid_validity: {:?}",
+             uid_next: {:?}, uid_validity: {:?}, access_mode: {:?}, uid_not_sticky: {}, \
+             no_modseq: {}, mailbox_id: {:?}",
             self.flags,
             self.exists,
             self.recent,
             self.unseen,
             self.permanent_flags,
             self.uid_next,
-            self.uid_validity
+            self.uid_validity,
+            self.access_mode,
+            self.uid_not_sticky,
+            self.no_modseq,
+            self.mailbox_id
         )
     }
 }
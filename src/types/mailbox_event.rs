@@ -0,0 +1,177 @@
+use std::ops::RangeInclusive;
+
+use imap_proto::{AttributeValue, Response};
+
+use super::{Flag, Seq, Uid, UnsolicitedResponse};
+
+/// A change to the selected mailbox that the server reported on its own, e.g. while idling.
+///
+/// These are converted from the [`UnsolicitedResponse`]s that describe messages of the selected
+/// mailbox, see the [`TryFrom`] implementation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MailboxEvent {
+    /// An `EXISTS` response: the mailbox now contains this many messages, usually because new
+    /// messages arrived.
+    NewMessages(u32),
+    /// An `EXPUNGE` response: the message with this sequence number was removed, and the
+    /// sequence numbers of the messages after it were decremented by one.
+    Expunged(Seq),
+    /// A `FETCH` response with the flags of a message, sent after they were changed.
+    FlagsChanged {
+        /// The sequence number of the message.
+        seq: Seq,
+        /// The flags the message has now.
+        flags: Vec<Flag<'static>>,
+        /// The new mod-sequence of the message, if `CONDSTORE` is enabled.
+        modseq: Option<u64>,
+    },
+    /// A `VANISHED` response, sent instead of `EXPUNGE` once `QRESYNC` is enabled: the messages
+    /// with these UIDs were removed.
+    Vanished(Vec<RangeInclusive<Uid>>),
+    /// The highest mod-sequence of the mailbox increased to this value, because the flags of
+    /// some of its messages changed. Only reported by [`Session::watch_mailbox`], which cannot
+    /// tell which messages changed.
+    ///
+    /// [`Session::watch_mailbox`]: crate::Session::watch_mailbox
+    HighestModSeq(u64),
+    /// The `UIDVALIDITY` of the mailbox changed to this value, so all UIDs known for it are
+    /// invalid. Only reported by [`Session::watch_mailbox`].
+    ///
+    /// [`Session::watch_mailbox`]: crate::Session::watch_mailbox
+    UidValidityChanged(u32),
+}
+
+impl TryFrom<UnsolicitedResponse> for MailboxEvent {
+    type Error = UnsolicitedResponse;
+
+    /// Returns the response back if it is not about the messages of the selected mailbox.
+    fn try_from(response: UnsolicitedResponse) -> Result<Self, UnsolicitedResponse> {
+        let data = match response {
+            UnsolicitedResponse::Exists(n) => return Ok(MailboxEvent::NewMessages(n)),
+            UnsolicitedResponse::Expunge(seq) => return Ok(MailboxEvent::Expunged(seq)),
+            UnsolicitedResponse::Other(data) => data,
+            response => return Err(response),
+        };
+        let event = match data.parsed() {
+            Response::Fetch(seq, attributes) => {
+                let mut flags = None;
+                let mut modseq = None;
+                for attribute in attributes {
+                    match attribute {
+                        AttributeValue::Flags(f) => {
+                            flags = Some(f.iter().map(|f| Flag::from(f.to_string())).collect())
+                        }
+                        AttributeValue::ModSeq(m) => modseq = Some(*m),
+                        _ => {}
+                    }
+                }
+                flags.map(|flags| MailboxEvent::FlagsChanged {
+                    seq: *seq,
+                    flags,
+                    modseq,
+                })
+            }
+            Response::Vanished { uids, .. } => Some(MailboxEvent::Vanished(uids.clone())),
+            _ => None,
+        };
+        event.ok_or(UnsolicitedResponse::Other(data))
+    }
+}
+
+/// Adds `event` to `events`, merging it with earlier events it supersedes, so that applying the
+/// result in order has the same effect as applying all events.
+pub(crate) fn coalesce(events: &mut Vec<MailboxEvent>, event: MailboxEvent) {
+    // Sequence numbers before the last `EXPUNGE` refer to other messages.
+    let since_expunge = events
+        .iter()
+        .rposition(|e| matches!(e, MailboxEvent::Expunged(_)))
+        .map_or(0, |index| index + 1);
+    match &event {
+        // The message count is absolute, so only the last one matters, unless events in between
+        // refer to messages by sequence number. These may be the new messages.
+        MailboxEvent::NewMessages(_) => {
+            let last = events
+                .iter()
+                .rposition(|e| matches!(e, MailboxEvent::NewMessages(_)));
+            if let Some(last) = last {
+                let refers_to_seq = events[last + 1..].iter().any(|e| {
+                    matches!(
+                        e,
+                        MailboxEvent::FlagsChanged { .. } | MailboxEvent::Expunged(_)
+                    )
+                });
+                if !refers_to_seq {
+                    events.remove(last);
+                }
+            }
+        }
+        MailboxEvent::HighestModSeq(_) => {
+            events.retain(|e| !matches!(e, MailboxEvent::HighestModSeq(_)));
+        }
+        MailboxEvent::FlagsChanged { seq, .. } => {
+            let mut index = since_expunge;
+            while index < events.len() {
+                match &events[index] {
+                    MailboxEvent::FlagsChanged { seq: earlier, .. } if earlier == seq => {
+                        events.remove(index);
+                    }
+                    _ => index += 1,
+                }
+            }
+        }
+        MailboxEvent::Vanished(uids) => {
+            if let Some(MailboxEvent::Vanished(earlier)) = events.last_mut() {
+                earlier.extend(uids.iter().cloned());
+                return;
+            }
+        }
+        MailboxEvent::Expunged(_) | MailboxEvent::UidValidityChanged(_) => {}
+    }
+    events.push(event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flags_changed(seq: Seq, flag: Flag<'static>) -> MailboxEvent {
+        MailboxEvent::FlagsChanged {
+            seq,
+            flags: vec![flag],
+            modseq: None,
+        }
+    }
+
+    #[test]
+    fn coalesce_events() {
+        let mut events = Vec::new();
+        for event in [
+            MailboxEvent::NewMessages(3),
+            flags_changed(2, Flag::Seen),
+            flags_changed(3, Flag::Seen),
+            flags_changed(2, Flag::Flagged),
+            MailboxEvent::Expunged(1),
+            flags_changed(2, Flag::Deleted),
+            MailboxEvent::NewMessages(4),
+            MailboxEvent::Vanished(vec![5..=6]),
+            MailboxEvent::Vanished(vec![8..=8]),
+            MailboxEvent::NewMessages(5),
+        ] {
+            coalesce(&mut events, event);
+        }
+
+        assert_eq!(
+            events,
+            vec![
+                MailboxEvent::NewMessages(3),
+                flags_changed(3, Flag::Seen),
+                flags_changed(2, Flag::Flagged),
+                MailboxEvent::Expunged(1),
+                flags_changed(2, Flag::Deleted),
+                MailboxEvent::Vanished(vec![5..=6, 8..=8]),
+                MailboxEvent::NewMessages(5),
+            ]
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use std::borrow::Cow;
+
+use imap_proto::types::{
+    BodyContentCommon, BodyContentSinglePart, BodyParams, BodyStructure, ContentEncoding,
+    SectionPath,
+};
+
+/// The parts of a message, flattened from its `BODYSTRUCTURE`, see
+/// [`Fetch::message_structure`](super::Fetch::message_structure).
+///
+/// It answers the usual questions about a message without walking the [`BodyStructure`] tree,
+/// such as which part is the plain text body, and which parts are attachments:
+///
+/// ```ignore
+/// let structure = fetch.message_structure().unwrap();
+/// if let Some(text) = structure.text_part() {
+///     let query = format!("BODY.PEEK[{}]", text.section_spec());
+///     // ... fetch it ...
+/// }
+/// for attachment in structure.attachments() {
+///     println!("{:?}: {} bytes", attachment.filename, attachment.size);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageStructure {
+    parts: Vec<MessagePart>,
+}
+
+/// A part of a message with content, i.e. not a `multipart/*` container.
+///
+/// Attached messages (`message/rfc822`) are parts of their own, the parts they consist of are not
+/// listed separately.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MessagePart {
+    /// The part numbers, e.g. `[1, 2]` for the second part of the first part of the message.
+    pub section: Vec<u32>,
+    /// The lowercase MIME type, e.g. `text/plain`.
+    pub content_type: String,
+    /// The `charset` parameter of the content type, if any.
+    pub charset: Option<String>,
+    /// The content transfer encoding.
+    pub encoding: TransferEncoding,
+    /// The lowercase content disposition, e.g. `attachment` or `inline`, if any.
+    pub disposition: Option<String>,
+    /// The file name from the content disposition or, failing that, the `name` parameter of the
+    /// content type.
+    pub filename: Option<String>,
+    /// The size of the encoded content in bytes.
+    pub size: u32,
+}
+
+/// A `Content-Transfer-Encoding`, see [RFC 2045](https://tools.ietf.org/html/rfc2045#section-6).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TransferEncoding {
+    /// `7bit`, the content is not encoded.
+    SevenBit,
+    /// `8bit`, the content is not encoded.
+    EightBit,
+    /// `binary`, the content is not encoded.
+    Binary,
+    /// `base64`.
+    Base64,
+    /// `quoted-printable`.
+    QuotedPrintable,
+    /// Any other encoding, in uppercase.
+    Other(String),
+}
+
+impl MessageStructure {
+    /// Flattens `body`, the `BODYSTRUCTURE` of a message.
+    pub fn new(body: &BodyStructure<'_>) -> Self {
+        let mut parts = Vec::new();
+        match body {
+            BodyStructure::Multipart { bodies, .. } => add_parts(&mut parts, &[], bodies),
+            // The content of a message that is not multipart is its part 1.
+            _ => add_part(&mut parts, vec![1], body),
+        }
+        MessageStructure { parts }
+    }
+
+    /// All parts, in the order they appear in the message.
+    pub fn parts(&self) -> &[MessagePart] {
+        &self.parts
+    }
+
+    /// The first `text/plain` part that is not an attachment, usually the body of the message.
+    pub fn text_part(&self) -> Option<&MessagePart> {
+        self.body_part("text/plain")
+    }
+
+    /// The first `text/html` part that is not an attachment, usually the body of the message if
+    /// it is formatted.
+    pub fn html_part(&self) -> Option<&MessagePart> {
+        self.body_part("text/html")
+    }
+
+    /// The parts that are attachments, see [`MessagePart::is_attachment`].
+    pub fn attachments(&self) -> impl Iterator<Item = &MessagePart> {
+        self.parts.iter().filter(|part| part.is_attachment())
+    }
+
+    /// The part with the given part numbers.
+    pub fn part(&self, section: &[u32]) -> Option<&MessagePart> {
+        self.parts.iter().find(|part| part.section == section)
+    }
+
+    fn body_part(&self, content_type: &str) -> Option<&MessagePart> {
+        self.parts
+            .iter()
+            .find(|part| part.content_type == content_type && !part.is_attachment())
+    }
+}
+
+impl MessagePart {
+    /// Whether the part is an attachment, i.e. its disposition is `attachment`, or it has no
+    /// disposition but a file name.
+    pub fn is_attachment(&self) -> bool {
+        match self.disposition.as_deref() {
+            Some(disposition) => disposition == "attachment",
+            None => self.filename.is_some(),
+        }
+    }
+
+    /// The section of the part, e.g. for comparing with the sections of a `FETCH` response using
+    /// [`Fetch::section`](super::Fetch::section).
+    pub fn section_path(&self) -> SectionPath {
+        SectionPath::Part(self.section.clone(), None)
+    }
+
+    /// The section of the part as used in a `FETCH` query, e.g. `1.2` for
+    /// `BODY.PEEK[1.2]`.
+    pub fn section_spec(&self) -> String {
+        let numbers: Vec<String> = self.section.iter().map(u32::to_string).collect();
+        numbers.join(".")
+    }
+}
+
+impl From<&ContentEncoding<'_>> for TransferEncoding {
+    fn from(encoding: &ContentEncoding<'_>) -> Self {
+        match encoding {
+            ContentEncoding::SevenBit => TransferEncoding::SevenBit,
+            ContentEncoding::EightBit => TransferEncoding::EightBit,
+            ContentEncoding::Binary => TransferEncoding::Binary,
+            ContentEncoding::Base64 => TransferEncoding::Base64,
+            ContentEncoding::QuotedPrintable => TransferEncoding::QuotedPrintable,
+            ContentEncoding::Other(other) => TransferEncoding::Other(other.to_uppercase()),
+        }
+    }
+}
+
+fn add_parts(parts: &mut Vec<MessagePart>, parent: &[u32], bodies: &[BodyStructure<'_>]) {
+    for (index, body) in bodies.iter().enumerate() {
+        let mut section = parent.to_vec();
+        section.push(index as u32 + 1);
+        match body {
+            BodyStructure::Multipart { bodies, .. } => add_parts(parts, &section, bodies),
+            _ => add_part(parts, section, body),
+        }
+    }
+}
+
+fn add_part(parts: &mut Vec<MessagePart>, section: Vec<u32>, body: &BodyStructure<'_>) {
+    let (common, other): (&BodyContentCommon<'_>, &BodyContentSinglePart<'_>) = match body {
+        BodyStructure::Basic { common, other, .. }
+        | BodyStructure::Text { common, other, .. }
+        | BodyStructure::Message { common, other, .. } => (common, other),
+        BodyStructure::Multipart { .. } => return,
+    };
+    let disposition = common.disposition.as_ref();
+    let filename = disposition
+        .and_then(|disposition| param(&disposition.params, "filename"))
+        .or_else(|| param(&common.ty.params, "name"));
+    parts.push(MessagePart {
+        section,
+        content_type: format!("{}/{}", common.ty.ty, common.ty.subtype).to_lowercase(),
+        charset: param(&common.ty.params, "charset"),
+        encoding: TransferEncoding::from(&other.transfer_encoding),
+        disposition: disposition.map(|disposition| disposition.ty.to_lowercase()),
+        filename,
+        size: other.octets,
+    });
+}
+
+/// The value of the parameter `name`, also if it is encoded as in
+/// [RFC 2231](https://tools.ietf.org/html/rfc2231), e.g. `filename*=utf-8''%E2%82%AC.txt`.
+fn param(params: &BodyParams<'_>, name: &str) -> Option<String> {
+    let params = params.as_ref()?;
+    let value = |key: &str| {
+        params
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    };
+    if let Some(encoded) = value(&format!("{}*", name)).and_then(|v| decode_rfc2231(v)) {
+        return Some(encoded);
+    }
+    value(name).map(Cow::to_string)
+}
+
+/// Decodes `<charset>'<language>'<percent-encoded value>` if the charset is UTF-8 or ASCII.
+fn decode_rfc2231(value: &str) -> Option<String> {
+    let mut fields = value.splitn(3, '\'');
+    let charset = fields.next()?;
+    let _language = fields.next()?;
+    let encoded = fields.next()?.as_bytes();
+    if !charset.eq_ignore_ascii_case("utf-8") && !charset.eq_ignore_ascii_case("us-ascii") {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        let hex = encoded
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (encoded[i], hex) {
+            (b'%', Some(byte)) => {
+                bytes.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                bytes.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use imap_proto::types::{AttributeValue, Response};
+
+    fn structure(response: &str) -> MessageStructure {
+        let (_, response) = imap_proto::parser::parse_response(response.as_bytes()).unwrap();
+        match response {
+            Response::Fetch(_, attributes) => attributes
+                .iter()
+                .find_map(|attribute| match attribute {
+                    AttributeValue::BodyStructure(body) => Some(MessageStructure::new(body)),
+                    _ => None,
+                })
+                .unwrap(),
+            _ => panic!("not a FETCH response"),
+        }
+    }
+
+    #[test]
+    fn flatten_multipart() {
+        let structure = structure(
+            "* 1 FETCH (BODYSTRUCTURE (\
+            ((\"TEXT\" \"PLAIN\" (\"CHARSET\" \"ISO-8859-1\") NIL NIL \"QUOTED-PRINTABLE\" 120 4 NIL NIL NIL NIL)\
+            (\"TEXT\" \"HTML\" (\"CHARSET\" \"UTF-8\") NIL NIL \"BASE64\" 400 6 NIL NIL NIL NIL) \
+            \"ALTERNATIVE\" (\"BOUNDARY\" \"b2\") NIL NIL NIL)\
+            (\"IMAGE\" \"PNG\" (\"NAME\" \"logo.png\") NIL NIL \"BASE64\" 2000 NIL (\"INLINE\" NIL) NIL NIL)\
+            (\"APPLICATION\" \"PDF\" NIL NIL NIL \"BASE64\" 5000 NIL \
+            (\"ATTACHMENT\" (\"FILENAME*\" \"utf-8''%E2%82%AC%20report.pdf\")) NIL NIL) \
+            \"MIXED\" (\"BOUNDARY\" \"b1\") NIL NIL NIL))\r\n",
+        );
+
+        let text = structure.text_part().unwrap();
+        assert_eq!(text.section, vec![1, 1]);
+        assert_eq!(text.section_spec(), "1.1");
+        assert_eq!(text.charset.as_deref(), Some("ISO-8859-1"));
+        assert_eq!(text.encoding, TransferEncoding::QuotedPrintable);
+        let html = structure.html_part().unwrap();
+        assert_eq!(html.section_path(), SectionPath::Part(vec![1, 2], None));
+        assert_eq!(html.size, 400);
+
+        let image = structure.part(&[2]).unwrap();
+        assert_eq!(image.content_type, "image/png");
+        assert_eq!(image.filename.as_deref(), Some("logo.png"));
+        assert!(!image.is_attachment());
+
+        let attachments: Vec<_> = structure.attachments().collect();
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].section, vec![3]);
+        assert_eq!(attachments[0].disposition.as_deref(), Some("attachment"));
+        assert_eq!(attachments[0].filename.as_deref(), Some("€ report.pdf"));
+    }
+
+    #[test]
+    fn flatten_single_part() {
+        let structure = structure(
+            "* 1 FETCH (BODYSTRUCTURE (\"TEXT\" \"PLAIN\" NIL NIL NIL \"7BIT\" 12 1 NIL NIL NIL NIL))\r\n",
+        );
+        assert_eq!(structure.parts().len(), 1);
+        let text = structure.text_part().unwrap();
+        assert_eq!(text.section, vec![1]);
+        assert_eq!(text.charset, None);
+        assert_eq!(text.encoding, TransferEncoding::SevenBit);
+        assert!(structure.html_part().is_none());
+    }
+}
@@ -190,7 +190,7 @@ impl<'a> From<&'a str> for Flag<'a> {
 }
 
 mod mailbox;
-pub use self::mailbox::Mailbox;
+pub use self::mailbox::{AccessMode, Mailbox};
 
 mod fetch;
 pub use self::fetch::Fetch;
@@ -201,6 +201,21 @@ pub use self::name::{Name, NameAttribute};
 mod capabilities;
 pub use self::capabilities::{Capabilities, Capability};
 
+mod response_code;
+pub use self::response_code::{ResponseCode, UidSetMember};
+
+mod selected_mailbox;
+pub use self::selected_mailbox::{MailboxDiff, MessageState, SelectedMailbox};
+
+mod message_structure;
+pub use self::message_structure::{MessagePart, MessageStructure, TransferEncoding};
+
+mod decode;
+
+mod mailbox_event;
+pub(crate) use self::mailbox_event::coalesce;
+pub use self::mailbox_event::MailboxEvent;
+
 /// re-exported from imap_proto;
 pub use imap_proto::StatusAttribute;
 
@@ -208,7 +223,7 @@ mod id_generator;
 pub(crate) use self::id_generator::IdGenerator;
 
 mod response_data;
-pub(crate) use self::response_data::ResponseData;
+pub(crate) use self::response_data::{RawResponse, ResponseData};
 
 mod request;
 pub(crate) use self::request::Request;
@@ -274,6 +289,31 @@ pub enum UnsolicitedResponse {
     /// sequence numbers 9, 8, 7, 6, and 5.
     // TODO: the spec doesn't seem to say anything about when these may be received as unsolicited?
     Expunge(u32),
+
+    /// An untagged [`BYE` response](https://tools.ietf.org/html/rfc3501#section-7.1.5): the
+    /// server is about to close the connection, e.g. because of an inactivity timeout or a
+    /// shutdown. This is also sent in response to `LOGOUT`.
+    Bye {
+        /// The response code, if the server sent one.
+        code: Option<ResponseCode>,
+        /// Human-readable text sent along with `BYE`.
+        text: String,
+    },
+
+    /// A response with an [`ALERT` response
+    /// code](https://tools.ietf.org/html/rfc3501#section-7.1). The text must be presented to the
+    /// user in a way that calls their attention to it.
+    Alert(String),
+
+    /// An untagged `OK` response, usually carrying a response code such as `HIGHESTMODSEQ`, or
+    /// `CLOSED` when a mailbox is deselected.
+    Notice {
+        /// The response code, if the server sent one.
+        code: Option<ResponseCode>,
+        /// Human-readable text sent along with `OK`.
+        text: String,
+    },
+
     /// Any other kind of unsolicted response.
     Other(ResponseData),
 }
@@ -0,0 +1,324 @@
+use imap_proto::ResponseCode as ResponseCodeRef;
+pub use imap_proto::UidSetMember;
+
+use super::{Capabilities, Seq, Uid};
+
+/// A [response code](https://tools.ietf.org/html/rfc3501#section-7.1) sent by the server in
+/// brackets at the start of the text of a status response, such as `NO [TRYCREATE] No such
+/// mailbox`.
+///
+/// Besides the codes of RFC 3501, this covers the codes of [RFC
+/// 5530](https://tools.ietf.org/html/rfc5530), [UIDPLUS](https://tools.ietf.org/html/rfc4315),
+/// [CONDSTORE](https://tools.ietf.org/html/rfc7162) and
+/// [METADATA](https://tools.ietf.org/html/rfc5464). Any other code is returned as
+/// [`ResponseCode::Other`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ResponseCode {
+    /// `ALERT`: the text must be presented to the user.
+    Alert,
+    /// `BADCHARSET`: the requested charset is not supported, optionally with the supported ones.
+    BadCharset(Option<Vec<String>>),
+    /// `CAPABILITY`: the capabilities of the server.
+    Capabilities(Capabilities),
+    /// `PARSE`: the server failed to parse the headers of a message.
+    Parse,
+    /// `PERMANENTFLAGS`: the flags that can be changed permanently.
+    PermanentFlags(Vec<String>),
+    /// `READ-ONLY`: the mailbox is selected read-only.
+    ReadOnly,
+    /// `READ-WRITE`: the mailbox is selected read-write.
+    ReadWrite,
+    /// `TRYCREATE`: the target mailbox does not exist, but could be created.
+    TryCreate,
+    /// `UIDNEXT`: the next unique identifier value.
+    UidNext(Uid),
+    /// `UIDVALIDITY`: the unique identifier validity value.
+    UidValidity(u32),
+    /// `UNSEEN`: the sequence number of the first message without the `\Seen` flag.
+    Unseen(Seq),
+
+    /// `UNAVAILABLE`: a subsystem is temporarily down, retrying later may succeed.
+    Unavailable,
+    /// `AUTHENTICATIONFAILED`: the credentials were rejected.
+    AuthenticationFailed,
+    /// `AUTHORIZATIONFAILED`: the credentials are valid, but the user may not use the requested
+    /// authorization identity.
+    AuthorizationFailed,
+    /// `EXPIRED`: the credentials have expired.
+    Expired,
+    /// `PRIVACYREQUIRED`: the operation is not allowed over an unencrypted connection.
+    PrivacyRequired,
+    /// `CONTACTADMIN`: the user should contact the system administrator.
+    ContactAdmin,
+    /// `NOPERM`: the access control list does not permit the operation.
+    NoPerm,
+    /// `INUSE`: the operation can not be performed because something is in use.
+    InUse,
+    /// `EXPUNGEISSUED`: a message was expunged by another session meanwhile.
+    ExpungeIssued,
+    /// `CORRUPTION`: the server discovered corrupted data.
+    Corruption,
+    /// `SERVERBUG`: the server encountered a bug in itself.
+    ServerBug,
+    /// `CLIENTBUG`: the server detected a bug in the client.
+    ClientBug,
+    /// `CANNOT`: the operation violates an invariant of the server and will never succeed.
+    CanNot,
+    /// `LIMIT`: the operation ran into a limit of the server.
+    Limit,
+    /// `OVERQUOTA`: the user is over quota.
+    OverQuota,
+    /// `ALREADYEXISTS`: the target mailbox already exists.
+    AlreadyExists,
+    /// `NONEXISTENT`: the mailbox or message does not exist.
+    NonExistent,
+
+    /// `APPENDUID`: the `UIDVALIDITY` of the target mailbox and the UIDs of appended messages.
+    AppendUid(u32, Vec<UidSetMember>),
+    /// `COPYUID`: the `UIDVALIDITY` of the target mailbox, the UIDs of the source messages and
+    /// the UIDs of the copies.
+    CopyUid(u32, Vec<UidSetMember>, Vec<UidSetMember>),
+    /// `UIDNOTSTICKY`: the mailbox does not support persistent UIDs.
+    UidNotSticky,
+
+    /// `HIGHESTMODSEQ`: the highest mod-sequence of the mailbox.
+    HighestModSeq(u64),
+    /// `NOMODSEQ`: the mailbox does not support mod-sequences.
+    NoModSeq,
+    /// `CLOSED`: the previously selected mailbox was closed, responses after it belong to the
+    /// newly selected mailbox.
+    Closed,
+    /// `MODIFIED`: the messages that failed the `UNCHANGEDSINCE` test of a conditional `STORE`.
+    /// These are UIDs for `UID STORE` and sequence numbers otherwise.
+    Modified(Vec<UidSetMember>),
+
+    /// `MAILBOXID`: the unique identifier of the mailbox, see [RFC
+    /// 8474](https://tools.ietf.org/html/rfc8474#section-4).
+    MailboxId(String),
+
+    /// `METADATA LONGENTRIES`: the size of the biggest entry that was not returned.
+    MetadataLongEntries(u64),
+    /// `METADATA MAXSIZE`: the maximum size of an entry the server accepts.
+    MetadataMaxSize(u64),
+    /// `METADATA TOOMANY`: the server can not store any more entries.
+    MetadataTooMany,
+    /// `METADATA NOPRIVATE`: the server does not support private annotations.
+    MetadataNoPrivate,
+
+    /// Any other response code, with its name and the unparsed rest of the code, if any. The
+    /// name is `UNKNOWN` for codes that the parser knows, but this version of the crate does not.
+    Other(String, Option<String>),
+}
+
+impl ResponseCode {
+    /// Splits the code and the human-readable text of a status response.
+    ///
+    /// `imap-proto` only parses the codes it knows about and leaves others in the text, so these
+    /// are parsed here.
+    pub(crate) fn from_status(
+        code: Option<&ResponseCodeRef<'_>>,
+        information: Option<&str>,
+    ) -> (Option<Self>, String) {
+        let information = information.unwrap_or_default();
+        if let Some(code) = code {
+            return (Some(Self::from(code)), information.to_string());
+        }
+
+        let parsed = information.strip_prefix('[').and_then(|rest| {
+            let (code, text) = rest.split_once(']')?;
+            let (name, args) = match code.split_once(' ') {
+                Some((name, args)) => (name, Some(args)),
+                None => (code, None),
+            };
+            Some((Self::from_atom(name, args), text.trim_start()))
+        });
+        match parsed {
+            Some((code, text)) => (Some(code), text.to_string()),
+            None => (None, information.to_string()),
+        }
+    }
+
+    fn from_atom(name: &str, args: Option<&str>) -> Self {
+        match (name.to_ascii_uppercase().as_str(), args) {
+            ("UNAVAILABLE", None) => Self::Unavailable,
+            ("AUTHENTICATIONFAILED", None) => Self::AuthenticationFailed,
+            ("AUTHORIZATIONFAILED", None) => Self::AuthorizationFailed,
+            ("EXPIRED", None) => Self::Expired,
+            ("PRIVACYREQUIRED", None) => Self::PrivacyRequired,
+            ("CONTACTADMIN", None) => Self::ContactAdmin,
+            ("NOPERM", None) => Self::NoPerm,
+            ("INUSE", None) => Self::InUse,
+            ("EXPUNGEISSUED", None) => Self::ExpungeIssued,
+            ("CORRUPTION", None) => Self::Corruption,
+            ("SERVERBUG", None) => Self::ServerBug,
+            ("CLIENTBUG", None) => Self::ClientBug,
+            ("CANNOT", None) => Self::CanNot,
+            ("LIMIT", None) => Self::Limit,
+            ("OVERQUOTA", None) => Self::OverQuota,
+            ("ALREADYEXISTS", None) => Self::AlreadyExists,
+            ("NONEXISTENT", None) => Self::NonExistent,
+            ("NOMODSEQ", None) => Self::NoModSeq,
+            ("CLOSED", None) => Self::Closed,
+            ("MAILBOXID", Some(id)) => {
+                match id.strip_prefix('(').and_then(|id| id.strip_suffix(')')) {
+                    Some(id) => Self::MailboxId(id.to_string()),
+                    None => Self::Other(name.to_string(), args.map(str::to_string)),
+                }
+            }
+            ("MODIFIED", Some(set)) => match parse_sequence_set(set) {
+                Some(set) => Self::Modified(set),
+                None => Self::Other(name.to_string(), args.map(str::to_string)),
+            },
+            _ => Self::Other(name.to_string(), args.map(str::to_string)),
+        }
+    }
+
+    /// The name of the response code, e.g. `TRYCREATE`.
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Alert => "ALERT",
+            Self::BadCharset(_) => "BADCHARSET",
+            Self::Capabilities(_) => "CAPABILITY",
+            Self::Parse => "PARSE",
+            Self::PermanentFlags(_) => "PERMANENTFLAGS",
+            Self::ReadOnly => "READ-ONLY",
+            Self::ReadWrite => "READ-WRITE",
+            Self::TryCreate => "TRYCREATE",
+            Self::UidNext(_) => "UIDNEXT",
+            Self::UidValidity(_) => "UIDVALIDITY",
+            Self::Unseen(_) => "UNSEEN",
+            Self::Unavailable => "UNAVAILABLE",
+            Self::AuthenticationFailed => "AUTHENTICATIONFAILED",
+            Self::AuthorizationFailed => "AUTHORIZATIONFAILED",
+            Self::Expired => "EXPIRED",
+            Self::PrivacyRequired => "PRIVACYREQUIRED",
+            Self::ContactAdmin => "CONTACTADMIN",
+            Self::NoPerm => "NOPERM",
+            Self::InUse => "INUSE",
+            Self::ExpungeIssued => "EXPUNGEISSUED",
+            Self::Corruption => "CORRUPTION",
+            Self::ServerBug => "SERVERBUG",
+            Self::ClientBug => "CLIENTBUG",
+            Self::CanNot => "CANNOT",
+            Self::Limit => "LIMIT",
+            Self::OverQuota => "OVERQUOTA",
+            Self::AlreadyExists => "ALREADYEXISTS",
+            Self::NonExistent => "NONEXISTENT",
+            Self::AppendUid(..) => "APPENDUID",
+            Self::CopyUid(..) => "COPYUID",
+            Self::UidNotSticky => "UIDNOTSTICKY",
+            Self::HighestModSeq(_) => "HIGHESTMODSEQ",
+            Self::NoModSeq => "NOMODSEQ",
+            Self::Closed => "CLOSED",
+            Self::Modified(_) => "MODIFIED",
+            Self::MailboxId(_) => "MAILBOXID",
+            Self::MetadataLongEntries(_)
+            | Self::MetadataMaxSize(_)
+            | Self::MetadataTooMany
+            | Self::MetadataNoPrivate => "METADATA",
+            Self::Other(name, _) => name,
+        }
+    }
+}
+
+impl From<&ResponseCodeRef<'_>> for ResponseCode {
+    fn from(code: &ResponseCodeRef<'_>) -> Self {
+        let strings =
+            |v: &Vec<std::borrow::Cow<'_, str>>| v.iter().map(|s| s.to_string()).collect();
+        match code {
+            ResponseCodeRef::Alert => Self::Alert,
+            ResponseCodeRef::BadCharset(charsets) => {
+                Self::BadCharset(charsets.as_ref().map(strings))
+            }
+            ResponseCodeRef::Capabilities(caps) => {
+                Self::Capabilities(Capabilities::from_parsed(caps))
+            }
+            ResponseCodeRef::HighestModSeq(modseq) => Self::HighestModSeq(*modseq),
+            ResponseCodeRef::Parse => Self::Parse,
+            ResponseCodeRef::PermanentFlags(flags) => Self::PermanentFlags(strings(flags)),
+            ResponseCodeRef::ReadOnly => Self::ReadOnly,
+            ResponseCodeRef::ReadWrite => Self::ReadWrite,
+            ResponseCodeRef::TryCreate => Self::TryCreate,
+            ResponseCodeRef::UidNext(uid) => Self::UidNext(*uid),
+            ResponseCodeRef::UidValidity(validity) => Self::UidValidity(*validity),
+            ResponseCodeRef::Unseen(seq) => Self::Unseen(*seq),
+            ResponseCodeRef::AppendUid(validity, uids) => Self::AppendUid(*validity, uids.clone()),
+            ResponseCodeRef::CopyUid(validity, from, to) => {
+                Self::CopyUid(*validity, from.clone(), to.clone())
+            }
+            ResponseCodeRef::UidNotSticky => Self::UidNotSticky,
+            ResponseCodeRef::MetadataLongEntries(size) => Self::MetadataLongEntries(*size),
+            ResponseCodeRef::MetadataMaxSize(size) => Self::MetadataMaxSize(*size),
+            ResponseCodeRef::MetadataTooMany => Self::MetadataTooMany,
+            ResponseCodeRef::MetadataNoPrivate => Self::MetadataNoPrivate,
+            // Codes added to `imap-proto` later on, whose name is not known here.
+            _ => Self::Other("UNKNOWN".to_string(), None),
+        }
+    }
+}
+
+/// Parses a sequence set without `*`, such as `1:3,7`.
+fn parse_sequence_set(set: &str) -> Option<Vec<UidSetMember>> {
+    set.split(',')
+        .map(|member| match member.split_once(':') {
+            Some((a, b)) => {
+                let (a, b): (u32, u32) = (a.parse().ok()?, b.parse().ok()?);
+                Some(UidSetMember::UidRange(a.min(b)..=a.max(b)))
+            }
+            None => Some(UidSetMember::Uid(member.parse().ok()?)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_code() {
+        let (code, text) =
+            ResponseCode::from_status(Some(&ResponseCodeRef::TryCreate), Some("No such mailbox"));
+        assert_eq!(code, Some(ResponseCode::TryCreate));
+        assert_eq!(text, "No such mailbox");
+    }
+
+    #[test]
+    fn code_left_in_text() {
+        let (code, text) =
+            ResponseCode::from_status(None, Some("[AUTHENTICATIONFAILED] Invalid credentials"));
+        assert_eq!(code, Some(ResponseCode::AuthenticationFailed));
+        assert_eq!(text, "Invalid credentials");
+
+        let (code, text) =
+            ResponseCode::from_status(None, Some("[MODIFIED 7,9:11] Conditional STORE failed"));
+        assert_eq!(
+            code,
+            Some(ResponseCode::Modified(vec![
+                UidSetMember::Uid(7),
+                UidSetMember::UidRange(9..=11)
+            ]))
+        );
+        assert_eq!(text, "Conditional STORE failed");
+
+        let (code, _) = ResponseCode::from_status(None, Some("[X-UNKNOWN foo bar] text"));
+        assert_eq!(
+            code,
+            Some(ResponseCode::Other(
+                "X-UNKNOWN".to_string(),
+                Some("foo bar".to_string())
+            ))
+        );
+    }
+
+    #[test]
+    fn no_code() {
+        let (code, text) = ResponseCode::from_status(None, Some("Mailbox doesn't exist"));
+        assert_eq!(code, None);
+        assert_eq!(text, "Mailbox doesn't exist");
+
+        let (code, text) = ResponseCode::from_status(None, None);
+        assert_eq!(code, None);
+        assert_eq!(text, "");
+    }
+}
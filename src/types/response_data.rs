@@ -4,15 +4,34 @@ use bytes::BytesMut;
 use imap_proto::{RequestId, Response};
 use self_cell::self_cell;
 
+use crate::extensions::binary::BinaryAttribute;
+
 self_cell!(
     pub struct ResponseData {
-        owner: BytesMut,
+        owner: RawResponse,
 
         #[covariant]
         dependent: Response,
     }
 );
 
+/// The bytes a [`ResponseData`] is parsed from.
+pub struct RawResponse {
+    pub(crate) bytes: BytesMut,
+    /// The `BINARY` items of a `FETCH` response, which were taken out of `bytes` because the
+    /// parser does not know them.
+    pub(crate) binary: Vec<BinaryAttribute>,
+}
+
+impl From<BytesMut> for RawResponse {
+    fn from(bytes: BytesMut) -> Self {
+        RawResponse {
+            bytes,
+            binary: Vec::new(),
+        }
+    }
+}
+
 impl std::cmp::PartialEq for ResponseData {
     fn eq(&self, other: &Self) -> bool {
         self.parsed() == other.parsed()
@@ -24,7 +43,7 @@ impl std::cmp::Eq for ResponseData {}
 impl fmt::Debug for ResponseData {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("ResponseData")
-            .field("raw", &self.borrow_owner().len())
+            .field("raw", &self.borrow_owner().bytes.len())
             .field("response", self.borrow_dependent())
             .finish()
     }
@@ -41,4 +60,20 @@ impl ResponseData {
     pub fn parsed(&self) -> &Response<'_> {
         self.borrow_dependent()
     }
+
+    /// Whether this is an `ENABLED` response, which the parser does not tell apart from a
+    /// `CAPABILITY` response.
+    pub(crate) fn is_enabled(&self) -> bool {
+        const ENABLED: &[u8] = b"* ENABLED";
+        let bytes = &self.borrow_owner().bytes[..];
+        bytes
+            .get(..ENABLED.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(ENABLED))
+            && matches!(bytes.get(ENABLED.len()), Some(b' ' | b'\r' | b'\n'))
+    }
+
+    /// The `BINARY` items of a `FETCH` response, see [`RawResponse::binary`].
+    pub(crate) fn binary(&self) -> &[BinaryAttribute] {
+        &self.borrow_owner().binary
+    }
 }
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use imap_proto::{AttributeValue, MailboxDatum, Response};
+
+use super::{Flag, ResponseCode, Seq, Uid};
+
+/// The state of the currently selected mailbox, kept up to date by the [`Session`] from the
+/// responses of the server as they arrive.
+///
+/// Tracking is enabled with [`Session::set_track_selected_mailbox`] and starts with the next
+/// [`Session::select`] or [`Session::examine`]. The state is then available through
+/// [`Session::selected_mailbox`]. It is updated from both solicited and unsolicited `EXISTS`,
+/// `RECENT`, `EXPUNGE`, `VANISHED`, `FLAGS` and `FETCH` responses, and from the `UIDVALIDITY`,
+/// `UIDNEXT` and `HIGHESTMODSEQ` response codes. UIDs, flags and mod-sequences of messages are
+/// only known once they have been fetched.
+///
+/// Clone the state to keep a snapshot, and use [`SelectedMailbox::diff`] to find out what changed
+/// since then.
+///
+/// [`Session`]: crate::Session
+/// [`Session::set_track_selected_mailbox`]: crate::Session::set_track_selected_mailbox
+/// [`Session::select`]: crate::Session::select
+/// [`Session::examine`]: crate::Session::examine
+/// [`Session::selected_mailbox`]: crate::Session::selected_mailbox
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SelectedMailbox {
+    name: String,
+    uid_validity: Option<u32>,
+    uid_next: Option<Uid>,
+    highest_modseq: Option<u64>,
+    recent: u32,
+    flags: Vec<Flag<'static>>,
+    messages: Vec<MessageState>,
+}
+
+/// What is known about a message in a [`SelectedMailbox`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MessageState {
+    /// The UID of the message, if it has been fetched.
+    pub uid: Option<Uid>,
+    /// The flags of the message, if they have been fetched.
+    pub flags: Option<Vec<Flag<'static>>>,
+    /// The mod-sequence of the message, if it has been fetched.
+    pub modseq: Option<u64>,
+}
+
+/// Changes between two states of a [`SelectedMailbox`], see [`SelectedMailbox::diff`].
+///
+/// Messages are identified by their UID, so messages whose UID is not known in both states can
+/// not be compared.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MailboxDiff {
+    /// UIDs of messages that were added, in ascending order.
+    pub added: Vec<Uid>,
+    /// UIDs of messages that were removed, in ascending order.
+    pub removed: Vec<Uid>,
+    /// UIDs of messages whose flags changed, in ascending order.
+    pub flags_changed: Vec<Uid>,
+}
+
+impl SelectedMailbox {
+    pub(crate) fn new(name: &str) -> Self {
+        SelectedMailbox {
+            name: name.to_string(),
+            uid_validity: None,
+            uid_next: None,
+            highest_modseq: None,
+            recent: 0,
+            flags: Vec::new(),
+            messages: Vec::new(),
+        }
+    }
+
+    /// The name of the mailbox, as passed to `SELECT` or `EXAMINE`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The number of messages in the mailbox.
+    pub fn exists(&self) -> u32 {
+        self.messages.len() as u32
+    }
+
+    /// The number of messages with the `\Recent` flag set.
+    pub fn recent(&self) -> u32 {
+        self.recent
+    }
+
+    /// The unique identifier validity value, if the server sent one.
+    pub fn uid_validity(&self) -> Option<u32> {
+        self.uid_validity
+    }
+
+    /// The next unique identifier value, if known.
+    pub fn uid_next(&self) -> Option<Uid> {
+        self.uid_next
+    }
+
+    /// The highest mod-sequence of the mailbox, if the server sent one.
+    pub fn highest_modseq(&self) -> Option<u64> {
+        self.highest_modseq
+    }
+
+    /// The flags defined in the mailbox, from the last `FLAGS` response.
+    pub fn flags(&self) -> &[Flag<'static>] {
+        &self.flags
+    }
+
+    /// The messages of the mailbox, where the message with sequence number `n` is at index
+    /// `n - 1`.
+    pub fn messages(&self) -> &[MessageState] {
+        &self.messages
+    }
+
+    /// The message with the given sequence number.
+    pub fn message(&self, seq: Seq) -> Option<&MessageState> {
+        self.messages.get((seq as usize).checked_sub(1)?)
+    }
+
+    /// The UID of the message with the given sequence number, if known.
+    pub fn uid(&self, seq: Seq) -> Option<Uid> {
+        self.message(seq)?.uid
+    }
+
+    /// The sequence number of the message with the given UID, if known.
+    pub fn seq(&self, uid: Uid) -> Option<Seq> {
+        let index = self.messages.iter().position(|m| m.uid == Some(uid))?;
+        Some(index as Seq + 1)
+    }
+
+    /// Returns the changes from `earlier` to this state.
+    ///
+    /// If the `UIDVALIDITY` changed in between, all messages of `earlier` are reported as removed.
+    pub fn diff(&self, earlier: &SelectedMailbox) -> MailboxDiff {
+        let now = self.by_uid();
+        let validity_changed = earlier.uid_validity != self.uid_validity;
+        let before = if validity_changed {
+            HashMap::new()
+        } else {
+            earlier.by_uid()
+        };
+
+        let mut diff = MailboxDiff::default();
+        for (uid, message) in &now {
+            match before.get(uid) {
+                None => diff.added.push(*uid),
+                Some(old) if message.flags.is_some() && message.flags != old.flags => {
+                    diff.flags_changed.push(*uid)
+                }
+                Some(_) => {}
+            }
+        }
+        diff.removed = earlier
+            .messages
+            .iter()
+            .filter_map(|m| m.uid)
+            .filter(|uid| validity_changed || !now.contains_key(uid))
+            .collect();
+
+        diff.added.sort_unstable();
+        diff.removed.sort_unstable();
+        diff.flags_changed.sort_unstable();
+        diff
+    }
+
+    fn by_uid(&self) -> HashMap<Uid, &MessageState> {
+        self.messages
+            .iter()
+            .filter_map(|m| Some((m.uid?, m)))
+            .collect()
+    }
+
+    /// Applies a response received while the mailbox is selected.
+    pub(crate) fn apply(&mut self, response: &Response<'_>) {
+        match response {
+            Response::MailboxData(MailboxDatum::Exists(n)) => {
+                self.messages.resize(*n as usize, MessageState::default());
+            }
+            Response::MailboxData(MailboxDatum::Recent(n)) => self.recent = *n,
+            Response::MailboxData(MailboxDatum::Flags(flags)) => {
+                self.flags = flags.iter().map(|f| Flag::from(f.to_string())).collect();
+            }
+            Response::Expunge(seq) if (1..=self.messages.len()).contains(&(*seq as usize)) => {
+                self.messages.remove(*seq as usize - 1);
+            }
+            // Messages whose UID is not known can not be removed here.
+            Response::Vanished { uids, .. } => self.messages.retain(|m| {
+                !m.uid
+                    .is_some_and(|uid| uids.iter().any(|range| range.contains(&uid)))
+            }),
+            Response::Fetch(seq, attributes) => {
+                let Some(index) = (*seq as usize).checked_sub(1) else {
+                    return;
+                };
+                if index >= self.messages.len() {
+                    self.messages.resize(index + 1, MessageState::default());
+                }
+                let message = &mut self.messages[index];
+                for attribute in attributes {
+                    match attribute {
+                        AttributeValue::Uid(uid) => {
+                            message.uid = Some(*uid);
+                            if self.uid_next.is_some_and(|next| *uid >= next) {
+                                self.uid_next = Some(uid + 1);
+                            }
+                        }
+                        AttributeValue::Flags(flags) => {
+                            message.flags =
+                                Some(flags.iter().map(|f| Flag::from(f.to_string())).collect());
+                        }
+                        AttributeValue::ModSeq(modseq) => message.modseq = Some(*modseq),
+                        _ => {}
+                    }
+                }
+            }
+            Response::Data {
+                status: imap_proto::Status::Ok,
+                code,
+                information,
+            }
+            | Response::Done {
+                status: imap_proto::Status::Ok,
+                code,
+                information,
+                ..
+            } => match ResponseCode::from_status(code.as_ref(), information.as_deref()).0 {
+                Some(ResponseCode::UidValidity(uid_validity)) => {
+                    self.uid_validity = Some(uid_validity)
+                }
+                Some(ResponseCode::UidNext(uid_next)) => self.uid_next = Some(uid_next),
+                Some(ResponseCode::HighestModSeq(modseq)) => self.highest_modseq = Some(modseq),
+                // Responses before `CLOSED` belong to the previously selected mailbox.
+                Some(ResponseCode::Closed) => *self = SelectedMailbox::new(&self.name),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
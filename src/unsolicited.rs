@@ -0,0 +1,323 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_channel::{self as channel, TrySendError};
+
+use crate::error::{Error, Result};
+use crate::types::UnsolicitedResponse;
+
+/// What to do with an unsolicited response when the
+/// [`Session::unsolicited_responses`](crate::Session::unsolicited_responses) channel is full,
+/// see [`Session::configure_unsolicited_responses`](crate::Session::configure_unsolicited_responses).
+///
+/// Every response that is dropped is counted in
+/// [`Session::unsolicited_overflow_count`](crate::Session::unsolicited_overflow_count).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum OverflowPolicy {
+    /// Wait until there is room in the channel. The running command makes no progress until the
+    /// channel is read, so it has to be consumed concurrently to the session.
+    Block,
+    /// Drop the oldest queued response to make room for the new one.
+    DropOldest,
+    /// Drop the new response.
+    #[default]
+    DropNewest,
+    /// Drop queued `EXISTS` responses that are superseded by a later one to make room, as only
+    /// the last one is relevant. If that does not free any room, the new response is dropped.
+    CoalesceExists,
+    /// Drop the new response and fail the running command with
+    /// [`Error::UnsolicitedOverflow`]. The remaining responses to that command are read by the next
+    /// command.
+    Error,
+}
+
+/// Receives unsolicited responses as they are read, instead of the
+/// [`Session::unsolicited_responses`](crate::Session::unsolicited_responses) channel, see
+/// [`Session::set_unsolicited_handler`](crate::Session::set_unsolicited_handler).
+///
+/// The handler is called synchronously while the response to a command is read, so it must not
+/// block. It is implemented for closures taking an [`UnsolicitedResponse`].
+///
+/// ```ignore
+/// let mut exists = 0;
+/// session.set_unsolicited_handler(move |response| {
+///     if let UnsolicitedResponse::Exists(n) = response {
+///         exists = n;
+///     }
+/// });
+/// ```
+pub trait UnsolicitedHandler: Send {
+    /// Handles a response that is not related to the current command.
+    fn handle(&mut self, response: UnsolicitedResponse);
+}
+
+impl<F: FnMut(UnsolicitedResponse) + Send> UnsolicitedHandler for F {
+    fn handle(&mut self, response: UnsolicitedResponse) {
+        self(response)
+    }
+}
+
+type SharedHandler = Arc<Mutex<Box<dyn UnsolicitedHandler>>>;
+
+/// The sending side of the unsolicited responses channel, applying the [`OverflowPolicy`].
+///
+/// Closing the channel, e.g. with [`async_channel::Receiver::close`], is not an error; responses
+/// are then discarded.
+#[derive(Clone)]
+pub(crate) struct UnsolicitedSender {
+    tx: channel::Sender<UnsolicitedResponse>,
+    /// Used to drain the channel when coalescing, without keeping it open.
+    rx: Option<channel::WeakReceiver<UnsolicitedResponse>>,
+    policy: OverflowPolicy,
+    overflow_count: Arc<AtomicU64>,
+    /// Receives the responses instead of the channel, if set.
+    handler: Option<SharedHandler>,
+}
+
+impl fmt::Debug for UnsolicitedSender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UnsolicitedSender")
+            .field("tx", &self.tx)
+            .field("policy", &self.policy)
+            .field("overflow_count", &self.overflow_count)
+            .field("handler", &self.handler.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl UnsolicitedSender {
+    pub(crate) fn new(
+        capacity: usize,
+        policy: OverflowPolicy,
+    ) -> (Self, channel::Receiver<UnsolicitedResponse>) {
+        // A channel without capacity can not hold responses, and `bounded` panics on it.
+        let (tx, rx) = channel::bounded(capacity.max(1));
+        let sender = UnsolicitedSender {
+            tx,
+            rx: Some(rx.downgrade()),
+            policy,
+            overflow_count: Default::default(),
+            handler: None,
+        };
+        (sender, rx)
+    }
+
+    /// Creates a new channel that shares the overflow count and the handler with this one.
+    pub(crate) fn reconfigure(
+        &self,
+        capacity: usize,
+        policy: OverflowPolicy,
+    ) -> (Self, channel::Receiver<UnsolicitedResponse>) {
+        let (mut sender, rx) = Self::new(capacity, policy);
+        sender.overflow_count = self.overflow_count.clone();
+        sender.handler = self.handler.clone();
+        (sender, rx)
+    }
+
+    pub(crate) fn set_handler(&mut self, handler: Option<Box<dyn UnsolicitedHandler>>) {
+        self.handler = handler.map(|handler| Arc::new(Mutex::new(handler)));
+    }
+
+    pub(crate) fn overflow_count(&self) -> u64 {
+        self.overflow_count.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn overflowed(&self) {
+        self.overflow_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Queues a response if there is room, regardless of the policy.
+    pub(crate) fn try_send(
+        &self,
+        response: UnsolicitedResponse,
+    ) -> std::result::Result<(), TrySendError<UnsolicitedResponse>> {
+        self.tx.try_send(response)
+    }
+
+    pub(crate) async fn send(&self, response: UnsolicitedResponse) -> Result<()> {
+        if let Some(handler) = &self.handler {
+            // A handler that panicked before does not leave anything inconsistent here.
+            let mut handler = handler.lock().unwrap_or_else(|err| err.into_inner());
+            handler.handle(response);
+            return Ok(());
+        }
+
+        match self.policy {
+            OverflowPolicy::Block => {
+                // Fails only if the channel is closed.
+                let _ = self.tx.send(response).await;
+            }
+            OverflowPolicy::DropOldest => {
+                if let Ok(Some(_)) = self.tx.force_send(response) {
+                    self.overflowed();
+                }
+            }
+            OverflowPolicy::DropNewest => {
+                if let Err(TrySendError::Full(_)) = self.tx.try_send(response) {
+                    self.overflowed();
+                }
+            }
+            OverflowPolicy::CoalesceExists => {
+                if let Err(TrySendError::Full(response)) = self.tx.try_send(response) {
+                    self.coalesce_exists(&response);
+                    if let Err(TrySendError::Full(_)) = self.tx.try_send(response) {
+                        self.overflowed();
+                    }
+                }
+            }
+            OverflowPolicy::Error => {
+                if let Err(TrySendError::Full(_)) = self.tx.try_send(response) {
+                    self.overflowed();
+                    return Err(Error::UnsolicitedOverflow);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the queued `EXISTS` responses that are superseded by a later one, or by `next`.
+    fn coalesce_exists(&self, next: &UnsolicitedResponse) {
+        let Some(rx) = self.rx.as_ref().and_then(|rx| rx.upgrade()) else {
+            return;
+        };
+        let is_exists = |r: &UnsolicitedResponse| matches!(r, UnsolicitedResponse::Exists(_));
+
+        let mut queued: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        let last = if is_exists(next) {
+            None
+        } else {
+            queued.iter().rposition(is_exists)
+        };
+        let mut index = 0;
+        queued.retain(|r| {
+            let keep = !is_exists(r) || Some(index) == last;
+            index += 1;
+            keep
+        });
+        for response in queued {
+            if self.tx.try_send(response).is_err() {
+                self.overflowed();
+            }
+        }
+    }
+}
+
+impl From<channel::Sender<UnsolicitedResponse>> for UnsolicitedSender {
+    fn from(tx: channel::Sender<UnsolicitedResponse>) -> Self {
+        UnsolicitedSender {
+            tx,
+            rx: None,
+            policy: OverflowPolicy::default(),
+            overflow_count: Default::default(),
+            handler: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(rx: &channel::Receiver<UnsolicitedResponse>) -> Vec<UnsolicitedResponse> {
+        std::iter::from_fn(|| rx.try_recv().ok()).collect()
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn drop_newest() {
+        let (tx, rx) = UnsolicitedSender::new(2, OverflowPolicy::DropNewest);
+        for n in 1..=3 {
+            tx.send(UnsolicitedResponse::Exists(n)).await.unwrap();
+        }
+        assert_eq!(tx.overflow_count(), 1);
+        assert_eq!(
+            drain(&rx),
+            [
+                UnsolicitedResponse::Exists(1),
+                UnsolicitedResponse::Exists(2)
+            ]
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn drop_oldest() {
+        let (tx, rx) = UnsolicitedSender::new(2, OverflowPolicy::DropOldest);
+        for n in 1..=3 {
+            tx.send(UnsolicitedResponse::Exists(n)).await.unwrap();
+        }
+        assert_eq!(tx.overflow_count(), 1);
+        assert_eq!(
+            drain(&rx),
+            [
+                UnsolicitedResponse::Exists(2),
+                UnsolicitedResponse::Exists(3)
+            ]
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn coalesce_exists() {
+        let (tx, rx) = UnsolicitedSender::new(3, OverflowPolicy::CoalesceExists);
+        tx.send(UnsolicitedResponse::Exists(1)).await.unwrap();
+        tx.send(UnsolicitedResponse::Expunge(1)).await.unwrap();
+        tx.send(UnsolicitedResponse::Exists(2)).await.unwrap();
+        tx.send(UnsolicitedResponse::Exists(3)).await.unwrap();
+        tx.send(UnsolicitedResponse::Recent(1)).await.unwrap();
+        assert_eq!(tx.overflow_count(), 0);
+        assert_eq!(
+            drain(&rx),
+            [
+                UnsolicitedResponse::Expunge(1),
+                UnsolicitedResponse::Exists(3),
+                UnsolicitedResponse::Recent(1)
+            ]
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn handler() {
+        let (mut tx, rx) = UnsolicitedSender::new(1, OverflowPolicy::Error);
+        let (handled_tx, handled_rx) = std::sync::mpsc::channel();
+        tx.set_handler(Some(Box::new(move |response| {
+            handled_tx.send(response).unwrap()
+        })));
+        for n in 1..=3 {
+            tx.send(UnsolicitedResponse::Exists(n)).await.unwrap();
+        }
+        assert!(rx.is_empty());
+        assert_eq!(tx.overflow_count(), 0);
+        assert_eq!(
+            handled_rx.try_iter().collect::<Vec<_>>(),
+            [
+                UnsolicitedResponse::Exists(1),
+                UnsolicitedResponse::Exists(2),
+                UnsolicitedResponse::Exists(3)
+            ]
+        );
+
+        tx.set_handler(None);
+        tx.send(UnsolicitedResponse::Exists(4)).await.unwrap();
+        assert_eq!(drain(&rx), [UnsolicitedResponse::Exists(4)]);
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn error_and_closed() {
+        let (tx, rx) = UnsolicitedSender::new(1, OverflowPolicy::Error);
+        tx.send(UnsolicitedResponse::Exists(1)).await.unwrap();
+        assert!(matches!(
+            tx.send(UnsolicitedResponse::Exists(2)).await,
+            Err(Error::UnsolicitedOverflow)
+        ));
+        assert_eq!(tx.overflow_count(), 1);
+
+        rx.close();
+        tx.send(UnsolicitedResponse::Exists(3)).await.unwrap();
+        assert_eq!(tx.overflow_count(), 1);
+    }
+}
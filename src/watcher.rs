@@ -0,0 +1,182 @@
+use std::fmt;
+use std::time::Duration;
+
+#[cfg(feature = "runtime-async-std")]
+use async_std::io::{Read, Write};
+use futures::stream::Stream;
+#[cfg(feature = "runtime-tokio")]
+use tokio::io::{AsyncRead as Read, AsyncWrite as Write};
+
+use crate::client::Session;
+use crate::error::Result;
+use crate::types::{coalesce, Mailbox, MailboxEvent};
+
+/// Returns the stream of [`Session::watch_mailbox`].
+pub(crate) fn status_stream<'a, T: Read + Write + Unpin + fmt::Debug + Send>(
+    session: &'a mut Session<T>,
+    mailbox_name: &str,
+    interval: Duration,
+) -> impl Stream<Item = Result<MailboxEvent>> + Unpin + Send + 'a {
+    let state = Watcher {
+        session,
+        mailbox_name: mailbox_name.to_string(),
+        interval,
+        data_items: None,
+        snapshot: None,
+        events: Vec::new(),
+    };
+    Box::pin(futures::stream::unfold(Some(state), |state| async move {
+        let mut state = state?;
+        match state.next_event().await {
+            Ok(event) => Some((Ok(event), Some(state))),
+            // The connection is unusable after an error, so the stream ends.
+            Err(err) => Some((Err(err), None)),
+        }
+    }))
+}
+
+/// The state of [`Session::watch_mailbox`].
+struct Watcher<'a, T: Read + Write + Unpin + fmt::Debug + Send> {
+    session: &'a mut Session<T>,
+    mailbox_name: String,
+    interval: Duration,
+    /// The `STATUS` data items to request, once the capabilities have been checked.
+    data_items: Option<&'static str>,
+    /// The result of the last `STATUS` command.
+    snapshot: Option<Mailbox>,
+    /// Events found but not returned yet.
+    events: Vec<MailboxEvent>,
+}
+
+impl<T: Read + Write + Unpin + fmt::Debug + Send> Watcher<'_, T> {
+    async fn next_event(&mut self) -> Result<MailboxEvent> {
+        loop {
+            if !self.events.is_empty() {
+                return Ok(self.events.remove(0));
+            }
+
+            let data_items = match self.data_items {
+                Some(data_items) => data_items,
+                None => {
+                    let capabilities = self.session.capabilities().await?;
+                    let data_items =
+                        if capabilities.has_str("CONDSTORE") || capabilities.has_str("QRESYNC") {
+                            "(MESSAGES UIDNEXT UIDVALIDITY HIGHESTMODSEQ)"
+                        } else {
+                            "(MESSAGES UIDNEXT UIDVALIDITY)"
+                        };
+                    self.data_items = Some(data_items);
+                    data_items
+                }
+            };
+            // The first snapshot is taken right away, as the baseline.
+            if self.snapshot.is_some() {
+                crate::runtime::sleep(self.interval).await;
+            }
+            let mailbox = self.session.status(&self.mailbox_name, data_items).await?;
+            if let Some(previous) = self.snapshot.replace(mailbox) {
+                let current = self.snapshot.as_ref().expect("just replaced");
+                for event in changes(&previous, current) {
+                    coalesce(&mut self.events, event);
+                }
+            }
+        }
+    }
+}
+
+/// The events that explain the difference between two snapshots of the same mailbox.
+fn changes(previous: &Mailbox, current: &Mailbox) -> Vec<MailboxEvent> {
+    if previous.uid_validity != current.uid_validity {
+        // The mailbox was recreated, nothing else can be compared.
+        return current
+            .uid_validity
+            .map(MailboxEvent::UidValidityChanged)
+            .into_iter()
+            .collect();
+    }
+
+    let mut events = Vec::new();
+    // Messages may have arrived and others been removed in between, leaving the count as it was.
+    if previous.exists != current.exists || previous.uid_next != current.uid_next {
+        events.push(MailboxEvent::NewMessages(current.exists));
+    }
+    if let Some(highest_modseq) = current.highest_modseq {
+        if previous.highest_modseq != Some(highest_modseq) {
+            events.push(MailboxEvent::HighestModSeq(highest_modseq));
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock_session;
+    use crate::mock_stream::MockStream;
+    use futures::StreamExt;
+
+    fn snapshot(exists: u32, uid_next: u32, uid_validity: u32, modseq: u64) -> Mailbox {
+        Mailbox {
+            exists,
+            uid_next: Some(uid_next),
+            uid_validity: Some(uid_validity),
+            highest_modseq: Some(modseq),
+            ..Mailbox::default()
+        }
+    }
+
+    #[test]
+    fn snapshot_changes() {
+        let base = snapshot(3, 10, 1, 100);
+        assert_eq!(changes(&base, &base), vec![]);
+        assert_eq!(
+            changes(&base, &snapshot(4, 11, 1, 101)),
+            vec![
+                MailboxEvent::NewMessages(4),
+                MailboxEvent::HighestModSeq(101)
+            ]
+        );
+        // One message arrived, another one was removed.
+        assert_eq!(
+            changes(&base, &snapshot(3, 11, 1, 100)),
+            vec![MailboxEvent::NewMessages(3)]
+        );
+        assert_eq!(
+            changes(&base, &snapshot(5, 20, 2, 300)),
+            vec![MailboxEvent::UidValidityChanged(2)]
+        );
+    }
+
+    #[cfg_attr(feature = "runtime-tokio", tokio::test)]
+    #[cfg_attr(feature = "runtime-async-std", async_std::test)]
+    async fn watch_mailbox() {
+        let response = b"* CAPABILITY IMAP4rev1 CONDSTORE\r\n\
+            A0001 OK Capability completed\r\n\
+            * STATUS Archive (MESSAGES 3 UIDNEXT 10 UIDVALIDITY 1 HIGHESTMODSEQ 100)\r\n\
+            A0002 OK Status completed\r\n\
+            * STATUS Archive (MESSAGES 3 UIDNEXT 10 UIDVALIDITY 1 HIGHESTMODSEQ 100)\r\n\
+            A0003 OK Status completed\r\n\
+            * STATUS Archive (MESSAGES 4 UIDNEXT 11 UIDVALIDITY 1 HIGHESTMODSEQ 102)\r\n\
+            A0004 OK Status completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let mut events = session.watch_mailbox("Archive", Duration::from_millis(1));
+        assert_eq!(
+            events.next().await.unwrap().unwrap(),
+            MailboxEvent::NewMessages(4)
+        );
+        assert_eq!(
+            events.next().await.unwrap().unwrap(),
+            MailboxEvent::HighestModSeq(102)
+        );
+        drop(events);
+        assert_eq!(
+            session.stream.inner.written_buf,
+            b"A0001 CAPABILITY\r\n\
+            A0002 STATUS \"Archive\" (MESSAGES UIDNEXT UIDVALIDITY HIGHESTMODSEQ)\r\n\
+            A0003 STATUS \"Archive\" (MESSAGES UIDNEXT UIDVALIDITY HIGHESTMODSEQ)\r\n\
+            A0004 STATUS \"Archive\" (MESSAGES UIDNEXT UIDVALIDITY HIGHESTMODSEQ)\r\n"
+                .to_vec()
+        );
+    }
+}